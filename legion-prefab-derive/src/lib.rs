@@ -0,0 +1,231 @@
+//! `#[derive(PrefabComponent)]` registers a component type with legion-prefab, replacing a separate
+//! `legion_prefab::register_component_type!` call that is easy to forget. The type must still
+//! implement the traits `ComponentRegistration::of` requires:
+//!
+//! ```ignore
+//! #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PrefabComponent)]
+//! #[uuid = "f5780013-bae4-49f0-ac0e-a108ff52fec0"]
+//! #[prefab(display_name = "Follow Target", version = 2)]
+//! struct FollowTarget {
+//!     #[prefab(entity)]
+//!     target: Option<Entity>,
+//!     distance: f32,
+//! }
+//! ```
+//!
+//! Type attributes, inside `#[prefab(...)]`:
+//!  - `editor_only` or `runtime_only`: sets the ComponentUsage of the registration
+//!  - `display_name = "..."`: the name tools show for the component type
+//!  - `version = N`: the version of the component type's data layout
+//!
+//! Fields marked `#[prefab(entity)]` hold references to other entities. Their types must implement
+//! `legion_prefab::MapEntities`, which it is for `Entity`, `Option<T>` and `Vec<T>`. If there are
+//! any, `MapEntities` is implemented for the type so that the references are remapped when it is
+//! cloned.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+#[proc_macro_derive(PrefabComponent, attributes(prefab))]
+pub fn derive_prefab_component(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match prefab_component(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct TypeAttributes {
+    usage: Option<syn::Ident>,
+    display_name: Option<syn::LitStr>,
+    version: Option<syn::LitInt>,
+}
+
+fn prefab_component(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    // Registrations are collected statically, so there must be exactly one concrete type
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "PrefabComponent can't be derived for generic types, register each instantiation \
+             with register_component_type! instead",
+        ));
+    }
+
+    let ty = &input.ident;
+    let attributes = type_attributes(&input.attrs)?;
+    let entity_fields = entity_fields(&input.data)?;
+
+    let constructor = if entity_fields.is_empty() {
+        quote!(of)
+    } else {
+        quote!(of_map_entities)
+    };
+
+    let mut registration = quote! {
+        ::legion_prefab::ComponentRegistration::#constructor::<#ty>()
+    };
+    if let Some(usage) = &attributes.usage {
+        registration = quote!(#registration.with_usage(::legion_prefab::ComponentUsage::#usage));
+    }
+    if let Some(display_name) = &attributes.display_name {
+        registration = quote!(#registration.with_display_name(#display_name));
+    }
+    if let Some(version) = &attributes.version {
+        registration = quote!(#registration.with_version(#version));
+    }
+
+    let map_entities = if entity_fields.is_empty() {
+        quote!()
+    } else {
+        quote! {
+            impl ::legion_prefab::MapEntities for #ty {
+                fn map_entities(
+                    &mut self,
+                    entity_map: &::legion_prefab::EntityMap,
+                ) {
+                    #(::legion_prefab::MapEntities::map_entities(&mut self.#entity_fields, entity_map);)*
+                }
+            }
+        }
+    };
+
+    Ok(quote! {
+        #map_entities
+
+        ::legion_prefab::inventory::submit! {
+            #![crate = legion_prefab]
+            #registration
+        }
+    })
+}
+
+// Reads the #[prefab(...)] attributes on the type
+fn type_attributes(attrs: &[Attribute]) -> syn::Result<TypeAttributes> {
+    let mut attributes = TypeAttributes::default();
+    for meta in prefab_attribute_items(attrs)? {
+        match &meta {
+            Meta::Path(path) if path.is_ident("editor_only") || path.is_ident("runtime_only") => {
+                if attributes.usage.is_some() {
+                    return Err(syn::Error::new(
+                        meta.span(),
+                        "only one of editor_only and runtime_only may be given",
+                    ));
+                }
+
+                let usage = if path.is_ident("editor_only") {
+                    "EditorOnly"
+                } else {
+                    "RuntimeOnly"
+                };
+                attributes.usage = Some(syn::Ident::new(usage, Span::call_site()));
+            }
+            Meta::NameValue(name_value) if name_value.path.is_ident("display_name") => {
+                match &name_value.lit {
+                    Lit::Str(display_name) => attributes.display_name = Some(display_name.clone()),
+                    lit => return Err(syn::Error::new(lit.span(), "expected a string")),
+                }
+            }
+            Meta::NameValue(name_value) if name_value.path.is_ident("version") => {
+                match &name_value.lit {
+                    Lit::Int(version) => attributes.version = Some(version.clone()),
+                    lit => return Err(syn::Error::new(lit.span(), "expected an integer")),
+                }
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "unknown prefab attribute, expected editor_only, runtime_only, \
+                     display_name = \"...\" or version = N",
+                ))
+            }
+        }
+    }
+
+    Ok(attributes)
+}
+
+// Returns the members of the fields marked #[prefab(entity)]
+fn entity_fields(data: &Data) -> syn::Result<Vec<syn::Member>> {
+    let fields = match data {
+        Data::Struct(data) => &data.fields,
+        Data::Enum(data) => {
+            for variant in &data.variants {
+                for field in variant.fields.iter() {
+                    if !prefab_attribute_items(&field.attrs)?.is_empty() {
+                        return Err(syn::Error::new(
+                            field.span(),
+                            "entity fields are only supported on structs, implement MapEntities \
+                             for the enum instead",
+                        ));
+                    }
+                }
+            }
+            return Ok(vec![]);
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new(
+                data.union_token.span(),
+                "PrefabComponent can't be derived for unions",
+            ))
+        }
+    };
+
+    let mut entity_fields = vec![];
+    let fields: Vec<_> = match fields {
+        Fields::Named(fields) => fields.named.iter().collect(),
+        Fields::Unnamed(fields) => fields.unnamed.iter().collect(),
+        Fields::Unit => vec![],
+    };
+    for (index, field) in fields.into_iter().enumerate() {
+        for meta in prefab_attribute_items(&field.attrs)? {
+            match &meta {
+                Meta::Path(path) if path.is_ident("entity") => {
+                    entity_fields.push(match &field.ident {
+                        Some(ident) => syn::Member::Named(ident.clone()),
+                        None => syn::Member::Unnamed(index.into()),
+                    });
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        meta.span(),
+                        "unknown prefab attribute, expected entity",
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(entity_fields)
+}
+
+// Flattens the items of all #[prefab(...)] attributes
+fn prefab_attribute_items(attrs: &[Attribute]) -> syn::Result<Vec<Meta>> {
+    let mut items = vec![];
+    for attr in attrs {
+        if !attr.path.is_ident("prefab") {
+            continue;
+        }
+
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(meta) => items.push(meta),
+                        NestedMeta::Lit(lit) => {
+                            return Err(syn::Error::new(lit.span(), "expected an identifier"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(syn::Error::new(meta.span(), "expected #[prefab(...)]")),
+        }
+    }
+
+    Ok(items)
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::ComponentRegistration;
 use legion::storage::{
     ComponentTypeId, Component, ComponentStorage, Components, EntityLayout, Archetype,
@@ -7,24 +7,230 @@ use legion::storage::{
 use legion::*;
 use std::ops::Range;
 use std::hash::BuildHasher;
-use legion::world::{EntityRewrite, Allocate};
+use legion::world::{EntityRewrite, Allocate, Merger};
 use std::marker::PhantomData;
+use std::any::Any;
 use legion::world::EntityHasher;
+use legion::query::{FilterResult, LayoutFilter};
+use prefab_format::EntityUuid;
+
+/// Implemented by the clone merge impls of this crate, which are only used through these methods
+/// rather than being passed to legion's clone_from. That lets them finish the clone once every
+/// archetype has been merged: entity references inside the cloned components are rewritten to
+/// point at the cloned entities, which can't be done while merging since the destination entities
+/// of the archetypes that haven't been merged yet aren't known.
+pub trait CloneMergeImpl {
+    /// Clones the entities of src in archetypes matching the filter (i.e. `legion::query::any()`
+    /// for all of them) into dst. Returns the mapping from source to destination entities
+    fn clone_world<F: LayoutFilter>(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        filter: &F,
+    ) -> HashMap<Entity, Entity, EntityHasher>;
+
+    /// Clones the given entities from src into dst, batching them where possible. Returns the
+    /// mapping from source to destination entities, which only contains the given entities.
+    /// Entities that don't exist in src are ignored. References to entities that aren't cloned are
+    /// left as they are.
+    fn clone_entities(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        entities: impl IntoIterator<Item = Entity>,
+    ) -> HashMap<Entity, Entity, EntityHasher>;
+}
+
+/// What CopyCloneImpl and SpawnCloneImpl do with components whose type isn't registered (and, for
+/// SpawnCloneImpl, has no mapping)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnregisteredComponentPolicy {
+    /// Panic. This is the default, since leaving out components usually isn't intended
+    Panic,
+    /// Leave the components out. The types are returned by CopyCloneImpl::finish or listed in
+    /// the SpawnCloneReport
+    Skip,
+    /// Leave the components out and make CopyCloneImpl::finish return an error, or the
+    /// SpawnCloneReport not ok. The destination world should be discarded in that case
+    Error,
+}
+
+impl Default for UnregisteredComponentPolicy {
+    fn default() -> Self {
+        UnregisteredComponentPolicy::Panic
+    }
+}
+
+/// Selects the component types that CopyCloneImpl clones. Types that are filtered out are left
+/// out silently and don't need to be registered
+#[derive(Debug, Clone)]
+pub enum ComponentTypeFilter {
+    All,
+    /// Only the listed types are cloned
+    Allow(HashSet<ComponentTypeId>),
+    /// The listed types are not cloned
+    Deny(HashSet<ComponentTypeId>),
+}
+
+impl Default for ComponentTypeFilter {
+    fn default() -> Self {
+        ComponentTypeFilter::All
+    }
+}
+
+impl ComponentTypeFilter {
+    pub fn allows(
+        &self,
+        component_type: ComponentTypeId,
+    ) -> bool {
+        match self {
+            ComponentTypeFilter::All => true,
+            ComponentTypeFilter::Allow(types) => types.contains(&component_type),
+            ComponentTypeFilter::Deny(types) => !types.contains(&component_type),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CopyCloneError {
+    /// Components of these types were left out because they aren't registered
+    UnregisteredComponentTypes(Vec<ComponentTypeId>),
+}
 
 /// A trivial clone merge impl that does nothing but copy data. All component types must be
-/// cloneable and no type transformations are allowed
-#[derive(Copy, Clone)]
+/// cloneable and no type transformations are allowed. Used through CloneMergeImpl
+#[derive(Clone)]
 pub struct CopyCloneImpl<'a, S: BuildHasher> {
     components: &'a HashMap<ComponentTypeId, ComponentRegistration, S>,
+    strip_editor_only: bool,
+    filter: ComponentTypeFilter,
+    unregistered_policy: UnregisteredComponentPolicy,
+
+    // Unregistered types that were left out since the last call to finish()
+    skipped_types: HashSet<ComponentTypeId>,
 }
 
 impl<'a, S: BuildHasher> CopyCloneImpl<'a, S> {
     pub fn new(components: &'a HashMap<ComponentTypeId, ComponentRegistration, S>) -> Self {
-        Self { components }
+        Self {
+            components,
+            strip_editor_only: false,
+            filter: ComponentTypeFilter::default(),
+            unregistered_policy: UnregisteredComponentPolicy::default(),
+            skipped_types: HashSet::new(),
+        }
+    }
+
+    /// Same as new(), but leaves out components that are registered as ComponentUsage::EditorOnly
+    pub fn new_for_runtime(
+        components: &'a HashMap<ComponentTypeId, ComponentRegistration, S>
+    ) -> Self {
+        Self {
+            strip_editor_only: true,
+            ..Self::new(components)
+        }
+    }
+
+    /// Restricts the component types that are cloned
+    pub fn with_filter(
+        mut self,
+        filter: ComponentTypeFilter,
+    ) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Sets what happens to components whose type isn't registered, i.e. runtime-only components
+    /// such as physics handles. Panics by default
+    pub fn with_unregistered_policy(
+        mut self,
+        unregistered_policy: UnregisteredComponentPolicy,
+    ) -> Self {
+        self.unregistered_policy = unregistered_policy;
+        self
+    }
+
+    /// Returns the unregistered component types that were left out since the last call, sorted.
+    /// Returns an error instead if there were any and the policy is
+    /// UnregisteredComponentPolicy::Error
+    pub fn finish(&mut self) -> Result<Vec<ComponentTypeId>, CopyCloneError> {
+        let mut skipped_types: Vec<_> = self.skipped_types.drain().collect();
+        skipped_types.sort();
+
+        if self.unregistered_policy == UnregisteredComponentPolicy::Error
+            && !skipped_types.is_empty()
+        {
+            Err(CopyCloneError::UnregisteredComponentTypes(skipped_types))
+        } else {
+            Ok(skipped_types)
+        }
+    }
+
+    /// False for component types that this impl leaves out
+    pub fn is_cloned(
+        &self,
+        comp_reg: &ComponentRegistration,
+    ) -> bool {
+        (!self.strip_editor_only || comp_reg.usage().used_at_runtime())
+            && self.filter.allows(comp_reg.component_type_id())
+    }
+
+    // Returns the registration if components of this type are cloned, applying the policy for
+    // unregistered types
+    fn cloned_registration(
+        &mut self,
+        component_type: ComponentTypeId,
+    ) -> Option<&'a ComponentRegistration> {
+        if !self.filter.allows(component_type) {
+            return None;
+        }
+
+        let components = self.components;
+        match components.get(&component_type) {
+            Some(comp_reg) if self.is_cloned(comp_reg) => Some(comp_reg),
+            Some(_) => None,
+            None => {
+                if self.unregistered_policy == UnregisteredComponentPolicy::Panic {
+                    panic!("component type {:?} is not registered", component_type);
+                }
+
+                self.skipped_types.insert(component_type);
+                None
+            }
+        }
     }
 }
 
-impl<'a, S: BuildHasher> legion::world::Merger for CopyCloneImpl<'a, S> {
+impl<'a, S: BuildHasher> CloneMergeImpl for CopyCloneImpl<'a, S> {
+    fn clone_world<F: LayoutFilter>(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        filter: &F,
+    ) -> HashMap<Entity, Entity, EntityHasher> {
+        let result_mappings = dst.clone_from(src, filter, &mut CopyMerger(self));
+        crate::map_entity_refs(dst, &result_mappings, self.components);
+        result_mappings
+    }
+
+    fn clone_entities(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        entities: impl IntoIterator<Item = Entity>,
+    ) -> HashMap<Entity, Entity, EntityHasher> {
+        let result_mappings =
+            crate::clone_subset::clone_entities(dst, src, entities, &mut CopyMerger(self));
+        crate::map_entity_refs(dst, &result_mappings, self.components);
+        result_mappings
+    }
+}
+
+// The legion merger behind CopyCloneImpl. Not exposed so that clones always go through
+// CloneMergeImpl, which remaps entity references once the merge is done
+struct CopyMerger<'x, 'a, S: BuildHasher>(&'x mut CopyCloneImpl<'a, S>);
+
+impl<'x, 'a, S: BuildHasher> Merger for CopyMerger<'x, 'a, S> {
     fn prefers_new_archetype() -> bool {
         false
     }
@@ -35,8 +241,9 @@ impl<'a, S: BuildHasher> legion::world::Merger for CopyCloneImpl<'a, S> {
     ) -> EntityLayout {
         let mut dest_layout = EntityLayout::default();
         for component_type in source_layout.component_types() {
-            let comp_reg = &self.components[component_type];
-            comp_reg.register_component(&mut dest_layout);
+            if let Some(comp_reg) = self.0.cloned_registration(*component_type) {
+                comp_reg.register_component(&mut dest_layout);
+            }
         }
 
         dest_layout
@@ -50,7 +257,11 @@ impl<'a, S: BuildHasher> legion::world::Merger for CopyCloneImpl<'a, S> {
         dst: &mut ArchetypeWriter,
     ) {
         for src_type in src_arch.layout().component_types() {
-            let comp_reg = &self.components[src_type];
+            let comp_reg = match self.0.cloned_registration(*src_type) {
+                Some(comp_reg) => comp_reg,
+                None => continue,
+            };
+
             unsafe {
                 comp_reg.clone_components(src_entity_range.clone(), src_arch, src_components, dst);
             }
@@ -114,10 +325,130 @@ where
     }
 }
 
-/// A registry of handlers for use with SpawnCloneImpl
+/// Passed to mappings and post-merge hooks while spawning with SpawnCloneImpl
+pub struct SpawnContext<'a> {
+    resources: &'a Resources,
+    entity_map: &'a HashMap<Entity, Entity, EntityHasher>,
+    src_entity_uuids: &'a HashMap<Entity, EntityUuid>,
+    src_uuid_entities: &'a HashMap<EntityUuid, Entity>,
+}
+
+impl<'a> SpawnContext<'a> {
+    /// The resources of the world being spawned into
+    pub fn resources(&self) -> &Resources {
+        self.resources
+    }
+
+    /// The entity that src_entity is cloned into. While archetypes are being merged, this is only
+    /// known for the entities in the entity map passed to SpawnCloneImpl::new. In post-merge
+    /// hooks it is known for every cloned entity
+    pub fn dst_entity(
+        &self,
+        src_entity: Entity,
+    ) -> Option<Entity> {
+        self.entity_map.get(&src_entity).cloned()
+    }
+
+    /// The UUID of an entity in the source world. Only known if the UUIDs were passed to
+    /// SpawnCloneImpl::with_entity_uuids
+    pub fn entity_uuid(
+        &self,
+        src_entity: Entity,
+    ) -> Option<EntityUuid> {
+        self.src_entity_uuids.get(&src_entity).cloned()
+    }
+
+    /// The entity that the entity with the given UUID is cloned into, i.e. to turn a
+    /// `PrefabParent(EntityUuid)` into a `Parent(Entity)`. Subject to the same limits as dst_entity
+    /// and entity_uuid
+    pub fn uuid_to_dst_entity(
+        &self,
+        uuid: &EntityUuid,
+    ) -> Option<Entity> {
+        self.src_uuid_entities
+            .get(uuid)
+            .and_then(|src_entity| self.dst_entity(*src_entity))
+    }
+}
+
+type PostMergeHook = Box<dyn Fn(&mut World, &SpawnContext) + Send + Sync>;
+
+/// A component that a mapping added with add_mapping_filter failed to convert
+#[derive(Debug)]
+pub struct SpawnCloneError {
+    /// The entity in the world that was cloned from
+    pub src_entity: Entity,
+    /// The entity that was created for src_entity. None if the clone didn't create one
+    pub dst_entity: Option<Entity>,
+    /// The type of the component that failed to convert
+    pub src_type: ComponentTypeId,
+    pub message: String,
+}
+
+/// Describes what mappings added with add_mapping_filter did during the clones done with a
+/// SpawnCloneImpl, and which unregistered types were left out. Returned by SpawnCloneImpl::finish
+#[derive(Debug, Default)]
+pub struct SpawnCloneReport {
+    errors: Vec<SpawnCloneError>,
+    skipped_count: usize,
+    unregistered_types: Vec<ComponentTypeId>,
+    unregistered_types_are_errors: bool,
+}
+
+impl SpawnCloneReport {
+    /// True if every component was converted or deliberately skipped. Unregistered types that were
+    /// left out only count as failures under UnregisteredComponentPolicy::Error
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+            && !(self.unregistered_types_are_errors && !self.unregistered_types.is_empty())
+    }
+
+    pub fn errors(&self) -> &[SpawnCloneError] {
+        &self.errors
+    }
+
+    /// The number of components that mappings chose not to clone. Doesn't include errors
+    pub fn skipped_count(&self) -> usize {
+        self.skipped_count
+    }
+
+    /// The component types that were left out because they aren't registered and have no mapping,
+    /// sorted. Always empty under UnregisteredComponentPolicy::Panic
+    pub fn unregistered_types(&self) -> &[ComponentTypeId] {
+        &self.unregistered_types
+    }
+}
+
+// Collected while cloning. Every entity in a destination archetype must have all of its
+// components, so a component converted by an add_mapping_filter mapping is only part of the
+// destination layout if every entity of its source archetype got one. The components converted
+// for other archetypes are added to their entities once the archetypes have been merged
+#[derive(Default)]
+struct SpawnClonePending {
+    // The components converted by each add_mapping_filter mapping, by source and destination type.
+    // Each is a FilterConversions<IntoT>
+    conversions: HashMap<(ComponentTypeId, ComponentTypeId), Box<dyn Any>>,
+    skipped_count: usize,
+    errors: Vec<SpawnCloneError>,
+}
+
+// The components converted by an add_mapping_filter mapping before the archetypes are merged
+struct FilterConversions<IntoT> {
+    // By source entity. The ones written while merging are taken out
+    components: HashMap<Entity, IntoT, EntityHasher>,
+    // The layouts of the source archetypes in which every entity got a component
+    complete_layouts: HashSet<Vec<ComponentTypeId>>,
+}
+
+/// A registry of handlers for use with SpawnCloneImpl. Several mappings can be added for the same
+/// source type (with different destination types), in which case the source component is mapped
+/// into one component of each destination type.
 #[derive(Default)]
 pub struct SpawnCloneImplHandlerSet {
-    handlers: HashMap<ComponentTypeId, Box<dyn SpawnCloneImplMapping>>,
+    handlers: HashMap<ComponentTypeId, Vec<Box<dyn SpawnCloneImplMapping>>>,
+    post_merge_hooks: Vec<PostMergeHook>,
+    // Lets clones skip the conversion pass when there's nothing to convert
+    has_filter_mappings: bool,
 }
 
 impl SpawnCloneImplHandlerSet {
@@ -135,7 +466,7 @@ impl SpawnCloneImplHandlerSet {
 
         let handler = Box::new(SpawnCloneImplMappingImpl::<_, IntoT>::new(
             into_type_id,
-            |_resources: &Resources,
+            |_context: &SpawnContext,
              src_entity_range: Range<usize>,
              src_arch: &Archetype,
              src_components: &Components,
@@ -156,20 +487,21 @@ impl SpawnCloneImplHandlerSet {
             },
         ));
 
-        self.handlers.insert(from_type_id, handler);
+        self.add_handler(from_type_id, handler);
     }
 
     /// Adds a mapping from one component type to another. The trait impl will be passed the new
     /// world's resources and all the memory that holds the components. The memory passed into
     /// the closure as IntoT MUST be initialized or undefined behavior could happen on future access
-    /// of the memory
+    /// of the memory. Use add_mapping_closure_with_context or add_mapping_filter for mappings that
+    /// need the rest of the SpawnContext
     pub fn add_mapping<FromT: Component + Clone + SpawnInto<IntoT>, IntoT: Component>(&mut self) {
         let from_type_id = ComponentTypeId::of::<FromT>();
         let into_type_id = ComponentTypeId::of::<IntoT>();
 
         let handler = Box::new(SpawnCloneImplMappingImpl::<_, IntoT>::new(
             into_type_id,
-            |resources: &Resources,
+            |context: &SpawnContext,
              src_entity_range: Range<usize>,
              src_arch: &Archetype,
              src_components: &Components,
@@ -182,7 +514,7 @@ impl SpawnCloneImplHandlerSet {
                         &src.get(src_arch.index()).unwrap().into_slice()[src_entity_range.clone()];
                     dst.ensure_capacity(src_slice.len());
                     <FromT as SpawnInto<IntoT>>::spawn_into(
-                        resources,
+                        context.resources(),
                         src_entity_range,
                         src_arch,
                         src_components,
@@ -196,7 +528,7 @@ impl SpawnCloneImplHandlerSet {
             },
         ));
 
-        self.handlers.insert(from_type_id, handler);
+        self.add_handler(from_type_id, handler);
     }
 
     /// Adds a mapping from one component type to another. The closure will be passed the new
@@ -219,13 +551,46 @@ impl SpawnCloneImplHandlerSet {
             ) + Send
             + Sync
             + 'static,
+    {
+        self.add_mapping_closure_with_context::<FromT, IntoT, _>(
+            move |context, src_entity_range, src_arch, src_components, dst, push_fn| {
+                (clone_fn)(
+                    context.resources(),
+                    src_entity_range,
+                    src_arch,
+                    src_components,
+                    dst,
+                    push_fn,
+                )
+            },
+        );
+    }
+
+    /// Same as add_mapping_closure, but the closure is passed the SpawnContext, i.e. to look up the
+    /// destination entities of source entities in the entity map
+    pub fn add_mapping_closure_with_context<FromT, IntoT, F>(
+        &mut self,
+        clone_fn: F,
+    ) where
+        FromT: Component,
+        IntoT: Component,
+        F: Fn(
+                &SpawnContext,                          // context
+                Range<usize>,                           // src_entity_range
+                &Archetype,                             // src_arch
+                &Components,                            // src_components
+                &mut ComponentWriter<IntoT>,            // dst
+                fn(&mut ComponentWriter<IntoT>, IntoT), // push_fn
+            ) + Send
+            + Sync
+            + 'static,
     {
         let from_type_id = ComponentTypeId::of::<FromT>();
         let into_type_id = ComponentTypeId::of::<IntoT>();
 
         let handler = Box::new(SpawnCloneImplMappingImpl::<_, IntoT>::new(
             into_type_id,
-            move |resources: &Resources,
+            move |context: &SpawnContext,
                   src_entity_range: Range<usize>,
                   src_arch: &Archetype,
                   src_components: &Components,
@@ -239,7 +604,7 @@ impl SpawnCloneImplHandlerSet {
                     dst.ensure_capacity(src_slice.len());
 
                     (clone_fn)(
-                        resources,
+                        context,
                         src_entity_range,
                         src_arch,
                         src_components,
@@ -253,21 +618,94 @@ impl SpawnCloneImplHandlerSet {
             },
         ));
 
-        self.handlers.insert(from_type_id, handler);
+        self.add_handler(from_type_id, handler);
+    }
+
+    /// Adds a mapping that converts components one at a time and may leave some of them out. The
+    /// closure is passed the SpawnContext, the entity in the source world and its
+    /// component. It returns:
+    ///  - Ok(Some(component)) to give the new entity that component
+    ///  - Ok(None) to skip the component, i.e. for editor-only components
+    ///  - Err(message) if the component can't be converted, i.e. because an asset it references
+    ///    is missing. The component is skipped and the error is included in the report returned
+    ///    by SpawnCloneImpl::finish
+    ///
+    /// Components are converted before the archetypes are merged. If every entity of a source
+    /// archetype got a component, the components are written along with the rest of the
+    /// archetype. Otherwise they are added to the entities that got one after the merge, one
+    /// entity at a time. Both happen as part of the clone, and entities whose component was
+    /// skipped never have one.
+    pub fn add_mapping_filter<FromT, IntoT, F>(
+        &mut self,
+        map_fn: F,
+    ) where
+        FromT: Component,
+        IntoT: Component,
+        F: Fn(
+                &SpawnContext, // context
+                Entity,        // src_entity
+                &FromT,        // src_component
+            ) -> Result<Option<IntoT>, String>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let handler = Box::new(SpawnCloneImplFilterMappingImpl::<_, FromT, IntoT> {
+            map_fn,
+            phantom_data: Default::default(),
+        });
+
+        self.add_handler(ComponentTypeId::of::<FromT>(), handler);
+        self.has_filter_mappings = true;
+    }
+
+    /// Adds a hook that is run at the end of every clone, once all archetypes have been merged. The
+    /// SpawnContext passed to it knows the destination entity of every cloned entity, so it can
+    /// resolve relationships that mappings can't, since most destination entities aren't known
+    /// while archetypes are merged. Hooks run in the order they were added
+    pub fn add_post_merge_hook<F>(
+        &mut self,
+        hook: F,
+    ) where
+        F: Fn(&mut World, &SpawnContext) + Send + Sync + 'static,
+    {
+        self.post_merge_hooks.push(Box::new(hook));
+    }
+
+    // Replaces the mapping between the same pair of types if there is one
+    fn add_handler(
+        &mut self,
+        from_type_id: ComponentTypeId,
+        handler: Box<dyn SpawnCloneImplMapping>,
+    ) {
+        let handlers = self.handlers.entry(from_type_id).or_insert_with(Vec::new);
+        handlers.retain(|existing| existing.dst_type_id() != handler.dst_type_id());
+        handlers.push(handler);
     }
 }
 
-/// A CloneMergeImpl that
-///
-/// An implementation passed into legion::world::World::clone_merge. This implementation supports
-/// providing custom mappings with add_mapping (which takes a closure) and add_mapping_into (which
-/// uses Rust standard library's .into(). If a mapping isn't provided for a type, the component
-/// will be cloned using ComponentRegistration passed in new()
+/// A CloneMergeImpl that spawns entities, i.e. from a CookedPrefab into the runtime world. This
+/// implementation supports providing custom mappings with add_mapping (which takes a closure) and
+/// add_mapping_into (which uses Rust standard library's .into(). If a mapping isn't provided for a
+/// type, the component will be cloned using ComponentRegistration passed in new()
 pub struct SpawnCloneImpl<'a, 'b, 'c, 'd, S: BuildHasher> {
     handler_set: &'a SpawnCloneImplHandlerSet,
     components: &'b HashMap<ComponentTypeId, ComponentRegistration, S>,
     resources: &'c Resources,
     entity_map: &'d HashMap<Entity, Entity, EntityHasher>,
+    pending: SpawnClonePending,
+    strip_editor_only: bool,
+    unregistered_policy: UnregisteredComponentPolicy,
+
+    // What add_mapping_filter mappings did and the unregistered types that were left out since
+    // the last call to finish()
+    report: SpawnCloneReport,
+    unregistered_types: HashSet<ComponentTypeId>,
+
+    // UUIDs of the entities in the source world, in both directions. Empty unless
+    // with_entity_uuids is called
+    src_entity_uuids: HashMap<Entity, EntityUuid>,
+    src_uuid_entities: HashMap<EntityUuid, Entity>,
 }
 
 impl<'a, 'b, 'c, 'd, S: BuildHasher> SpawnCloneImpl<'a, 'b, 'c, 'd, S> {
@@ -283,11 +721,232 @@ impl<'a, 'b, 'c, 'd, S: BuildHasher> SpawnCloneImpl<'a, 'b, 'c, 'd, S> {
             components,
             resources,
             entity_map,
+            pending: SpawnClonePending::default(),
+            strip_editor_only: false,
+            unregistered_policy: UnregisteredComponentPolicy::default(),
+            report: SpawnCloneReport::default(),
+            unregistered_types: HashSet::new(),
+            src_entity_uuids: HashMap::new(),
+            src_uuid_entities: HashMap::new(),
+        }
+    }
+
+    /// Same as new(), but leaves out components that are registered as ComponentUsage::EditorOnly
+    /// and have no mapping. Use this when spawning into the runtime world, and new() when spawning
+    /// into an editor world
+    pub fn new_for_runtime(
+        handler_set: &'a SpawnCloneImplHandlerSet,
+        components: &'b HashMap<ComponentTypeId, ComponentRegistration, S>,
+        resources: &'c Resources,
+        entity_map: &'d HashMap<Entity, Entity, EntityHasher>,
+    ) -> Self {
+        Self {
+            strip_editor_only: true,
+            ..Self::new(handler_set, components, resources, entity_map)
+        }
+    }
+
+    /// Makes the UUIDs of the source entities available through SpawnContext, i.e. the entities
+    /// of the CookedPrefab being spawned
+    pub fn with_entity_uuids<T: BuildHasher>(
+        mut self,
+        uuid_to_entity: &HashMap<EntityUuid, Entity, T>,
+    ) -> Self {
+        self.src_entity_uuids = uuid_to_entity
+            .iter()
+            .map(|(uuid, entity)| (*entity, *uuid))
+            .collect();
+        self.src_uuid_entities = uuid_to_entity
+            .iter()
+            .map(|(uuid, entity)| (*uuid, *entity))
+            .collect();
+        self
+    }
+
+    /// Sets what happens to components whose type isn't registered and has no mapping, i.e.
+    /// runtime-only components such as physics handles. Panics by default
+    pub fn with_unregistered_policy(
+        mut self,
+        unregistered_policy: UnregisteredComponentPolicy,
+    ) -> Self {
+        self.unregistered_policy = unregistered_policy;
+        self
+    }
+
+    fn context<'e>(
+        &'e self,
+        entity_map: &'e HashMap<Entity, Entity, EntityHasher>,
+    ) -> SpawnContext<'e> {
+        SpawnContext {
+            resources: self.resources,
+            entity_map,
+            src_entity_uuids: &self.src_entity_uuids,
+            src_uuid_entities: &self.src_uuid_entities,
+        }
+    }
+
+    /// Reports what mappings added with add_mapping_filter skipped or failed to convert, and the
+    /// unregistered types that were left out. Each call covers the clones since the previous one
+    pub fn finish(&mut self) -> SpawnCloneReport {
+        let mut report = std::mem::take(&mut self.report);
+        report.unregistered_types = self.unregistered_types.drain().collect();
+        report.unregistered_types.sort();
+        report.unregistered_types_are_errors =
+            self.unregistered_policy == UnregisteredComponentPolicy::Error;
+        report
+    }
+
+    // Returns the registration used to clone components of a type without a mapping, if they are
+    // cloned, applying the policy for unregistered types
+    fn cloned_registration(
+        &mut self,
+        component_type: ComponentTypeId,
+    ) -> Option<&'b ComponentRegistration> {
+        let components = self.components;
+        match components.get(&component_type) {
+            Some(comp_reg) if !self.strip_editor_only || comp_reg.usage().used_at_runtime() => {
+                Some(comp_reg)
+            }
+            Some(_) => None,
+            None => {
+                if self.unregistered_policy == UnregisteredComponentPolicy::Panic {
+                    panic!(
+                        "component type {:?} is not registered and has no mapping",
+                        component_type
+                    );
+                }
+
+                self.unregistered_types.insert(component_type);
+                None
+            }
+        }
+    }
+
+    // Run before the archetypes are merged. Converts the components of the cloned entities that
+    // add_mapping_filter mappings handle, which decides which destination layouts include them
+    fn prepare_clone(
+        &mut self,
+        src: &World,
+        entities: impl IntoIterator<Item = Entity>,
+        is_cloned_layout: impl Fn(&[ComponentTypeId]) -> bool,
+    ) {
+        if !self.handler_set.has_filter_mappings {
+            return;
+        }
+
+        // The number of entities in each archetype, and the cloned ones
+        let mut archetypes: HashMap<Vec<ComponentTypeId>, (usize, Vec<Entity>)> = HashMap::new();
+        for entity in entities {
+            if let Ok(entry) = src.entry_ref(entity) {
+                let archetype = entry.archetype();
+                let layout = archetype.layout().component_types();
+                if is_cloned_layout(layout) {
+                    archetypes
+                        .entry(layout.to_vec())
+                        .or_insert_with(|| (archetype.entities().len(), vec![]))
+                        .1
+                        .push(entity);
+                }
+            }
+        }
+
+        // Built from the fields rather than with context() since pending is borrowed mutably below
+        let context = SpawnContext {
+            resources: self.resources,
+            entity_map: self.entity_map,
+            src_entity_uuids: &self.src_entity_uuids,
+            src_uuid_entities: &self.src_uuid_entities,
+        };
+
+        for (layout, (archetype_len, src_entities)) in &archetypes {
+            for component_type in layout {
+                if let Some(handlers) = self.handler_set.handlers.get(component_type) {
+                    for handler in handlers {
+                        handler.prepare(
+                            &context,
+                            src,
+                            layout,
+                            *archetype_len,
+                            src_entities,
+                            &mut self.pending,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Run once the archetypes have been merged. Converted components are added before entity
+    // references are remapped so that they are remapped too, and hooks run last so that they see
+    // the finished entities
+    fn complete_clone(
+        &mut self,
+        world: &mut World,
+        result_mappings: &HashMap<Entity, Entity, EntityHasher>,
+    ) {
+        let mut pending = std::mem::take(&mut self.pending);
+        for handlers in self.handler_set.handlers.values() {
+            for handler in handlers {
+                handler.complete(world, result_mappings, &mut pending);
+            }
+        }
+
+        crate::map_entity_refs(world, result_mappings, self.components);
+
+        let context = self.context(result_mappings);
+        for hook in &self.handler_set.post_merge_hooks {
+            (hook)(world, &context);
+        }
+
+        self.report.skipped_count += pending.skipped_count;
+        for mut error in pending.errors {
+            error.dst_entity = result_mappings.get(&error.src_entity).cloned();
+            self.report.errors.push(error);
         }
     }
 }
 
-impl<'a, 'b, 'c, 'd, S: BuildHasher> legion::world::Merger for SpawnCloneImpl<'a, 'b, 'c, 'd, S> {
+impl<'a, 'b, 'c, 'd, S: BuildHasher> CloneMergeImpl for SpawnCloneImpl<'a, 'b, 'c, 'd, S> {
+    fn clone_world<F: LayoutFilter>(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        filter: &F,
+    ) -> HashMap<Entity, Entity, EntityHasher> {
+        let mut all = Entity::query();
+        self.prepare_clone(src, all.iter(src).cloned(), |layout| {
+            match filter.matches_layout(layout) {
+                FilterResult::Match(matches) => matches,
+                FilterResult::Defer => true,
+            }
+        });
+
+        let result_mappings = dst.clone_from(src, filter, &mut SpawnMerger(self));
+        self.complete_clone(dst, &result_mappings);
+        result_mappings
+    }
+
+    fn clone_entities(
+        &mut self,
+        dst: &mut World,
+        src: &World,
+        entities: impl IntoIterator<Item = Entity>,
+    ) -> HashMap<Entity, Entity, EntityHasher> {
+        let entities: HashSet<Entity> = entities.into_iter().collect();
+        self.prepare_clone(src, entities.iter().cloned(), |_| true);
+
+        let result_mappings =
+            crate::clone_subset::clone_entities(dst, src, entities, &mut SpawnMerger(self));
+        self.complete_clone(dst, &result_mappings);
+        result_mappings
+    }
+}
+
+// The legion merger behind SpawnCloneImpl. Not exposed so that clones always go through
+// CloneMergeImpl, which adds the converted components and remaps entity references
+struct SpawnMerger<'x, 'a, 'b, 'c, 'd, S: BuildHasher>(&'x mut SpawnCloneImpl<'a, 'b, 'c, 'd, S>);
+
+impl<'x, 'a, 'b, 'c, 'd, S: BuildHasher> Merger for SpawnMerger<'x, 'a, 'b, 'c, 'd, S> {
     fn prefers_new_archetype() -> bool {
         false
     }
@@ -305,7 +964,7 @@ impl<'a, 'b, 'c, 'd, S: BuildHasher> legion::world::Merger for SpawnCloneImpl<'a
         existing: Entity,
         allocator: &mut Allocate,
     ) -> Entity {
-        if let Some(e) = self.entity_map.get(&existing) {
+        if let Some(e) = self.0.entity_map.get(&existing) {
             *e
         } else {
             allocator.next().unwrap()
@@ -318,13 +977,18 @@ impl<'a, 'b, 'c, 'd, S: BuildHasher> legion::world::Merger for SpawnCloneImpl<'a
     ) -> EntityLayout {
         let mut dest_layout = EntityLayout::default();
         for component_type in source_layout.component_types() {
-            // We expect any type we will encounter to be registered either as an explicit mapping or
-            // registered in the component registrations
-            let handler = &self.handler_set.handlers.get(&component_type);
-            if let Some(handler) = handler {
-                handler.register_dst_type(&mut dest_layout);
-            } else {
-                let comp_reg = &self.components[component_type];
+            // Types without a mapping are cloned with their registration, and the policy decides
+            // what happens to types without either
+            let handlers = &self.0.handler_set.handlers.get(&component_type);
+            if let Some(handlers) = handlers {
+                for handler in *handlers {
+                    handler.register_dst_type(
+                        source_layout.component_types(),
+                        &mut dest_layout,
+                        &self.0.pending,
+                    );
+                }
+            } else if let Some(comp_reg) = self.0.cloned_registration(*component_type) {
                 comp_reg.register_component(&mut dest_layout);
             }
         }
@@ -340,19 +1004,30 @@ impl<'a, 'b, 'c, 'd, S: BuildHasher> legion::world::Merger for SpawnCloneImpl<'a
         dst: &mut ArchetypeWriter,
     ) {
         for src_type in src_arch.layout().component_types() {
-            // We expect any type we will encounter to be registered either as an explicit mapping or
-            // registered in the component registrations
-            let handler = &self.handler_set.handlers.get(&src_type);
-            if let Some(handler) = handler {
-                handler.clone_components(
-                    self.resources,
-                    src_entity_range.clone(),
-                    src_arch,
-                    src_components,
-                    dst,
-                )
-            } else {
-                let comp_reg = &self.components[&src_type];
+            // Types without a mapping are cloned with their registration, and the policy decides
+            // what happens to types without either
+            let handlers = &self.0.handler_set.handlers.get(&src_type);
+            if let Some(handlers) = handlers {
+                // Built from the fields rather than with context() since pending is
+                // borrowed mutably below
+                let context = SpawnContext {
+                    resources: self.0.resources,
+                    entity_map: self.0.entity_map,
+                    src_entity_uuids: &self.0.src_entity_uuids,
+                    src_uuid_entities: &self.0.src_uuid_entities,
+                };
+
+                for handler in *handlers {
+                    handler.clone_components(
+                        &context,
+                        src_entity_range.clone(),
+                        src_arch,
+                        src_components,
+                        dst,
+                        &mut self.0.pending,
+                    )
+                }
+            } else if let Some(comp_reg) = self.0.cloned_registration(*src_type) {
                 unsafe {
                     comp_reg.clone_components(
                         src_entity_range.clone(),
@@ -371,26 +1046,52 @@ impl<'a, 'b, 'c, 'd, S: BuildHasher> legion::world::Merger for SpawnCloneImpl<'a
 trait SpawnCloneImplMapping: Send + Sync {
     fn dst_type_id(&self) -> ComponentTypeId;
 
+    // Called before the archetypes are merged with the cloned entities of each source archetype
+    // that has the source type
+    #[allow(clippy::too_many_arguments)]
+    fn prepare(
+        &self,
+        _context: &SpawnContext,
+        _src: &World,
+        _src_layout: &[ComponentTypeId],
+        _archetype_len: usize,
+        _src_entities: &[Entity],
+        _pending: &mut SpawnClonePending,
+    ) {
+    }
+
     fn register_dst_type(
         &self,
+        src_layout: &[ComponentTypeId],
         entity_layout: &mut EntityLayout,
+        pending: &SpawnClonePending,
     );
 
     #[allow(clippy::too_many_arguments)]
     fn clone_components(
         &self,
-        resources: &Resources,
+        context: &SpawnContext,
         src_entity_range: Range<usize>,
         src_arch: &Archetype,
         src_components: &Components,
         dst: &mut ArchetypeWriter,
+        pending: &mut SpawnClonePending,
     );
+
+    // Called once the archetypes have been merged
+    fn complete(
+        &self,
+        _world: &mut World,
+        _result_mappings: &HashMap<Entity, Entity, EntityHasher>,
+        _pending: &mut SpawnClonePending,
+    ) {
+    }
 }
 
 struct SpawnCloneImplMappingImpl<F, IntoT>
 where
     F: Fn(
-        &Resources,           // resources
+        &SpawnContext,        // context
         Range<usize>,         // src_entity_range
         &Archetype,           // src_arch
         &Components,          // src_components
@@ -407,7 +1108,7 @@ where
 impl<F, IntoT> SpawnCloneImplMappingImpl<F, IntoT>
 where
     F: Fn(
-        &Resources,           // resources
+        &SpawnContext,        // context
         Range<usize>,         // src_entity_range
         &Archetype,           // src_arch
         &Components,          // src_components
@@ -430,7 +1131,7 @@ where
 impl<F, IntoT> SpawnCloneImplMapping for SpawnCloneImplMappingImpl<F, IntoT>
 where
     F: Fn(
-            &Resources,           // resources
+            &SpawnContext,        // context
             Range<usize>,         // src_entity_range
             &Archetype,           // src_arch
             &Components,          // src_components
@@ -445,19 +1146,428 @@ where
 
     fn register_dst_type(
         &self,
+        _src_layout: &[ComponentTypeId],
         entity_layout: &mut EntityLayout,
+        _pending: &SpawnClonePending,
     ) {
         entity_layout.register_component::<IntoT>();
     }
 
     fn clone_components(
         &self,
-        resources: &Resources,
+        context: &SpawnContext,
         src_entity_range: Range<usize>,
         src_arch: &Archetype,
         src_components: &Components,
         dst: &mut ArchetypeWriter,
+        _pending: &mut SpawnClonePending,
+    ) {
+        (self.clone_fn)(context, src_entity_range, src_arch, src_components, dst);
+    }
+}
+
+/// Created by add_mapping_filter. Converts the components of the cloned entities before the
+/// archetypes are merged and holds on to the converted ones until they are written or added
+struct SpawnCloneImplFilterMappingImpl<F, FromT, IntoT> {
+    map_fn: F,
+    phantom_data: PhantomData<fn(&FromT) -> IntoT>,
+}
+
+impl<F, FromT, IntoT> SpawnCloneImplFilterMappingImpl<F, FromT, IntoT>
+where
+    FromT: Component,
+    IntoT: Component,
+{
+    fn conversions<'p>(
+        &self,
+        pending: &'p SpawnClonePending,
+    ) -> Option<&'p FilterConversions<IntoT>> {
+        pending
+            .conversions
+            .get(&Self::conversions_key())
+            .and_then(|conversions| conversions.downcast_ref())
+    }
+
+    fn conversions_key() -> (ComponentTypeId, ComponentTypeId) {
+        (
+            ComponentTypeId::of::<FromT>(),
+            ComponentTypeId::of::<IntoT>(),
+        )
+    }
+}
+
+impl<F, FromT, IntoT> SpawnCloneImplMapping for SpawnCloneImplFilterMappingImpl<F, FromT, IntoT>
+where
+    FromT: Component,
+    IntoT: Component,
+    F: Fn(&SpawnContext, Entity, &FromT) -> Result<Option<IntoT>, String> + Send + Sync,
+{
+    fn dst_type_id(&self) -> ComponentTypeId {
+        ComponentTypeId::of::<IntoT>()
+    }
+
+    fn prepare(
+        &self,
+        context: &SpawnContext,
+        src: &World,
+        src_layout: &[ComponentTypeId],
+        archetype_len: usize,
+        src_entities: &[Entity],
+        pending: &mut SpawnClonePending,
+    ) {
+        let conversions = pending
+            .conversions
+            .entry(Self::conversions_key())
+            .or_insert_with(|| {
+                Box::new(FilterConversions::<IntoT> {
+                    components: HashMap::default(),
+                    complete_layouts: HashSet::new(),
+                })
+            })
+            .downcast_mut::<FilterConversions<IntoT>>()
+            .unwrap();
+
+        let mut converted_count = 0;
+        for src_entity in src_entities {
+            let component = src
+                .entry_ref(*src_entity)
+                .ok()
+                .and_then(|entry| entry.into_component::<FromT>().ok());
+            let component = match component {
+                Some(component) => component,
+                None => continue,
+            };
+
+            match (self.map_fn)(context, *src_entity, component) {
+                Ok(Some(into)) => {
+                    conversions.components.insert(*src_entity, into);
+                    converted_count += 1;
+                }
+                Ok(None) => pending.skipped_count += 1,
+                Err(message) => pending.errors.push(SpawnCloneError {
+                    src_entity: *src_entity,
+                    dst_entity: None,
+                    src_type: ComponentTypeId::of::<FromT>(),
+                    message,
+                }),
+            }
+        }
+
+        if converted_count == archetype_len {
+            conversions.complete_layouts.insert(src_layout.to_vec());
+        }
+    }
+
+    fn register_dst_type(
+        &self,
+        src_layout: &[ComponentTypeId],
+        entity_layout: &mut EntityLayout,
+        pending: &SpawnClonePending,
+    ) {
+        let is_complete = self
+            .conversions(pending)
+            .map(|conversions| conversions.complete_layouts.contains(src_layout))
+            .unwrap_or(false);
+        if is_complete {
+            entity_layout.register_component::<IntoT>();
+        }
+    }
+
+    fn clone_components(
+        &self,
+        _context: &SpawnContext,
+        src_entity_range: Range<usize>,
+        src_arch: &Archetype,
+        _src_components: &Components,
+        dst: &mut ArchetypeWriter,
+        pending: &mut SpawnClonePending,
     ) {
-        (self.clone_fn)(resources, src_entity_range, src_arch, src_components, dst);
+        let conversions = pending
+            .conversions
+            .get_mut(&Self::conversions_key())
+            .and_then(|conversions| conversions.downcast_mut::<FilterConversions<IntoT>>());
+        let conversions = match conversions {
+            Some(conversions) => conversions,
+            None => return,
+        };
+
+        // The components of other archetypes aren't part of the destination layout
+        if !conversions
+            .complete_layouts
+            .contains(src_arch.layout().component_types())
+        {
+            return;
+        }
+
+        unsafe {
+            let mut dst = dst.claim_components::<IntoT>();
+            dst.ensure_capacity(src_entity_range.len());
+            for src_entity in &src_arch.entities()[src_entity_range] {
+                // Every entity of a complete archetype got a component
+                let into = conversions.components.remove(src_entity).unwrap();
+                dst.extend_memcopy(&into as *const IntoT, 1);
+                std::mem::forget(into);
+            }
+        }
+    }
+
+    fn complete(
+        &self,
+        world: &mut World,
+        result_mappings: &HashMap<Entity, Entity, EntityHasher>,
+        pending: &mut SpawnClonePending,
+    ) {
+        let conversions = pending
+            .conversions
+            .remove(&Self::conversions_key())
+            .and_then(|conversions| conversions.downcast::<FilterConversions<IntoT>>().ok());
+
+        if let Some(conversions) = conversions {
+            for (src_entity, into) in conversions.components {
+                let entry = result_mappings
+                    .get(&src_entity)
+                    .and_then(|dst_entity| world.entry(*dst_entity));
+                if let Some(mut entry) = entry {
+                    entry.add_component(into);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EntityMap, MapEntities};
+    use serde::{Deserialize, Serialize};
+    use serde_diff::SerdeDiff;
+    use type_uuid::TypeUuid;
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "0b6f9a43-6a1f-4c2e-8d0e-3f5c8a9e2b71"]
+    struct Target {
+        #[serde_diff(opaque)]
+        entity: Option<Entity>,
+    }
+
+    impl MapEntities for Target {
+        fn map_entities(
+            &mut self,
+            entity_map: &EntityMap,
+        ) {
+            self.entity.map_entities(entity_map);
+        }
+    }
+
+    fn components() -> HashMap<ComponentTypeId, ComponentRegistration> {
+        let registration = ComponentRegistration::of_map_entities::<Target>();
+        let mut components = HashMap::new();
+        components.insert(registration.component_type_id(), registration);
+        components
+    }
+
+    // Two entities that refer to each other
+    fn linked_world() -> (World, Entity, Entity) {
+        let mut world = World::default();
+        let first = world.extend(vec![(Target::default(),)])[0];
+        let second = world.extend(vec![(Target {
+            entity: Some(first),
+        },)])[0];
+        world
+            .entry(first)
+            .unwrap()
+            .get_component_mut::<Target>()
+            .unwrap()
+            .entity = Some(second);
+        (world, first, second)
+    }
+
+    fn target(
+        world: &World,
+        entity: Entity,
+    ) -> Option<Entity> {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Target>()
+            .unwrap()
+            .entity
+    }
+
+    #[test]
+    fn copy_clone_remaps_entity_references() {
+        let components = components();
+        let (src, first, second) = linked_world();
+
+        let mut dst = World::default();
+        let result_mappings =
+            CopyCloneImpl::new(&components).clone_world(&mut dst, &src, &legion::query::any());
+        assert_eq!(
+            target(&dst, result_mappings[&first]),
+            Some(result_mappings[&second])
+        );
+        assert_eq!(
+            target(&dst, result_mappings[&second]),
+            Some(result_mappings[&first])
+        );
+
+        // References to entities that aren't cloned are left alone
+        let mut dst = World::default();
+        let result_mappings =
+            CopyCloneImpl::new(&components).clone_entities(&mut dst, &src, vec![first]);
+        assert_eq!(target(&dst, result_mappings[&first]), Some(second));
+    }
+
+    #[test]
+    fn spawn_clone_remaps_entity_references() {
+        let components = components();
+        let (src, first, second) = linked_world();
+
+        let handler_set = SpawnCloneImplHandlerSet::new();
+        let resources = Resources::default();
+        let entity_map = HashMap::default();
+        let mut clone_impl =
+            SpawnCloneImpl::new(&handler_set, &components, &resources, &entity_map);
+
+        let mut dst = World::default();
+        let result_mappings = clone_impl.clone_world(&mut dst, &src, &legion::query::any());
+        assert_eq!(
+            target(&dst, result_mappings[&first]),
+            Some(result_mappings[&second])
+        );
+        assert!(clone_impl.finish().is_ok());
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Level(u32);
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Converted(u32);
+
+    #[test]
+    fn filter_mapping_converts_complete_and_mixed_archetypes() {
+        let components = components();
+
+        // Every entity of the first archetype is converted, but only some of the second
+        let mut src = World::default();
+        let complete = src.extend(vec![(Level(1),), (Level(2),)]).to_vec();
+        let mixed = src
+            .extend(vec![
+                (Level(0), Target::default()),
+                (Level(3), Target::default()),
+                (Level(99), Target::default()),
+            ])
+            .to_vec();
+
+        let mut handler_set = SpawnCloneImplHandlerSet::new();
+        handler_set.add_mapping_filter::<Level, Converted, _>(
+            |_context, _entity, level| match level.0 {
+                0 => Ok(None),
+                99 => Err("level too high".to_string()),
+                level => Ok(Some(Converted(level))),
+            },
+        );
+        let resources = Resources::default();
+        let entity_map = HashMap::default();
+        let mut clone_impl =
+            SpawnCloneImpl::new(&handler_set, &components, &resources, &entity_map);
+
+        let mut dst = World::default();
+        let result_mappings = clone_impl.clone_world(&mut dst, &src, &legion::query::any());
+        assert_eq!(dst.len(), 5);
+
+        let converted = |src_entity: Entity| {
+            dst.entry_ref(result_mappings[&src_entity])
+                .unwrap()
+                .get_component::<Converted>()
+                .ok()
+                .cloned()
+        };
+        assert_eq!(converted(complete[0]), Some(Converted(1)));
+        assert_eq!(converted(complete[1]), Some(Converted(2)));
+        assert_eq!(converted(mixed[0]), None);
+        assert_eq!(converted(mixed[1]), Some(Converted(3)));
+        assert_eq!(converted(mixed[2]), None);
+
+        let report = clone_impl.finish();
+        assert_eq!(report.skipped_count(), 1);
+        assert_eq!(report.errors().len(), 1);
+        assert_eq!(report.errors()[0].src_entity, mixed[2]);
+        assert_eq!(
+            report.errors()[0].dst_entity,
+            Some(result_mappings[&mixed[2]])
+        );
+    }
+
+    #[test]
+    fn spawn_clone_applies_unregistered_policy() {
+        let components = components();
+
+        let mut src = World::default();
+        let entity = src.extend(vec![(Level(1), Target::default())])[0];
+
+        let handler_set = SpawnCloneImplHandlerSet::new();
+        let resources = Resources::default();
+        let entity_map = HashMap::default();
+
+        for &(policy, is_ok) in &[
+            (UnregisteredComponentPolicy::Skip, true),
+            (UnregisteredComponentPolicy::Error, false),
+        ] {
+            let mut clone_impl =
+                SpawnCloneImpl::new(&handler_set, &components, &resources, &entity_map)
+                    .with_unregistered_policy(policy);
+
+            let mut dst = World::default();
+            let result_mappings = clone_impl.clone_world(&mut dst, &src, &legion::query::any());
+            let entry = dst.entry_ref(result_mappings[&entity]).unwrap();
+            assert!(entry.get_component::<Target>().is_ok());
+            assert!(entry.get_component::<Level>().is_err());
+
+            let report = clone_impl.finish();
+            assert_eq!(
+                report.unregistered_types(),
+                &[ComponentTypeId::of::<Level>()]
+            );
+            assert_eq!(report.is_ok(), is_ok);
+        }
+    }
+
+    #[test]
+    fn only_runtime_spawn_clone_strips_editor_only_components() {
+        let registration = ComponentRegistration::of_map_entities::<Target>()
+            .with_usage(crate::ComponentUsage::EditorOnly);
+        let mut components = HashMap::new();
+        components.insert(registration.component_type_id(), registration);
+
+        let mut src = World::default();
+        let entity = src.extend(vec![(Target::default(),)])[0];
+
+        let handler_set = SpawnCloneImplHandlerSet::new();
+        let resources = Resources::default();
+        let entity_map = HashMap::default();
+
+        let mut dst = World::default();
+        let result_mappings =
+            SpawnCloneImpl::new(&handler_set, &components, &resources, &entity_map).clone_world(
+                &mut dst,
+                &src,
+                &legion::query::any(),
+            );
+        assert!(dst
+            .entry_ref(result_mappings[&entity])
+            .unwrap()
+            .get_component::<Target>()
+            .is_ok());
+
+        let mut dst = World::default();
+        let result_mappings =
+            SpawnCloneImpl::new_for_runtime(&handler_set, &components, &resources, &entity_map)
+                .clone_world(&mut dst, &src, &legion::query::any());
+        assert!(dst
+            .entry_ref(result_mappings[&entity])
+            .unwrap()
+            .get_component::<Target>()
+            .is_err());
     }
 }
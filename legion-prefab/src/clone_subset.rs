@@ -0,0 +1,331 @@
+use crate::CloneMergeImpl;
+use legion::query::{FilterResult, LayoutFilter};
+use legion::storage::ComponentTypeId;
+use legion::world::{EntityHasher, Merger};
+use legion::*;
+use prefab_format::EntityUuid;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+// Matches the archetypes that are cloned whole
+struct SelectedLayoutsFilter {
+    layouts: HashSet<Vec<ComponentTypeId>>,
+}
+
+impl LayoutFilter for SelectedLayoutsFilter {
+    fn matches_layout(
+        &self,
+        components: &[ComponentTypeId],
+    ) -> FilterResult {
+        FilterResult::Match(self.layouts.contains(components))
+    }
+}
+
+// Implements CloneMergeImpl::clone_entities for the clone impls of this crate, which finish the
+// clone themselves.
+//
+// legion clones whole archetypes, so the archetypes whose entities are all selected are cloned
+// with a single clone_from. The selected entities of other archetypes are cloned one at a time
+// with clone_from_single. Cloning a whole archetype and deleting the unselected entities afterwards
+// isn't an option, since the merger may have assigned them to entities that already exist in dst,
+// which would then be lost.
+pub(crate) fn clone_entities<M: Merger>(
+    dst: &mut World,
+    src: &World,
+    entities: impl IntoIterator<Item = Entity>,
+    merger: &mut M,
+) -> HashMap<Entity, Entity, EntityHasher> {
+    let entities: HashSet<Entity> = entities.into_iter().collect();
+
+    // The number of entities in each archetype, and the selected ones
+    let mut archetypes: HashMap<Vec<ComponentTypeId>, (usize, Vec<Entity>)> = HashMap::new();
+    for entity in &entities {
+        if let Ok(entry) = src.entry_ref(*entity) {
+            let archetype = entry.archetype();
+            archetypes
+                .entry(archetype.layout().component_types().to_vec())
+                .or_insert_with(|| (archetype.entities().len(), vec![]))
+                .1
+                .push(*entity);
+        }
+    }
+
+    let mut batched_layouts = HashSet::new();
+    let mut single_entities = vec![];
+    for (layout, (len, selected)) in archetypes {
+        if selected.len() == len {
+            batched_layouts.insert(layout);
+        } else {
+            single_entities.extend(selected);
+        }
+    }
+
+    let mut result_mappings = HashMap::default();
+    if !batched_layouts.is_empty() {
+        let filter = SelectedLayoutsFilter {
+            layouts: batched_layouts,
+        };
+        result_mappings = dst.clone_from(src, &filter, merger);
+    }
+
+    for entity in single_entities {
+        let cloned = dst.clone_from_single(src, entity, merger);
+        result_mappings.insert(entity, cloned);
+    }
+
+    result_mappings
+}
+
+/// Clones the entities with the given UUIDs from src into dst with CloneMergeImpl::clone_entities.
+/// Returns the UUIDs of the cloned entities and their entities in dst. UUIDs that aren't in uuid_to_entity
+/// or whose entity doesn't exist in src are left out.
+pub fn clone_entities_by_uuid<C: CloneMergeImpl, S: BuildHasher>(
+    dst: &mut World,
+    src: &World,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, S>,
+    selection: impl IntoIterator<Item = EntityUuid>,
+    clone_impl: &mut C,
+) -> HashMap<EntityUuid, Entity> {
+    let selection: Vec<_> = selection
+        .into_iter()
+        .filter_map(|uuid| uuid_to_entity.get(&uuid).map(|entity| (uuid, *entity)))
+        .collect();
+
+    let result_mappings =
+        clone_impl.clone_entities(dst, src, selection.iter().map(|(_, entity)| *entity));
+
+    selection
+        .into_iter()
+        .filter_map(|(uuid, entity)| result_mappings.get(&entity).map(|cloned| (uuid, *cloned)))
+        .collect()
+}
+
+/// Clones all entities in archetypes matching the filter, i.e. `legion::query::component::<T>()`.
+/// Returns the UUIDs of the cloned entities and their entities in dst. Cloned entities that aren't
+/// in uuid_to_entity are cloned but not returned.
+pub fn clone_filtered<F: LayoutFilter, C: CloneMergeImpl, S: BuildHasher>(
+    dst: &mut World,
+    src: &World,
+    filter: &F,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, S>,
+    clone_impl: &mut C,
+) -> HashMap<EntityUuid, Entity> {
+    let result_mappings = clone_impl.clone_world(dst, src, filter);
+
+    uuid_to_entity
+        .iter()
+        .filter_map(|(uuid, entity)| result_mappings.get(entity).map(|cloned| (*uuid, *cloned)))
+        .collect()
+}
+
+/// Clones the given root entities and all of their descendants with
+/// CloneMergeImpl::clone_entities. The
+/// hierarchy is defined by `children`, which returns the children of an entity in src (i.e. read
+/// from a `Children` component). Returns the UUIDs of the cloned entities and their entities in
+/// dst. Descendants that aren't in uuid_to_entity are cloned but not returned.
+pub fn clone_subtree<C: CloneMergeImpl, S: BuildHasher>(
+    dst: &mut World,
+    src: &World,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, S>,
+    roots: impl IntoIterator<Item = EntityUuid>,
+    mut children: impl FnMut(&World, Entity) -> Vec<Entity>,
+    clone_impl: &mut C,
+) -> HashMap<EntityUuid, Entity> {
+    let mut subtree = HashSet::new();
+    let mut pending: Vec<Entity> = roots
+        .into_iter()
+        .filter_map(|uuid| uuid_to_entity.get(&uuid).cloned())
+        .collect();
+
+    // Guards against cycles, which would otherwise never finish
+    while let Some(entity) = pending.pop() {
+        if subtree.insert(entity) {
+            pending.extend(children(src, entity));
+        }
+    }
+
+    let result_mappings = clone_impl.clone_entities(dst, src, subtree);
+
+    uuid_to_entity
+        .iter()
+        .filter_map(|(uuid, entity)| result_mappings.get(entity).map(|cloned| (*uuid, *cloned)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{by_type_id, number, number_component, set_number};
+    use crate::{ComponentRegistration, CopyCloneImpl};
+    use legion::storage::{Archetype, ArchetypeWriter, Components, EntityLayout};
+    use legion::world::{Allocate, EntityRewrite};
+    use std::ops::Range;
+
+    fn spawn(
+        world: &mut World,
+        registrations: &[&ComponentRegistration],
+        x: f64,
+    ) -> Entity {
+        let entity = world.extend(vec![()])[0];
+        for registration in registrations {
+            set_number(registration, world, entity, x);
+        }
+        entity
+    }
+
+    // Clones source entities into the destination entities given by entity_map, the way
+    // SpawnCloneImpl does
+    struct MappingMerger<'a> {
+        components: &'a HashMap<ComponentTypeId, ComponentRegistration>,
+        entity_map: HashMap<Entity, Entity, EntityHasher>,
+    }
+
+    impl<'a> Merger for MappingMerger<'a> {
+        fn prefers_new_archetype() -> bool {
+            false
+        }
+
+        fn entity_map(&mut self) -> EntityRewrite {
+            EntityRewrite::default()
+        }
+
+        fn assign_id(
+            &mut self,
+            existing: Entity,
+            allocator: &mut Allocate,
+        ) -> Entity {
+            match self.entity_map.get(&existing) {
+                Some(entity) => *entity,
+                None => allocator.next().unwrap(),
+            }
+        }
+
+        fn convert_layout(
+            &mut self,
+            source_layout: EntityLayout,
+        ) -> EntityLayout {
+            let mut dest_layout = EntityLayout::default();
+            for component_type in source_layout.component_types() {
+                self.components[component_type].register_component(&mut dest_layout);
+            }
+            dest_layout
+        }
+
+        fn merge_archetype(
+            &mut self,
+            src_entity_range: Range<usize>,
+            src_arch: &Archetype,
+            src_components: &Components,
+            dst: &mut ArchetypeWriter,
+        ) {
+            for component_type in src_arch.layout().component_types() {
+                unsafe {
+                    self.components[component_type].clone_components(
+                        src_entity_range.clone(),
+                        src_arch,
+                        src_components,
+                        dst,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clones_only_selected_entities() {
+        let position = number_component("CloneSubsetPosition");
+        let velocity = number_component("CloneSubsetVelocity");
+        let components = by_type_id(&[&position, &velocity]);
+
+        let mut src = World::default();
+        let positions: Vec<_> = (0..4)
+            .map(|i| spawn(&mut src, &[&position], i as f64))
+            .collect();
+        let moving = spawn(&mut src, &[&position, &velocity], 10.0);
+
+        // One of four entities in the first archetype is cloned on its own, and the second
+        // archetype is cloned whole
+        let mut dst = World::default();
+        let result_mappings = CopyCloneImpl::new(&components).clone_entities(
+            &mut dst,
+            &src,
+            vec![positions[2], moving],
+        );
+
+        assert_eq!(result_mappings.len(), 2);
+        assert_eq!(dst.len(), 2);
+        assert_eq!(
+            number(&position, &dst, result_mappings[&positions[2]]),
+            Some(2.0)
+        );
+        assert_eq!(
+            number(&velocity, &dst, result_mappings[&moving]),
+            Some(10.0)
+        );
+    }
+
+    #[test]
+    fn clones_whole_archetypes_and_ignores_missing_entities() {
+        let position = number_component("CloneSubsetWholePosition");
+        let components = by_type_id(&[&position]);
+
+        let mut src = World::default();
+        let entities: Vec<_> = (0..4)
+            .map(|i| spawn(&mut src, &[&position], i as f64))
+            .collect();
+        let missing = src.extend(vec![()])[0];
+        src.remove(missing);
+
+        let mut dst = World::default();
+        let mut selection = entities.clone();
+        selection.push(missing);
+        let result_mappings =
+            CopyCloneImpl::new(&components).clone_entities(&mut dst, &src, selection);
+
+        assert_eq!(result_mappings.len(), 4);
+        assert_eq!(dst.len(), 4);
+        for (i, entity) in entities.iter().enumerate() {
+            assert_eq!(
+                number(&position, &dst, result_mappings[entity]),
+                Some(i as f64)
+            );
+        }
+    }
+
+    #[test]
+    fn unselected_entities_mapped_to_existing_entities_are_left_alone() {
+        let position = number_component("CloneSubsetMappedPosition");
+        let components = by_type_id(&[&position]);
+
+        let mut src = World::default();
+        let entities: Vec<_> = (0..4)
+            .map(|i| spawn(&mut src, &[&position], i as f64))
+            .collect();
+
+        let mut dst = World::default();
+        let selected_target = spawn(&mut dst, &[&position], 100.0);
+        let unselected_target = spawn(&mut dst, &[&position], 200.0);
+
+        let mut entity_map = HashMap::default();
+        entity_map.insert(entities[0], selected_target);
+        entity_map.insert(entities[1], unselected_target);
+        let mut merger = MappingMerger {
+            components: &components,
+            entity_map,
+        };
+
+        // Half of the archetype is selected, including an entity mapped onto an existing one
+        let result_mappings =
+            clone_entities(&mut dst, &src, vec![entities[0], entities[2]], &mut merger);
+
+        assert_eq!(result_mappings.len(), 2);
+        assert_eq!(result_mappings[&entities[0]], selected_target);
+        assert_eq!(dst.len(), 3);
+        assert_eq!(number(&position, &dst, selected_target), Some(0.0));
+        assert_eq!(number(&position, &dst, unselected_target), Some(200.0));
+        assert_eq!(
+            number(&position, &dst, result_mappings[&entities[2]]),
+            Some(2.0)
+        );
+    }
+}
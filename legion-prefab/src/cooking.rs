@@ -1,15 +1,139 @@
 use legion::*;
 use legion::storage::ComponentTypeId;
 use std::collections::HashMap;
-use crate::{CookedPrefab, Prefab, ComponentRegistration, CopyCloneImpl};
+use crate::{CookedPrefab, Prefab, ComponentRegistration, CloneMergeImpl, CopyCloneImpl};
 use prefab_format::{PrefabUuid, ComponentTypeUuid};
 use std::hash::BuildHasher;
+use std::collections::HashSet;
 
+#[derive(Debug)]
+pub enum PrefabCookOrderError {
+    /// A prefab references another prefab that was not provided
+    MissingPrefab(PrefabUuid),
+    /// A prefab references itself, either directly or through other prefabs. The cycle is listed
+    /// in reference order, starting and ending with the same prefab
+    ReferenceCycle(Vec<PrefabUuid>),
+}
+
+/// Determines the order in which prefabs must be cooked so that every prefab comes after all the
+/// prefabs it references. The root prefab is always last.
+pub fn prefab_cook_order<U: BuildHasher>(
+    root_prefab: PrefabUuid,
+    prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+) -> Result<Vec<PrefabUuid>, PrefabCookOrderError> {
+    let mut cook_order = vec![];
+    let mut errors = vec![];
+    visit_prefab_refs(root_prefab, prefab_lookup, &mut cook_order, &mut errors);
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(cook_order),
+    }
+}
+
+// Depth-first walk of the prefab reference graph. Unlike prefab_cook_order, this does not stop at
+// the first problem so that callers can report everything that is wrong with a set of prefabs
+pub(crate) fn visit_prefab_refs<U: BuildHasher>(
+    root_prefab: PrefabUuid,
+    prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+    cook_order: &mut Vec<PrefabUuid>,
+    errors: &mut Vec<PrefabCookOrderError>,
+) {
+    fn visit<U: BuildHasher>(
+        prefab_id: PrefabUuid,
+        prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+        stack: &mut Vec<PrefabUuid>,
+        visited: &mut HashSet<PrefabUuid>,
+        cook_order: &mut Vec<PrefabUuid>,
+        errors: &mut Vec<PrefabCookOrderError>,
+    ) {
+        if let Some(position) = stack.iter().position(|x| *x == prefab_id) {
+            let mut cycle = stack[position..].to_vec();
+            cycle.push(prefab_id);
+            errors.push(PrefabCookOrderError::ReferenceCycle(cycle));
+            return;
+        }
+
+        if !visited.insert(prefab_id) {
+            return;
+        }
+
+        let prefab = match prefab_lookup.get(&prefab_id) {
+            Some(prefab) => prefab,
+            None => {
+                errors.push(PrefabCookOrderError::MissingPrefab(prefab_id));
+                return;
+            }
+        };
+
+        // Sort so that the cook order is deterministic
+        let mut prefab_refs: Vec<_> = prefab.prefab_meta.prefab_refs.keys().cloned().collect();
+        prefab_refs.sort();
+
+        stack.push(prefab_id);
+        for prefab_ref in prefab_refs {
+            visit(
+                prefab_ref,
+                prefab_lookup,
+                stack,
+                visited,
+                cook_order,
+                errors,
+            );
+        }
+        stack.pop();
+
+        cook_order.push(prefab_id);
+    }
+
+    let mut stack = vec![];
+    let mut visited = HashSet::new();
+    visit(
+        root_prefab,
+        prefab_lookup,
+        &mut stack,
+        &mut visited,
+        cook_order,
+        errors,
+    );
+}
+
+/// Cooks the prefab for use at runtime. Components registered as ComponentUsage::EditorOnly are
+/// left out of the cooked prefab
 pub fn cook_prefab<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
     registered_components: &HashMap<ComponentTypeId, ComponentRegistration, S>,
     registered_components_by_uuid: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
     prefab_cook_order: &[PrefabUuid],
     prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+) -> CookedPrefab {
+    cook_prefab_with_clone_impl(
+        CopyCloneImpl::new_for_runtime(registered_components),
+        registered_components_by_uuid,
+        prefab_cook_order,
+        prefab_lookup,
+    )
+}
+
+/// Cooks the prefab for editing, i.e. with PrefabBuilder. Unlike cook_prefab, editor-only
+/// components are kept
+pub fn cook_prefab_for_editor<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
+    registered_components: &HashMap<ComponentTypeId, ComponentRegistration, S>,
+    registered_components_by_uuid: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    prefab_cook_order: &[PrefabUuid],
+    prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+) -> CookedPrefab {
+    cook_prefab_with_clone_impl(
+        CopyCloneImpl::new(registered_components),
+        registered_components_by_uuid,
+        prefab_cook_order,
+        prefab_lookup,
+    )
+}
+
+fn cook_prefab_with_clone_impl<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
+    mut clone_merge_impl: CopyCloneImpl<S>,
+    registered_components_by_uuid: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    prefab_cook_order: &[PrefabUuid],
+    prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
 ) -> CookedPrefab {
     // Create a new world to hold the cooked data
     let mut world = World::default();
@@ -20,13 +144,10 @@ pub fn cook_prefab<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
     // merge all entity data from all prefabs. This data doesn't include any overrides, so order
     // doesn't matter
     for prefab in prefab_lookup.values() {
-        // Create the clone_merge impl. For prefab cooking, we will clone everything so we don't need to
-        // set up any transformations
-        let mut clone_merge_impl = CopyCloneImpl::new(registered_components);
-
-        // Clone all the entities from the prefab into the cooked world.
+        // Clone all the entities from the prefab into the cooked world. References between
+        // entities of the prefab are pointed at the cooked entities
         let result_mappings =
-            world.clone_from(&prefab.world, &legion::query::any(), &mut clone_merge_impl);
+            clone_merge_impl.clone_world(&mut world, &prefab.world, &legion::query::any());
 
         // Iterate the entities in this prefab. Determine where they are stored in the cooked
         // world and store this in entity_lookup
@@ -54,6 +175,11 @@ pub fn cook_prefab<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
                     let component_registration =
                         &registered_components_by_uuid[&component_override.component_type];
 
+                    // The component was stripped when the entity was cloned
+                    if !clone_merge_impl.is_cloned(component_registration) {
+                        continue;
+                    }
+
                     let mut deserializer =
                         ron::de::Deserializer::from_str(&component_override.data).unwrap();
 
@@ -0,0 +1,461 @@
+//! Component types defined at runtime, i.e. by scripts or from a schema file.
+//!
+//! legion identifies component types by their Rust type, so runtime-defined types can't each get
+//! their own. Instead there is a fixed number of slots, each backed by its own Rust type
+//! (`DynamicComponent<Slot0>`, `DynamicComponent<Slot1>`, ...). Registering a layout assigns it
+//! to a free slot and returns a `ComponentRegistration` that serializes, deserializes, diffs and
+//! clones the component using the layout, like the registration of any other component type.
+//!
+//! Dynamic registrations are not collected by `iter_component_registrations`. Add them to the
+//! maps of registered components alongside the static ones. Their values are accessed through
+//! the reflection functions of the registration (`read_field`, `write_field`, ...).
+
+use crate::ComponentRegistration;
+use prefab_format::ComponentTypeUuid;
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_diff::{ApplyContext, DiffContext, SerdeDiff};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+// Used to derive the UUIDs of dynamic component types from their names
+const DYNAMIC_COMPONENT_NAMESPACE: uuid::Uuid = uuid::Uuid::from_bytes([
+    0x6b, 0x1c, 0x5e, 0x0f, 0x8a, 0x2d, 0x4c, 0x43, 0x9e, 0x57, 0x31, 0x0b, 0xd4, 0x9a, 0x77, 0xe2,
+]);
+
+/// The type of a field of a dynamic component
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DynamicFieldType {
+    Bool,
+    I64,
+    U64,
+    F32,
+    F64,
+    String,
+    List(Box<DynamicFieldType>),
+}
+
+impl DynamicFieldType {
+    /// The value a field of this type has in a new component
+    pub fn default_value(&self) -> DynamicValue {
+        match self {
+            DynamicFieldType::Bool => DynamicValue::Bool(false),
+            DynamicFieldType::I64 => DynamicValue::I64(0),
+            DynamicFieldType::U64 => DynamicValue::U64(0),
+            DynamicFieldType::F32 => DynamicValue::F32(0.0),
+            DynamicFieldType::F64 => DynamicValue::F64(0.0),
+            DynamicFieldType::String => DynamicValue::String(String::new()),
+            DynamicFieldType::List(_) => DynamicValue::List(vec![]),
+        }
+    }
+}
+
+/// The value of a field of a dynamic component
+#[derive(Debug, Clone, PartialEq)]
+pub enum DynamicValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    String(String),
+    List(Vec<DynamicValue>),
+}
+
+impl Serialize for DynamicValue {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            DynamicValue::Bool(value) => serializer.serialize_bool(*value),
+            DynamicValue::I64(value) => serializer.serialize_i64(*value),
+            DynamicValue::U64(value) => serializer.serialize_u64(*value),
+            DynamicValue::F32(value) => serializer.serialize_f32(*value),
+            DynamicValue::F64(value) => serializer.serialize_f64(*value),
+            DynamicValue::String(value) => serializer.serialize_str(value),
+            DynamicValue::List(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicFieldDef {
+    pub name: String,
+    pub field_type: DynamicFieldType,
+}
+
+/// Describes a component type defined at runtime
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicComponentLayout {
+    name: String,
+    uuid: ComponentTypeUuid,
+    fields: Vec<DynamicFieldDef>,
+}
+
+impl DynamicComponentLayout {
+    /// Creates a layout without fields. The UUID is derived from the name, so a type that is
+    /// defined the same way every run keeps its UUID, and with it the data saved in prefabs
+    pub fn new(name: &str) -> Self {
+        DynamicComponentLayout {
+            name: name.to_string(),
+            uuid: *uuid::Uuid::new_v5(&DYNAMIC_COMPONENT_NAMESPACE, name.as_bytes()).as_bytes(),
+            fields: vec![],
+        }
+    }
+
+    /// Overrides the UUID derived from the name, i.e. to keep a type's data after renaming it
+    pub fn with_uuid(
+        mut self,
+        uuid: ComponentTypeUuid,
+    ) -> Self {
+        self.uuid = uuid;
+        self
+    }
+
+    pub fn with_field(
+        mut self,
+        name: &str,
+        field_type: DynamicFieldType,
+    ) -> Self {
+        self.fields.push(DynamicFieldDef {
+            name: name.to_string(),
+            field_type,
+        });
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn uuid(&self) -> &ComponentTypeUuid {
+        &self.uuid
+    }
+
+    pub fn fields(&self) -> &[DynamicFieldDef] {
+        &self.fields
+    }
+}
+
+#[derive(Debug)]
+pub enum DynamicComponentError {
+    /// All slots for dynamic component types are in use
+    TooManyTypes,
+    /// A different layout was already registered with this UUID
+    UuidAlreadyRegistered(ComponentTypeUuid),
+    /// Two fields of the layout have the same name
+    DuplicateField(String),
+}
+
+/// Registers a component type defined at runtime. Registering the same layout again returns the
+/// same registration. At most `MAX_DYNAMIC_COMPONENT_TYPES` types can be registered, and
+/// registrations last for the rest of the process.
+pub fn register_dynamic_component(
+    layout: DynamicComponentLayout
+) -> Result<ComponentRegistration, DynamicComponentError> {
+    for (i, field) in layout.fields.iter().enumerate() {
+        if layout.fields[..i]
+            .iter()
+            .any(|other| other.name == field.name)
+        {
+            return Err(DynamicComponentError::DuplicateField(field.name.clone()));
+        }
+    }
+
+    let mut slots = DYNAMIC_SLOTS.write();
+    let slot = match slots
+        .iter()
+        .position(|existing| existing.layout.uuid == layout.uuid)
+    {
+        Some(slot) if *slots[slot].layout == layout => slot,
+        Some(_) => return Err(DynamicComponentError::UuidAlreadyRegistered(layout.uuid)),
+        None => {
+            if slots.len() >= MAX_DYNAMIC_COMPONENT_TYPES {
+                return Err(DynamicComponentError::TooManyTypes);
+            }
+
+            // The registration needs a 'static name. Slots are never freed, so this is only
+            // leaked once per type
+            let type_name = &*Box::leak(layout.name.clone().into_boxed_str());
+            slots.push(DynamicSlotInfo {
+                layout: Arc::new(layout),
+                type_name,
+            });
+            slots.len() - 1
+        }
+    };
+
+    let info = &slots[slot];
+    Ok(slot_registration(slot, info.layout.uuid, info.type_name).with_display_name(info.type_name))
+}
+
+/// Returns the layout of a dynamic component type, or None if the registration isn't for one
+pub fn dynamic_component_layout(
+    registration: &ComponentRegistration
+) -> Option<Arc<DynamicComponentLayout>> {
+    DYNAMIC_SLOTS
+        .read()
+        .iter()
+        .find(|info| info.layout.uuid == *registration.uuid())
+        .map(|info| info.layout.clone())
+}
+
+struct DynamicSlotInfo {
+    layout: Arc<DynamicComponentLayout>,
+    type_name: &'static str,
+}
+
+// The types registered in each slot. Slots are assigned in order and never freed
+static DYNAMIC_SLOTS: parking_lot::RwLock<Vec<DynamicSlotInfo>> =
+    parking_lot::const_rwlock(Vec::new());
+
+fn slot_layout(slot: usize) -> Option<Arc<DynamicComponentLayout>> {
+    DYNAMIC_SLOTS
+        .read()
+        .get(slot)
+        .map(|info| info.layout.clone())
+}
+
+// Implemented by the types that select a slot
+trait DynamicSlot: Send + Sync + 'static {
+    const SLOT: usize;
+}
+
+// The component stored in legion for the dynamic component type in slot S. Holds the value of
+// each field of the layout, in order
+struct DynamicComponent<S: DynamicSlot> {
+    values: Vec<DynamicValue>,
+    phantom_data: PhantomData<S>,
+}
+
+impl<S: DynamicSlot> Clone for DynamicComponent<S> {
+    fn clone(&self) -> Self {
+        DynamicComponent {
+            values: self.values.clone(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<S: DynamicSlot> PartialEq for DynamicComponent<S> {
+    fn eq(
+        &self,
+        other: &Self,
+    ) -> bool {
+        self.values == other.values
+    }
+}
+
+impl<S: DynamicSlot> Default for DynamicComponent<S> {
+    fn default() -> Self {
+        let values = match slot_layout(S::SLOT) {
+            Some(layout) => layout
+                .fields
+                .iter()
+                .map(|field| field.field_type.default_value())
+                .collect(),
+            None => vec![],
+        };
+
+        DynamicComponent {
+            values,
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+// Serialized as a map from field name to value
+impl<S: DynamicSlot> Serialize for DynamicComponent<S> {
+    fn serialize<Ser: Serializer>(
+        &self,
+        serializer: Ser,
+    ) -> Result<Ser::Ok, Ser::Error> {
+        let layout = slot_layout(S::SLOT)
+            .ok_or_else(|| serde::ser::Error::custom("dynamic component type not registered"))?;
+
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (field, value) in layout.fields.iter().zip(&self.values) {
+            map.serialize_entry(&field.name, value)?;
+        }
+        map.end()
+    }
+}
+
+// The field types come from the layout, so this works with formats that aren't self-describing,
+// like bincode. Fields missing from the data keep their default value
+impl<'de, S: DynamicSlot> Deserialize<'de> for DynamicComponent<S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ComponentVisitor<S> {
+            layout: Arc<DynamicComponentLayout>,
+            phantom_data: PhantomData<S>,
+        }
+
+        impl<'de, S: DynamicSlot> Visitor<'de> for ComponentVisitor<S> {
+            type Value = DynamicComponent<S>;
+
+            fn expecting(
+                &self,
+                formatter: &mut fmt::Formatter,
+            ) -> fmt::Result {
+                write!(formatter, "a {} component", self.layout.name)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut values: Vec<_> = self
+                    .layout
+                    .fields
+                    .iter()
+                    .map(|field| field.field_type.default_value())
+                    .collect();
+
+                while let Some(name) = map.next_key::<String>()? {
+                    let index = self
+                        .layout
+                        .fields
+                        .iter()
+                        .position(|field| field.name == name)
+                        .ok_or_else(|| de::Error::custom(format!("unknown field {}", name)))?;
+                    values[index] =
+                        map.next_value_seed(ValueSeed(&self.layout.fields[index].field_type))?;
+                }
+
+                Ok(DynamicComponent {
+                    values,
+                    phantom_data: PhantomData,
+                })
+            }
+        }
+
+        let layout = slot_layout(S::SLOT)
+            .ok_or_else(|| de::Error::custom("dynamic component type not registered"))?;
+        deserializer.deserialize_map(ComponentVisitor {
+            layout,
+            phantom_data: PhantomData,
+        })
+    }
+}
+
+// Deserializes a value of a known field type
+struct ValueSeed<'a>(&'a DynamicFieldType);
+
+impl<'a, 'de> DeserializeSeed<'de> for ValueSeed<'a> {
+    type Value = DynamicValue;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        struct ListVisitor<'a>(&'a DynamicFieldType);
+
+        impl<'a, 'de> Visitor<'de> for ListVisitor<'a> {
+            type Value = Vec<DynamicValue>;
+
+            fn expecting(
+                &self,
+                formatter: &mut fmt::Formatter,
+            ) -> fmt::Result {
+                write!(formatter, "a list of {:?}", self.0)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut values = vec![];
+                while let Some(value) = seq.next_element_seed(ValueSeed(self.0))? {
+                    values.push(value);
+                }
+                Ok(values)
+            }
+        }
+
+        Ok(match self.0 {
+            DynamicFieldType::Bool => DynamicValue::Bool(bool::deserialize(deserializer)?),
+            DynamicFieldType::I64 => DynamicValue::I64(i64::deserialize(deserializer)?),
+            DynamicFieldType::U64 => DynamicValue::U64(u64::deserialize(deserializer)?),
+            DynamicFieldType::F32 => DynamicValue::F32(f32::deserialize(deserializer)?),
+            DynamicFieldType::F64 => DynamicValue::F64(f64::deserialize(deserializer)?),
+            DynamicFieldType::String => DynamicValue::String(String::deserialize(deserializer)?),
+            DynamicFieldType::List(element_type) => {
+                DynamicValue::List(deserializer.deserialize_seq(ListVisitor(element_type))?)
+            }
+        })
+    }
+}
+
+// Diffed as a single value, so a change to one field stores the whole component
+impl<S: DynamicSlot> SerdeDiff for DynamicComponent<S> {
+    fn diff<'a, Seq: SerializeSeq>(
+        &self,
+        ctx: &mut DiffContext<'a, Seq>,
+        other: &Self,
+    ) -> Result<bool, Seq::Error> {
+        if self != other {
+            ctx.save_value(other)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn apply<'de, A>(
+        &mut self,
+        seq: &mut A,
+        ctx: &mut ApplyContext,
+    ) -> Result<bool, <A as SeqAccess<'de>>::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        ctx.read_value(seq, self)
+    }
+}
+
+macro_rules! dynamic_slots {
+    ($($slot:ident = $index:literal,)*) => {
+        $(
+            struct $slot;
+
+            impl DynamicSlot for $slot {
+                const SLOT: usize = $index;
+            }
+        )*
+
+        fn slot_registration(
+            slot: usize,
+            uuid: ComponentTypeUuid,
+            type_name: &'static str,
+        ) -> ComponentRegistration {
+            match slot {
+                $(
+                    $index => ComponentRegistration::of_type_with_uuid::<DynamicComponent<$slot>>(
+                        uuid, type_name,
+                    ),
+                )*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+/// The number of component types that can be registered with `register_dynamic_component`
+pub const MAX_DYNAMIC_COMPONENT_TYPES: usize = 32;
+
+dynamic_slots! {
+    Slot0 = 0, Slot1 = 1, Slot2 = 2, Slot3 = 3, Slot4 = 4, Slot5 = 5, Slot6 = 6, Slot7 = 7,
+    Slot8 = 8, Slot9 = 9, Slot10 = 10, Slot11 = 11, Slot12 = 12, Slot13 = 13, Slot14 = 14,
+    Slot15 = 15, Slot16 = 16, Slot17 = 17, Slot18 = 18, Slot19 = 19, Slot20 = 20, Slot21 = 21,
+    Slot22 = 22, Slot23 = 23, Slot24 = 24, Slot25 = 25, Slot26 = 26, Slot27 = 27, Slot28 = 28,
+    Slot29 = 29, Slot30 = 30, Slot31 = 31,
+}
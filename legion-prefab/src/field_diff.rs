@@ -0,0 +1,388 @@
+use crate::{ComponentRegistration, DiffSingleResult};
+use legion::world::{Entity, World};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// One step into a serialized component
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FieldPathElement {
+    /// A named field of a struct
+    Field(String),
+    /// An element of a sequence, or a field of a tuple struct
+    Index(usize),
+    /// A key of a map, in its JSON representation
+    Key(String),
+}
+
+/// The location of a value within a serialized component, like `position[0]`. An empty path refers
+/// to the whole component.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FieldPath(pub Vec<FieldPathElement>);
+
+impl FieldPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn elements(&self) -> &[FieldPathElement] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Parses the format produced by Display, i.e. `transform.position[0]` or `tags["name"]`
+    pub fn parse(path: &str) -> Option<Self> {
+        let mut elements = vec![];
+        let mut rest = path;
+        while !rest.is_empty() {
+            if rest.starts_with('[') {
+                let end = rest.find(']')?;
+                let inner = &rest[1..end];
+                if inner.starts_with('"') {
+                    let key: String = serde_json::from_str(inner).ok()?;
+                    elements.push(FieldPathElement::Key(key));
+                } else {
+                    elements.push(FieldPathElement::Index(inner.parse().ok()?));
+                }
+                rest = &rest[end + 1..];
+            } else {
+                if rest.starts_with('.') {
+                    if elements.is_empty() {
+                        return None;
+                    }
+                    rest = &rest[1..];
+                }
+                let end = rest
+                    .find(|c| c == '.' || c == '[')
+                    .unwrap_or_else(|| rest.len());
+                if end == 0 {
+                    return None;
+                }
+                elements.push(FieldPathElement::Field(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        Some(FieldPath(elements))
+    }
+
+    /// Returns true if this path is `other` or refers to a value nested within it
+    pub fn starts_with(
+        &self,
+        other: &FieldPath,
+    ) -> bool {
+        self.0.len() >= other.0.len() && self.0[..other.0.len()] == other.0[..]
+    }
+
+    /// Finds the value this path refers to within a serialized component
+    pub fn find<'a>(
+        &self,
+        value: &'a Value,
+    ) -> Option<&'a Value> {
+        let mut value = value;
+        for element in &self.0 {
+            value = match (element, value) {
+                (FieldPathElement::Field(name), Value::Object(map)) => map.get(name)?,
+                (FieldPathElement::Key(key), Value::Object(map)) => map.get(key)?,
+                (FieldPathElement::Index(index), Value::Array(array)) => array.get(*index)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Same as `find`, but returns a mutable reference
+    pub fn find_mut<'a>(
+        &self,
+        value: &'a mut Value,
+    ) -> Option<&'a mut Value> {
+        let mut value = value;
+        for element in &self.0 {
+            value = match (element, value) {
+                (FieldPathElement::Field(name), Value::Object(map)) => map.get_mut(name)?,
+                (FieldPathElement::Key(key), Value::Object(map)) => map.get_mut(key)?,
+                (FieldPathElement::Index(index), Value::Array(array)) => array.get_mut(*index)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    fn child(
+        &self,
+        element: FieldPathElement,
+    ) -> FieldPath {
+        let mut path = self.clone();
+        path.0.push(element);
+        path
+    }
+}
+
+impl fmt::Display for FieldPath {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        for (i, element) in self.0.iter().enumerate() {
+            match element {
+                FieldPathElement::Field(name) if i == 0 => write!(f, "{}", name)?,
+                FieldPathElement::Field(name) => write!(f, ".{}", name)?,
+                FieldPathElement::Index(index) => write!(f, "[{}]", index)?,
+                FieldPathElement::Key(key) => write!(f, "[{}]", Value::String(key.clone()))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single changed value within a component. `before` is None when the value was added (i.e. a
+/// new element of a collection) and `after` is None when it was removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub path: FieldPath,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => write!(f, "{}: {} → {}", self.path, before, after),
+            (None, Some(after)) => write!(f, "{}: added {}", self.path, after),
+            (Some(before), None) => write!(f, "{}: removed {}", self.path, before),
+            (None, None) => write!(f, "{}: changed", self.path),
+        }
+    }
+}
+
+/// Applies a change to a serialized component. Values are replaced or inserted, elements added to
+/// the end of a sequence are appended and removed elements are truncated from it. Returns false if
+/// the change doesn't fit the structure of the value.
+pub fn apply_field_change(
+    value: &mut Value,
+    change: &FieldChange,
+) -> bool {
+    let (parent_path, last) = match change.path.0.split_last() {
+        Some((last, parent)) => (FieldPath(parent.to_vec()), last),
+        None => {
+            return match &change.after {
+                Some(after) => {
+                    *value = after.clone();
+                    true
+                }
+                None => false,
+            };
+        }
+    };
+
+    let parent = match parent_path.find_mut(value) {
+        Some(parent) => parent,
+        None => return false,
+    };
+
+    match (parent, last, &change.after) {
+        (Value::Object(map), FieldPathElement::Field(key), Some(after))
+        | (Value::Object(map), FieldPathElement::Key(key), Some(after)) => {
+            map.insert(key.clone(), after.clone());
+            true
+        }
+        (Value::Object(map), FieldPathElement::Field(key), None)
+        | (Value::Object(map), FieldPathElement::Key(key), None) => {
+            map.remove(key);
+            true
+        }
+        (Value::Array(array), FieldPathElement::Index(index), Some(after)) => {
+            if *index < array.len() {
+                array[*index] = after.clone();
+                true
+            } else if *index == array.len() {
+                array.push(after.clone());
+                true
+            } else {
+                false
+            }
+        }
+        (Value::Array(array), FieldPathElement::Index(index), None) => {
+            // Removals always truncate, so removing the first of several elements removes them all
+            array.truncate(*index);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Serializes a component on the given entity into a JSON value. This is a lossless, self-describing
+/// view of the component that can be inspected without knowing its type.
+pub fn component_to_value(
+    registration: &ComponentRegistration,
+    world: &World,
+    entity: Entity,
+) -> Result<Value, serde_json::Error> {
+    let mut result = None;
+    registration.serialize_single(world, entity, &mut |comp| {
+        result = Some(serde_json::to_value(comp));
+    });
+    // serialize_single always invokes the callback exactly once
+    result.unwrap()
+}
+
+/// Lists the values that differ between a component on two entities. The component must exist on
+/// both entities.
+///
+/// The changes are found by running serde_diff (via `ComponentRegistration::diff_single`) and
+/// reading back the paths it produced, so they have the same granularity as the diffs stored in
+/// prefab overrides and transactions.
+pub fn diff_component_fields(
+    registration: &ComponentRegistration,
+    src_world: &World,
+    src_entity: Entity,
+    dst_world: &World,
+    dst_entity: Entity,
+) -> Result<Vec<FieldChange>, serde_json::Error> {
+    let mut data = vec![];
+    let mut json_ser = serde_json::Serializer::new(&mut data);
+    let mut json_ser_erased = erased_serde::Serializer::erase(&mut json_ser);
+    let result = registration.diff_single(
+        &mut json_ser_erased,
+        src_world,
+        Some(src_entity),
+        dst_world,
+        Some(dst_entity),
+    );
+
+    if result != DiffSingleResult::Change {
+        return Ok(vec![]);
+    }
+
+    let commands: Value = serde_json::from_slice(&data)?;
+    let before = component_to_value(registration, src_world, src_entity)?;
+    Ok(field_changes_from_diff_commands(&commands, &before))
+}
+
+// Walks the commands of a serde_diff diff that was serialized into JSON. Each command is an
+// externally tagged enum, i.e. {"Enter":{"Field":"position"}}, {"Value":1.0} or "Exit". Values of
+// fields that were entered are looked up in `before` to provide the old value of each change.
+fn field_changes_from_diff_commands(
+    commands: &Value,
+    before: &Value,
+) -> Vec<FieldChange> {
+    let commands = match commands {
+        Value::Array(commands) => commands,
+        _ => return vec![],
+    };
+
+    // Every entered path, and whether it was newly added (in which case it has no old value)
+    let mut stack: Vec<(FieldPath, bool)> = vec![(FieldPath::new(), false)];
+    // Number of elements appended to each collection so far, used to assign indices to them
+    let mut appended: HashMap<FieldPath, usize> = HashMap::new();
+    let mut changes = vec![];
+
+    for command in commands {
+        let (current_path, current_added) = stack.last().cloned().unwrap_or_default();
+        let (name, arg) = match command {
+            Value::String(name) => (name.as_str(), &Value::Null),
+            Value::Object(map) if map.len() == 1 => {
+                let (name, arg) = map.iter().next().unwrap();
+                (name.as_str(), arg)
+            }
+            _ => continue,
+        };
+
+        match name {
+            "Enter" => {
+                let (element, added) = match arg {
+                    Value::String(s) if s == "AddToCollection" => {
+                        let existing = current_path
+                            .find(before)
+                            .and_then(|v| v.as_array())
+                            .map(|array| array.len())
+                            .unwrap_or(0);
+                        let count = appended.entry(current_path.clone()).or_insert(0);
+                        let index = existing + *count;
+                        *count += 1;
+                        (FieldPathElement::Index(index), true)
+                    }
+                    Value::Object(map) => match map.iter().next() {
+                        Some((kind, Value::String(field))) if kind == "Field" => {
+                            (FieldPathElement::Field(field.clone()), current_added)
+                        }
+                        Some((kind, index))
+                            if kind == "FieldIndex" || kind == "CollectionIndex" =>
+                        {
+                            (
+                                FieldPathElement::Index(index.as_u64().unwrap_or(0) as usize),
+                                current_added,
+                            )
+                        }
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                stack.push((current_path.child(element), added));
+            }
+            "EnterKey" | "AddKey" => {
+                let element = FieldPathElement::Key(map_key_string(arg));
+                stack.push((
+                    current_path.child(element),
+                    current_added || name == "AddKey",
+                ));
+            }
+            "Exit" => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+            }
+            "Value" => {
+                let old_value = if current_added {
+                    None
+                } else {
+                    current_path.find(before).cloned()
+                };
+                changes.push(FieldChange {
+                    path: current_path,
+                    before: old_value,
+                    after: Some(arg.clone()),
+                });
+            }
+            "Remove" => {
+                // Remove(n) truncates the collection by n elements
+                let removed = arg.as_u64().unwrap_or(0) as usize;
+                if let Some(array) = current_path.find(before).and_then(|v| v.as_array()) {
+                    let start = array.len().saturating_sub(removed);
+                    for (index, value) in array.iter().enumerate().skip(start) {
+                        changes.push(FieldChange {
+                            path: current_path.child(FieldPathElement::Index(index)),
+                            before: Some(value.clone()),
+                            after: None,
+                        });
+                    }
+                }
+            }
+            "RemoveKey" => {
+                let path = current_path.child(FieldPathElement::Key(map_key_string(arg)));
+                changes.push(FieldChange {
+                    before: path.find(before).cloned(),
+                    path,
+                    after: None,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    changes
+}
+
+// serde_json writes non-string map keys as strings, so do the same to find them in serialized maps
+pub(crate) fn map_key_string(key: &Value) -> String {
+    match key {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
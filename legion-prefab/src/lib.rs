@@ -1,10 +1,16 @@
 #[doc(hidden)]
 pub use inventory;
 
+// Registers a component type, see the legion-prefab-derive crate
+pub use legion_prefab_derive::PrefabComponent;
+
 use prefab_format as format;
 
 mod registration;
-pub use registration::{ComponentRegistration, iter_component_registrations, DiffSingleResult};
+pub use registration::{
+    ComponentRegistration, ComponentUsage, iter_component_registrations, DiffSingleResult,
+    diff_component_types,
+};
 
 mod prefab_uncooked;
 pub use prefab_uncooked::{
@@ -23,16 +29,80 @@ mod world_serde;
 
 mod cooking;
 pub use cooking::cook_prefab;
+pub use cooking::cook_prefab_for_editor;
+pub use cooking::prefab_cook_order;
+pub use cooking::PrefabCookOrderError;
+
+// Checks uncooked prefabs for problems without cooking them
+mod validation;
+pub use validation::validate_prefab;
+pub use validation::PrefabDiagnostic;
+
+// Field-level inspection of component values
+mod field_diff;
+pub use field_diff::FieldPath;
+pub use field_diff::FieldPathElement;
+pub use field_diff::FieldChange;
+pub use field_diff::component_to_value;
+pub use field_diff::diff_component_fields;
+pub use field_diff::apply_field_change;
+
+// Lists, reads and writes the fields of registered components without knowing their types
+mod reflection;
+pub use reflection::FieldInfo;
+pub use reflection::FieldKind;
+pub use reflection::ReflectError;
+
+// Component types defined at runtime, i.e. by scripts
+mod dynamic_component;
+pub use dynamic_component::DynamicComponentError;
+pub use dynamic_component::DynamicComponentLayout;
+pub use dynamic_component::DynamicFieldDef;
+pub use dynamic_component::DynamicFieldType;
+pub use dynamic_component::DynamicValue;
+pub use dynamic_component::MAX_DYNAMIC_COMPONENT_TYPES;
+pub use dynamic_component::dynamic_component_layout;
+pub use dynamic_component::register_dynamic_component;
+
+// JSON Schema export of the prefab format and the data of registered components
+mod schema;
+pub use schema::component_json_schema;
+pub use schema::prefab_json_schema;
+
+// Remaps references to entities stored inside components after cloning
+mod map_entities;
+pub use map_entities::EntityMap;
+pub use map_entities::MapEntities;
+pub use map_entities::map_entity_refs;
+pub use map_entities::map_entity_refs_in;
 
 // Implements a safer, easier to use layer on top of legion's clone_from and clone_from_single by
 // using the type registry in legion-prefab
 mod clone_merge;
+pub use clone_merge::CloneMergeImpl;
 pub use clone_merge::CopyCloneImpl;
+pub use clone_merge::CopyCloneError;
+pub use clone_merge::ComponentTypeFilter;
+pub use clone_merge::UnregisteredComponentPolicy;
 pub use clone_merge::SpawnCloneImpl;
 pub use clone_merge::SpawnCloneImplHandlerSet;
+pub use clone_merge::SpawnContext;
+pub use clone_merge::SpawnCloneError;
+pub use clone_merge::SpawnCloneReport;
 pub use clone_merge::SpawnFrom;
 pub use clone_merge::SpawnInto;
 
+// Clones a selection of entities from a world in batches
+mod clone_subset;
+pub use clone_subset::clone_entities_by_uuid;
+pub use clone_subset::clone_filtered;
+pub use clone_subset::clone_subtree;
+
+// Component types and helpers for the unit tests of this crate and the crates that depend on it
+#[cfg(any(test, feature = "test-util"))]
+#[doc(hidden)]
+pub mod test_util;
+
 // A utility iterator that simplifies accessing values from SpawnFrom
 mod option_iter;
 pub use option_iter::OptionIter;
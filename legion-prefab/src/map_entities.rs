@@ -0,0 +1,120 @@
+use crate::ComponentRegistration;
+use legion::storage::ComponentTypeId;
+use legion::world::EntityHasher;
+use legion::*;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// Maps entities in a world that was cloned from to the entities that were created for them
+pub struct EntityMap<'a> {
+    map: &'a HashMap<Entity, Entity, EntityHasher>,
+}
+
+impl<'a> EntityMap<'a> {
+    pub fn new(map: &'a HashMap<Entity, Entity, EntityHasher>) -> Self {
+        EntityMap { map }
+    }
+
+    /// Returns the entity that was created for the given one. Entities that weren't cloned are
+    /// returned unchanged, so references to entities outside the cloned set are left alone
+    pub fn get(
+        &self,
+        entity: Entity,
+    ) -> Entity {
+        self.map.get(&entity).cloned().unwrap_or(entity)
+    }
+}
+
+/// Implemented by components that hold references to other entities, i.e. a `Target(Entity)` or
+/// a list of children. When entities are cloned, these references still point at the entities in
+/// the source world until they are remapped. Clones done through `CloneMergeImpl` remap them, other
+/// mergers need to call `map_entity_refs`.
+///
+/// Components must be registered with `ComponentRegistration::of_map_entities` (or
+/// `register_component_type!(T, MapEntities)`) for this to be called.
+pub trait MapEntities {
+    fn map_entities(
+        &mut self,
+        entity_map: &EntityMap,
+    );
+}
+
+impl MapEntities for Entity {
+    fn map_entities(
+        &mut self,
+        entity_map: &EntityMap,
+    ) {
+        *self = entity_map.get(*self);
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(
+        &mut self,
+        entity_map: &EntityMap,
+    ) {
+        if let Some(value) = self {
+            value.map_entities(entity_map);
+        }
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(
+        &mut self,
+        entity_map: &EntityMap,
+    ) {
+        for value in self {
+            value.map_entities(entity_map);
+        }
+    }
+}
+
+/// Rewrites the entity references inside the components of cloned entities. `result_mappings` is
+/// the mapping returned by `World::clone_from`. Must be called after the whole set of entities has
+/// been cloned, since a component may refer to an entity that is cloned after it. Clones done
+/// through `CloneMergeImpl` call this themselves; it is only needed with other mergers.
+pub fn map_entity_refs<S: BuildHasher>(
+    world: &mut World,
+    result_mappings: &HashMap<Entity, Entity, EntityHasher>,
+    registered_components: &HashMap<ComponentTypeId, ComponentRegistration, S>,
+) {
+    map_entity_refs_in(
+        world,
+        result_mappings.values().cloned(),
+        result_mappings,
+        registered_components,
+    );
+}
+
+/// Rewrites the entity references inside the components of the given entities with `entity_map`.
+/// Unlike `map_entity_refs`, the entities don't need to be the ones the map points at, i.e. to
+/// point the references of entities that were cloned separately at entities in another world.
+pub fn map_entity_refs_in<S: BuildHasher>(
+    world: &mut World,
+    entities: impl IntoIterator<Item = Entity>,
+    entity_map: &HashMap<Entity, Entity, EntityHasher>,
+    registered_components: &HashMap<ComponentTypeId, ComponentRegistration, S>,
+) {
+    // Nothing to do for worlds without any components that refer to entities
+    if !registered_components
+        .values()
+        .any(|registration| registration.maps_entities())
+    {
+        return;
+    }
+
+    let entity_map = EntityMap::new(entity_map);
+    for entity in entities {
+        let component_types: Vec<_> = match world.entry_ref(entity) {
+            Ok(entry) => entry.archetype().layout().component_types().to_vec(),
+            Err(_) => continue,
+        };
+
+        for component_type in component_types {
+            if let Some(registration) = registered_components.get(&component_type) {
+                registration.map_entities(world, entity, &entity_map);
+            }
+        }
+    }
+}
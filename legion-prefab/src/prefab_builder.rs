@@ -1,9 +1,9 @@
 use legion::*;
 use prefab_format::{EntityUuid, ComponentTypeUuid, PrefabUuid};
 
-use std::{collections::HashMap, io::Cursor};
+use std::collections::HashMap;
 use crate::{ComponentRegistration, DiffSingleResult, ComponentOverride, PrefabMeta, PrefabRef};
-use crate::{CookedPrefab, CopyCloneImpl, Prefab};
+use crate::{CloneMergeImpl, CookedPrefab, CopyCloneImpl, Prefab};
 use fnv::FnvHashMap;
 use std::hash::BuildHasher;
 
@@ -61,11 +61,11 @@ impl PrefabBuilder {
     ) -> Self {
         let mut before_world = World::default();
         let before_result_mappings =
-            before_world.clone_from(&prefab.world, &legion::query::any(), &mut clone_impl);
+            clone_impl.clone_world(&mut before_world, &prefab.world, &legion::query::any());
 
         let mut after_world = World::default();
         let after_result_mappings =
-            after_world.clone_from(&prefab.world, &legion::query::any(), &mut clone_impl);
+            clone_impl.clone_world(&mut after_world, &prefab.world, &legion::query::any());
 
         let mut uuid_to_entities = FnvHashMap::default();
         for (uuid, entity) in &prefab.entities {
@@ -117,25 +117,51 @@ impl PrefabBuilder {
 
         // Find the entities that have been added (i.e. are in the after_world but not the
         // before_world) and copy them into new_prefab_world
-        for after_entity in all.iter(&self.after_world) {
-            if !self.before_world.contains(*after_entity) {
-                let new_entity = new_prefab_world.clone_from_single(
-                    &self.after_world,
-                    *after_entity,
-                    &mut clone_impl,
-                );
-
-                new_prefab_entities.insert(*uuid::Uuid::new_v4().as_bytes(), new_entity);
-            }
+        let added_entities: Vec<_> = all
+            .iter(&self.after_world)
+            .filter(|after_entity| !self.before_world.contains(**after_entity))
+            .cloned()
+            .collect();
+        let result_mappings =
+            clone_impl.clone_entities(&mut new_prefab_world, &self.after_world, added_entities);
+        for new_entity in result_mappings.values() {
+            new_prefab_entities.insert(*uuid::Uuid::new_v4().as_bytes(), *new_entity);
         }
 
+        // Only the component types on an entity are diffed, so look registrations up by type id
+        let registrations_by_type_id: HashMap<_, _> = registered_components
+            .iter()
+            .map(|(component_type, registration)| {
+                (
+                    registration.component_type_id(),
+                    (*component_type, registration),
+                )
+            })
+            .collect();
+
+        // Reused for every component so that unchanged components don't allocate
+        let mut buf = Vec::new();
+
         let mut entity_overrides = HashMap::new();
         for (entity_uuid, entity_info) in &self.uuid_to_entities {
             let mut component_overrides = vec![];
 
-            for (component_type, registration) in registered_components {
-                let mut buf = Cursor::new(Vec::new());
-                let mut ron_ser = ron::ser::Serializer::new(buf.get_mut(), None, true).unwrap();
+            let component_type_ids = crate::diff_component_types(
+                &self.before_world,
+                Some(entity_info.before_entity()),
+                &self.after_world,
+                Some(entity_info.after_entity()),
+            );
+
+            for component_type_id in component_type_ids {
+                let (component_type, registration) =
+                    match registrations_by_type_id.get(&component_type_id) {
+                        Some(registration) => *registration,
+                        None => continue,
+                    };
+
+                buf.clear();
+                let mut ron_ser = ron::ser::Serializer::new(&mut buf, None, true).unwrap();
                 let mut erased = erased_serde::Serializer::erase(&mut ron_ser);
 
                 let result = registration.diff_single(
@@ -154,8 +180,8 @@ impl PrefabBuilder {
                         // Store the change
 
                         component_overrides.push(ComponentOverride {
-                            component_type: *component_type,
-                            data: String::from_utf8(buf.into_inner()).expect("Ron should be utf-8"),
+                            component_type,
+                            data: String::from_utf8(buf.clone()).expect("Ron should be utf-8"),
                         })
                     }
                     DiffSingleResult::Add => {
@@ -405,8 +405,12 @@ impl<'a, 'b, T: BuildHasher> PrefabFormatSerializer<'a, 'b, T> {
     }
 }
 impl<T: BuildHasher> StorageSerializer for PrefabFormatSerializer<'_, '_, T> {
+    // Everything returned to the prefab format serializer is sorted so that serializing the same
+    // prefab always produces the same output
     fn entities(&self) -> Vec<EntityUuid> {
-        self.prefab.prefab_meta.entities.keys().cloned().collect()
+        let mut entities: Vec<_> = self.prefab.prefab_meta.entities.keys().cloned().collect();
+        entities.sort();
+        entities
     }
 
     fn component_types(
@@ -420,13 +424,16 @@ impl<T: BuildHasher> StorageSerializer for PrefabFormatSerializer<'_, '_, T> {
             .entry_ref(entity)
             .expect("entity not in World when serializing prefab");
 
-        e.archetype()
+        let mut component_types: Vec<_> = e
+            .archetype()
             .layout()
             .component_types()
             .iter()
             .filter_map(|type_id| self.type_id_to_uuid.get(type_id).cloned())
             .filter(|type_id| self.context.registered_components.contains_key(type_id))
-            .collect()
+            .collect();
+        component_types.sort();
+        component_types
     }
     fn serialize_entity_component<S: Serializer>(
         &self,
@@ -447,28 +454,33 @@ impl<T: BuildHasher> StorageSerializer for PrefabFormatSerializer<'_, '_, T> {
         result.unwrap()
     }
     fn prefab_refs(&self) -> Vec<PrefabUuid> {
-        self.prefab
+        let mut prefab_refs: Vec<_> = self
+            .prefab
             .prefab_meta
             .prefab_refs
             .keys()
             .cloned()
-            .collect()
+            .collect();
+        prefab_refs.sort();
+        prefab_refs
     }
     fn prefab_ref_overrides(
         &self,
         uuid: &PrefabUuid,
     ) -> Vec<(EntityUuid, Vec<ComponentTypeUuid>)> {
         let prefab_ref = &self.prefab.prefab_meta.prefab_refs[uuid];
-        prefab_ref
+        let mut overrides: Vec<(EntityUuid, Vec<ComponentTypeUuid>)> = prefab_ref
             .overrides
             .iter()
             .map(|(entity_uuid, comps)| {
-                (
-                    *entity_uuid,
-                    comps.iter().map(|comp| comp.component_type).collect(),
-                )
+                let mut component_types: Vec<_> =
+                    comps.iter().map(|comp| comp.component_type).collect();
+                component_types.sort();
+                (*entity_uuid, component_types)
             })
-            .collect()
+            .collect();
+        overrides.sort_by_key(|(entity_uuid, _)| *entity_uuid);
+        overrides
     }
     fn serialize_component_override_diff<S: Serializer>(
         &self,
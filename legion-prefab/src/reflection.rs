@@ -0,0 +1,816 @@
+use crate::field_diff::map_key_string;
+use crate::{component_to_value, ComponentRegistration, DiffSingleResult, FieldPath, FieldPathElement};
+use legion::world::{Entity, EntityStore, World};
+use serde::ser::{self, Serialize};
+use serde_json::Value;
+
+/// The shape of a field's value in the serde data model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Null,
+    Bool,
+    Number,
+    String,
+    /// A sequence, tuple or tuple struct. Its elements are listed as separate fields
+    Sequence,
+    /// A struct, map or enum variant holding data. Its fields are listed as separate fields
+    Object,
+}
+
+/// A field of a component, as listed by `ComponentRegistration::fields`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInfo {
+    pub path: FieldPath,
+    pub kind: FieldKind,
+    /// The name serde gives the field's type: the Rust name of primitives (`f32`, `u8`, `str`),
+    /// structs and enums, `Option<T>` for options holding a value and `Enum::Variant` for the
+    /// contents of an enum variant. Generic parameters other than those of `Option` aren't known.
+    /// None for sequences, tuples and maps
+    pub type_name: Option<String>,
+}
+
+impl FieldInfo {
+    /// The last element of the path, i.e. `x` for `position.x` or `0` for `position[0]`
+    pub fn name(&self) -> String {
+        match self.path.elements().last() {
+            Some(FieldPathElement::Field(name)) | Some(FieldPathElement::Key(name)) => name.clone(),
+            Some(FieldPathElement::Index(index)) => index.to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReflectError {
+    /// The entity doesn't exist or doesn't have the component
+    ComponentNotFound,
+    /// The component has no value at this path
+    FieldNotFound(FieldPath),
+    /// The component couldn't be converted to its JSON representation
+    Json(serde_json::Error),
+    /// The component couldn't be read back after a field was written, i.e. because the written
+    /// value has the wrong type
+    InvalidValue(erased_serde::Error),
+}
+
+impl ComponentRegistration {
+    /// Lists every field of the component type, depth first, as found in a default instance.
+    /// Fields of nested structs and elements of sequences are listed after the field that contains
+    /// them. Sequences that are empty by default, options that are None by default and variants
+    /// of enums other than the default one have no fields listed, see `entity_fields`
+    pub fn fields(&self) -> Result<Vec<FieldInfo>, ReflectError> {
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        self.add_default_to_entity(&mut world, entity);
+        self.entity_fields(&world, entity)
+    }
+
+    /// Lists every field of the component on the given entity, the same way as `fields`. Since the
+    /// fields are found in the serialized component, the list can differ between entities, i.e.
+    /// for sequences of different lengths
+    pub fn entity_fields(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> Result<Vec<FieldInfo>, ReflectError> {
+        if !self.has_component(world, entity) {
+            return Err(ReflectError::ComponentNotFound);
+        }
+
+        let mut result = None;
+        self.serialize_single(world, entity, &mut |comp| {
+            result = Some(comp.serialize(FieldNodeSerializer));
+        });
+        // serialize_single always invokes the callback exactly once
+        let node = result.unwrap().map_err(ReflectError::Json)?;
+
+        let mut fields = vec![];
+        collect_fields(&node, &mut FieldPath::new(), &mut fields);
+        Ok(fields)
+    }
+
+    /// Reads a single field of the component on the given entity. An empty path returns the whole
+    /// component. The value is read from the component's JSON representation, so non-finite
+    /// floats read as null
+    pub fn read_field(
+        &self,
+        world: &World,
+        entity: Entity,
+        path: &FieldPath,
+    ) -> Result<Value, ReflectError> {
+        let value = self.component_value(world, entity)?;
+        path.find(&value)
+            .cloned()
+            .ok_or_else(|| ReflectError::FieldNotFound(path.clone()))
+    }
+
+    /// Replaces a single field of the component on the given entity. The component is left
+    /// unchanged if the new value doesn't fit the field.
+    ///
+    /// The whole component is converted to its JSON representation, changed and read back, which
+    /// is lossy: non-finite floats become null, so writing any field of a component that holds one
+    /// fails with InvalidValue, and components with maps whose keys aren't strings, numbers or
+    /// booleans fail with Json. Such components have to be changed through their Rust type
+    pub fn write_field(
+        &self,
+        world: &mut World,
+        entity: Entity,
+        path: &FieldPath,
+        field_value: Value,
+    ) -> Result<(), ReflectError> {
+        let mut value = self.component_value(world, entity)?;
+        match path.find_mut(&mut value) {
+            Some(field) => *field = field_value,
+            None => return Err(ReflectError::FieldNotFound(path.clone())),
+        }
+
+        self.try_add_to_entity(&mut erased_serde::Deserializer::erase(value), world, entity)
+            .map_err(ReflectError::InvalidValue)
+    }
+
+    /// Writes a diff that changes a single field of the component on the given entity to `ser`,
+    /// in the same format as `diff_single`. This is the diff a property inspector would record in
+    /// a transaction or prefab override. The world is not modified. Returns NoChange if the field
+    /// already has this value
+    pub fn diff_field(
+        &self,
+        ser: &mut dyn erased_serde::Serializer,
+        world: &World,
+        entity: Entity,
+        path: &FieldPath,
+        field_value: Value,
+    ) -> Result<DiffSingleResult, ReflectError> {
+        let mut scratch_world = World::default();
+        let scratch_entity = scratch_world.extend(vec![()])[0];
+        if !self.copy_to_entity(world, entity, &mut scratch_world, scratch_entity) {
+            return Err(ReflectError::ComponentNotFound);
+        }
+
+        self.write_field(&mut scratch_world, scratch_entity, path, field_value)?;
+        Ok(self.diff_single(
+            ser,
+            world,
+            Some(entity),
+            &scratch_world,
+            Some(scratch_entity),
+        ))
+    }
+
+    fn component_value(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> Result<Value, ReflectError> {
+        if !self.has_component(world, entity) {
+            return Err(ReflectError::ComponentNotFound);
+        }
+
+        component_to_value(self, world, entity).map_err(ReflectError::Json)
+    }
+
+    fn has_component(
+        &self,
+        world: &World,
+        entity: Entity,
+    ) -> bool {
+        world.entry_ref(entity).ok().map_or(false, |entry| {
+            entry
+                .archetype()
+                .layout()
+                .component_types()
+                .contains(&self.component_type_id())
+        })
+    }
+}
+
+// A serialized value with the type names serde reported for it. The structure follows the JSON
+// representation used by component_to_value, so that the paths of its fields can be used with
+// read_field and write_field
+struct FieldNode {
+    kind: FieldKind,
+    type_name: Option<String>,
+    children: Vec<(FieldPathElement, FieldNode)>,
+}
+
+impl FieldNode {
+    fn leaf(
+        kind: FieldKind,
+        type_name: &str,
+    ) -> Self {
+        FieldNode {
+            kind,
+            type_name: Some(type_name.to_string()),
+            children: vec![],
+        }
+    }
+
+    // Enum variants holding data are objects with the variant name as their only key
+    fn variant(
+        name: &'static str,
+        variant: &'static str,
+        contents: FieldNode,
+    ) -> Self {
+        FieldNode {
+            kind: FieldKind::Object,
+            type_name: Some(name.to_string()),
+            children: vec![(FieldPathElement::Field(variant.to_string()), contents)],
+        }
+    }
+}
+
+fn collect_fields(
+    node: &FieldNode,
+    path: &mut FieldPath,
+    fields: &mut Vec<FieldInfo>,
+) {
+    for (element, child) in &node.children {
+        path.0.push(element.clone());
+        fields.push(FieldInfo {
+            path: path.clone(),
+            kind: child.kind,
+            type_name: child.type_name.clone(),
+        });
+        collect_fields(child, path, fields);
+        path.0.pop();
+    }
+}
+
+struct FieldNodeSerializer;
+
+// Collects the elements of sequences, tuples, maps, structs and enum variants
+struct FieldNodeCompound {
+    node: FieldNode,
+    // Set for enum variants, which are wrapped in an object after all fields are collected
+    variant: Option<(&'static str, &'static str)>,
+    next_key: Option<String>,
+}
+
+impl FieldNodeCompound {
+    fn new(
+        kind: FieldKind,
+        type_name: Option<String>,
+        variant: Option<(&'static str, &'static str)>,
+    ) -> Self {
+        FieldNodeCompound {
+            node: FieldNode {
+                kind,
+                type_name,
+                children: vec![],
+            },
+            variant,
+            next_key: None,
+        }
+    }
+
+    fn push_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let element = FieldPathElement::Index(self.node.children.len());
+        self.push(element, value)
+    }
+
+    fn push<T: ?Sized + Serialize>(
+        &mut self,
+        element: FieldPathElement,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let child = value.serialize(FieldNodeSerializer)?;
+        self.node.children.push((element, child));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<FieldNode, serde_json::Error> {
+        Ok(match self.variant {
+            Some((name, variant)) => FieldNode::variant(name, variant, self.node),
+            None => self.node,
+        })
+    }
+}
+
+impl ser::Serializer for FieldNodeSerializer {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+    type SerializeSeq = FieldNodeCompound;
+    type SerializeTuple = FieldNodeCompound;
+    type SerializeTupleStruct = FieldNodeCompound;
+    type SerializeTupleVariant = FieldNodeCompound;
+    type SerializeMap = FieldNodeCompound;
+    type SerializeStruct = FieldNodeCompound;
+    type SerializeStructVariant = FieldNodeCompound;
+
+    fn serialize_bool(
+        self,
+        _v: bool,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Bool, "bool"))
+    }
+
+    fn serialize_i8(
+        self,
+        _v: i8,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "i8"))
+    }
+
+    fn serialize_i16(
+        self,
+        _v: i16,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "i16"))
+    }
+
+    fn serialize_i32(
+        self,
+        _v: i32,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "i32"))
+    }
+
+    fn serialize_i64(
+        self,
+        _v: i64,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "i64"))
+    }
+
+    fn serialize_i128(
+        self,
+        _v: i128,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "i128"))
+    }
+
+    fn serialize_u8(
+        self,
+        _v: u8,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "u8"))
+    }
+
+    fn serialize_u16(
+        self,
+        _v: u16,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "u16"))
+    }
+
+    fn serialize_u32(
+        self,
+        _v: u32,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "u32"))
+    }
+
+    fn serialize_u64(
+        self,
+        _v: u64,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "u64"))
+    }
+
+    fn serialize_u128(
+        self,
+        _v: u128,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "u128"))
+    }
+
+    fn serialize_f32(
+        self,
+        _v: f32,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "f32"))
+    }
+
+    fn serialize_f64(
+        self,
+        _v: f64,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Number, "f64"))
+    }
+
+    fn serialize_char(
+        self,
+        _v: char,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::String, "char"))
+    }
+
+    fn serialize_str(
+        self,
+        _v: &str,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::String, "str"))
+    }
+
+    // Bytes are represented as a sequence of numbers
+    fn serialize_bytes(
+        self,
+        v: &[u8],
+    ) -> Result<FieldNode, Self::Error> {
+        let mut compound =
+            FieldNodeCompound::new(FieldKind::Sequence, Some("bytes".to_string()), None);
+        for byte in v {
+            compound.push_element(byte)?;
+        }
+        compound.finish()
+    }
+
+    fn serialize_none(self) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Null, "Option"))
+    }
+
+    // Options holding a value are represented by the value itself
+    fn serialize_some<T: ?Sized + Serialize>(
+        self,
+        value: &T,
+    ) -> Result<FieldNode, Self::Error> {
+        let mut node = value.serialize(self)?;
+        node.type_name = Some(format!(
+            "Option<{}>",
+            node.type_name.as_ref().map_or("_", String::as_str)
+        ));
+        Ok(node)
+    }
+
+    fn serialize_unit(self) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Null, "()"))
+    }
+
+    fn serialize_unit_struct(
+        self,
+        name: &'static str,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::Null, name))
+    }
+
+    // Unit variants are represented by the variant name
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<FieldNode, Self::Error> {
+        Ok(FieldNode::leaf(FieldKind::String, name))
+    }
+
+    // Newtype structs are represented by the value they wrap
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<FieldNode, Self::Error> {
+        let mut node = value.serialize(self)?;
+        node.type_name = Some(name.to_string());
+        Ok(node)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<FieldNode, Self::Error> {
+        let contents = value.serialize(self)?;
+        Ok(FieldNode::variant(name, variant, contents))
+    }
+
+    fn serialize_seq(
+        self,
+        _len: Option<usize>,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(FieldKind::Sequence, None, None))
+    }
+
+    fn serialize_tuple(
+        self,
+        _len: usize,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(FieldKind::Sequence, None, None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(
+            FieldKind::Sequence,
+            Some(name.to_string()),
+            None,
+        ))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(
+            FieldKind::Sequence,
+            Some(format!("{}::{}", name, variant)),
+            Some((name, variant)),
+        ))
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(FieldKind::Object, None, None))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(
+            FieldKind::Object,
+            Some(name.to_string()),
+            None,
+        ))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<FieldNodeCompound, Self::Error> {
+        Ok(FieldNodeCompound::new(
+            FieldKind::Object,
+            Some(format!("{}::{}", name, variant)),
+            Some((name, variant)),
+        ))
+    }
+}
+
+impl ser::SerializeSeq for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+// Keys are named the way they appear in the JSON representation
+impl ser::SerializeMap for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(
+        &mut self,
+        key: &T,
+    ) -> Result<(), Self::Error> {
+        self.next_key = Some(map_key_string(&serde_json::to_value(key)?));
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| ser::Error::custom("map value serialized without a key"))?;
+        self.push(FieldPathElement::Key(key), value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(FieldPathElement::Field(key.to_string()), value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for FieldNodeCompound {
+    type Ok = FieldNode;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.push(FieldPathElement::Field(key.to_string()), value)
+    }
+
+    fn end(self) -> Result<FieldNode, Self::Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_diff::SerdeDiff;
+    use type_uuid::TypeUuid;
+
+    #[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+    struct Color {
+        r: u8,
+        g: u8,
+        b: u8,
+    }
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "3c1a9d52-7e04-4b8f-96a2-d5e07f1b4c38"]
+    struct Light {
+        intensity: f32,
+        #[serde_diff(opaque)]
+        color: Color,
+        range: Option<u16>,
+        tags: Vec<String>,
+    }
+
+    fn field(
+        path: &str,
+        kind: FieldKind,
+        type_name: Option<&str>,
+    ) -> FieldInfo {
+        FieldInfo {
+            path: FieldPath::parse(path).unwrap(),
+            kind,
+            type_name: type_name.map(str::to_string),
+        }
+    }
+
+    fn light_of(
+        world: &World,
+        entity: Entity,
+    ) -> Light {
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Light>()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn lists_fields_of_a_default_instance_with_type_names() {
+        let registration = ComponentRegistration::of::<Light>();
+
+        assert_eq!(
+            registration.fields().unwrap(),
+            vec![
+                field("intensity", FieldKind::Number, Some("f32")),
+                field("color", FieldKind::Object, Some("Color")),
+                field("color.r", FieldKind::Number, Some("u8")),
+                field("color.g", FieldKind::Number, Some("u8")),
+                field("color.b", FieldKind::Number, Some("u8")),
+                field("range", FieldKind::Null, Some("Option")),
+                field("tags", FieldKind::Sequence, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_fields_of_the_component_on_an_entity() {
+        let registration = ComponentRegistration::of::<Light>();
+        let mut world = World::default();
+        let light = Light {
+            range: Some(5),
+            tags: vec!["lamp".to_string()],
+            ..Light::default()
+        };
+        let entity = world.extend(vec![(light,)])[0];
+
+        let fields = registration.entity_fields(&world, entity).unwrap();
+        assert_eq!(
+            fields[5..],
+            [
+                field("range", FieldKind::Number, Some("Option<u16>")),
+                field("tags", FieldKind::Sequence, None),
+                field("tags[0]", FieldKind::String, Some("str")),
+            ]
+        );
+
+        let empty_entity = world.extend(vec![()])[0];
+        match registration.entity_fields(&world, empty_entity) {
+            Err(ReflectError::ComponentNotFound) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn writes_fields_and_rejects_values_that_do_not_fit() {
+        let registration = ComponentRegistration::of::<Light>();
+        let mut world = World::default();
+        let entity = world.extend(vec![(Light::default(),)])[0];
+        let path = FieldPath::parse("color.g").unwrap();
+
+        registration
+            .write_field(&mut world, entity, &path, Value::from(200))
+            .unwrap();
+        assert_eq!(
+            registration.read_field(&world, entity, &path).unwrap(),
+            Value::from(200)
+        );
+
+        let result = registration.write_field(&mut world, entity, &path, Value::from("bright"));
+        match result {
+            Err(ReflectError::InvalidValue(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(light_of(&world, entity).color.g, 200);
+    }
+
+    #[test]
+    fn write_field_fails_for_components_holding_non_finite_floats() {
+        let registration = ComponentRegistration::of::<Light>();
+        let mut world = World::default();
+        let light = Light {
+            intensity: std::f32::INFINITY,
+            ..Light::default()
+        };
+        let entity = world.extend(vec![(light.clone(),)])[0];
+
+        let path = FieldPath::parse("color.r").unwrap();
+        let result = registration.write_field(&mut world, entity, &path, Value::from(10));
+        match result {
+            Err(ReflectError::InvalidValue(_)) => {}
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(light_of(&world, entity), light);
+    }
+}
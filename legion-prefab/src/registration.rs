@@ -14,6 +14,7 @@ use legion::storage::ComponentTypeId;
 use legion::EntityStore;
 use legion::world::{Entity, World};
 use std::ops::Range;
+use crate::{EntityMap, MapEntities};
 
 struct ComponentDeserializer<'de, T: Deserialize<'de>> {
     ptr: *mut T,
@@ -118,6 +119,58 @@ pub enum DiffSingleResult {
     Remove,
 }
 
+/// Returns the component types on either of two entities, without duplicates. These are the only
+/// types for which `ComponentRegistration::diff_single` can return anything but NoChange, so
+/// diffing can be limited to them instead of every registered type.
+pub fn diff_component_types(
+    src_world: &World,
+    src_entity: Option<Entity>,
+    dst_world: &World,
+    dst_entity: Option<Entity>,
+) -> Vec<ComponentTypeId> {
+    let mut component_types = vec![];
+    for (world, entity) in &[(src_world, src_entity), (dst_world, dst_entity)] {
+        let entry = match entity.and_then(|entity| world.entry_ref(entity).ok()) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        for component_type in entry.archetype().layout().component_types() {
+            if !component_types.contains(component_type) {
+                component_types.push(*component_type);
+            }
+        }
+    }
+
+    component_types
+}
+
+/// Where a component type is used. cook_prefab, CopyCloneImpl::new_for_runtime and
+/// SpawnCloneImpl::new_for_runtime strip editor-only components, so that things like gizmos and
+/// editor metadata never reach the runtime world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentUsage {
+    EditorOnly,
+    RuntimeOnly,
+    EditorAndRuntime,
+}
+
+impl Default for ComponentUsage {
+    fn default() -> Self {
+        ComponentUsage::EditorAndRuntime
+    }
+}
+
+impl ComponentUsage {
+    pub fn used_in_editor(self) -> bool {
+        self != ComponentUsage::RuntimeOnly
+    }
+
+    pub fn used_at_runtime(self) -> bool {
+        self != ComponentUsage::EditorOnly
+    }
+}
+
 type CompRegisterFn = fn(&mut EntityLayout);
 type CompSerializeFn = fn(*const u8, &mut dyn FnMut(&dyn erased_serde::Serialize));
 type CompSerializeSliceFn = fn(
@@ -140,6 +193,10 @@ type DiffSingleFn = fn(
     Option<Entity>,
 ) -> DiffSingleResult;
 type ApplyDiffFn = fn(&mut dyn erased_serde::Deserializer, &mut World, Entity);
+type TryApplyDiffFn =
+    fn(&mut dyn erased_serde::Deserializer, &mut World, Entity) -> Result<(), erased_serde::Error>;
+type ValidateDiffFn =
+    fn(&mut dyn erased_serde::Deserializer, &World, Entity) -> Result<(), erased_serde::Error>;
 type CompCloneFn = fn(
     src_entity_range: Range<usize>,
     src_arch: &Archetype,
@@ -148,7 +205,11 @@ type CompCloneFn = fn(
 );
 type AddDefaultToEntityFn = fn(&mut World, Entity);
 type AddToEntityFn = fn(&mut dyn erased_serde::Deserializer, &mut World, Entity);
+type TryAddToEntityFn =
+    fn(&mut dyn erased_serde::Deserializer, &mut World, Entity) -> Result<(), erased_serde::Error>;
 type RemoveFromEntityFn = fn(&mut World, Entity);
+type CopyToEntityFn = fn(&World, Entity, &mut World, Entity) -> bool;
+type MapEntitiesFn = fn(&mut World, Entity, &EntityMap);
 
 #[derive(Clone)]
 pub struct ComponentRegistration {
@@ -164,10 +225,18 @@ pub struct ComponentRegistration {
     serialize_single_fn: SerializeSingleFn,
     diff_single_fn: DiffSingleFn,
     apply_diff_fn: ApplyDiffFn,
+    try_apply_diff_fn: TryApplyDiffFn,
+    validate_diff_fn: ValidateDiffFn,
     comp_clone_fn: CompCloneFn,
     add_default_to_entity_fn: AddDefaultToEntityFn,
     add_to_entity_fn: AddToEntityFn,
+    try_add_to_entity_fn: TryAddToEntityFn,
     remove_from_entity_fn: RemoveFromEntityFn,
+    copy_to_entity_fn: CopyToEntityFn,
+    map_entities_fn: Option<MapEntitiesFn>,
+    usage: ComponentUsage,
+    display_name: Option<&'static str>,
+    version: u32,
 }
 
 impl ComponentRegistration {
@@ -187,6 +256,46 @@ impl ComponentRegistration {
         self.type_name
     }
 
+    pub fn usage(&self) -> ComponentUsage {
+        self.usage
+    }
+
+    /// Sets where the component type is used. Registrations default to EditorAndRuntime
+    pub fn with_usage(
+        mut self,
+        usage: ComponentUsage,
+    ) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// The name to show for the component type in tools, if one was set
+    pub fn display_name(&self) -> Option<&'static str> {
+        self.display_name
+    }
+
+    pub fn with_display_name(
+        mut self,
+        display_name: &'static str,
+    ) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+
+    /// The version of the component type's data layout, for tools that need to detect outdated
+    /// data. Defaults to 0
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn with_version(
+        mut self,
+        version: u32,
+    ) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn register_component(
         &self,
         layout: &mut EntityLayout,
@@ -260,6 +369,17 @@ impl ComponentRegistration {
         (self.add_to_entity_fn)(deserializer, world, entity)
     }
 
+    // Same as add_to_entity, but returns an error rather than panicking if the data doesn't
+    // deserialize. The entity is left unchanged in that case
+    pub fn try_add_to_entity(
+        &self,
+        deserializer: &mut dyn erased_serde::Deserializer,
+        world: &mut legion::world::World,
+        entity: Entity,
+    ) -> Result<(), erased_serde::Error> {
+        (self.try_add_to_entity_fn)(deserializer, world, entity)
+    }
+
     // Used when applying a "Remove" diff command from a transaction to an entity
     pub fn remove_from_entity(
         &self,
@@ -269,6 +389,37 @@ impl ComponentRegistration {
         (self.remove_from_entity_fn)(world, entity)
     }
 
+    // Clones the component from one entity onto another, replacing it if the destination already
+    // has one. Returns false (and does nothing) if the source entity doesn't have the component
+    pub fn copy_to_entity(
+        &self,
+        src_world: &legion::world::World,
+        src_entity: Entity,
+        dst_world: &mut legion::world::World,
+        dst_entity: Entity,
+    ) -> bool {
+        (self.copy_to_entity_fn)(src_world, src_entity, dst_world, dst_entity)
+    }
+
+    // True if the component holds references to other entities that must be remapped when it is
+    // cloned
+    pub fn maps_entities(&self) -> bool {
+        self.map_entities_fn.is_some()
+    }
+
+    // Rewrites the entity references inside the component with the given mapping. Does nothing if
+    // the component type wasn't registered with of_map_entities
+    pub fn map_entities(
+        &self,
+        world: &mut legion::world::World,
+        entity: Entity,
+        entity_map: &EntityMap,
+    ) {
+        if let Some(map_entities_fn) = self.map_entities_fn {
+            (map_entities_fn)(world, entity, entity_map)
+        }
+    }
+
     // Used when creating prefabs
     // Used for creating "modified" diff commands in a transaction
     pub fn diff_single(
@@ -293,6 +444,28 @@ impl ComponentRegistration {
         (self.apply_diff_fn)(de, world, entity);
     }
 
+    // Same as apply_diff, but returns an error rather than panicking if the entity doesn't have the
+    // component or the diff doesn't deserialize. The component is left unchanged in that case
+    pub fn try_apply_diff(
+        &self,
+        de: &mut dyn erased_serde::Deserializer,
+        world: &mut legion::world::World,
+        entity: Entity,
+    ) -> Result<(), erased_serde::Error> {
+        (self.try_apply_diff_fn)(de, world, entity)
+    }
+
+    // Used for checking that a diff stored in a prefab can be applied to an entity without
+    // modifying the world. The diff is applied to a copy of the component
+    pub fn validate_diff(
+        &self,
+        de: &mut dyn erased_serde::Deserializer,
+        world: &legion::world::World,
+        entity: Entity,
+    ) -> Result<(), erased_serde::Error> {
+        (self.validate_diff_fn)(de, world, entity)
+    }
+
     // Used to clone components from one world into another
     #[allow(clippy::missing_safety_doc)]
     pub unsafe fn clone_components(
@@ -317,11 +490,30 @@ impl ComponentRegistration {
             + legion::storage::Component
             + 'static,
     >() -> Self {
+        Self::of_type_with_uuid::<T>(T::UUID, std::any::type_name::<T>())
+    }
+
+    // Same as `of`, for types whose UUID and name are only known at runtime, i.e. the types that
+    // hold dynamic components
+    pub(crate) fn of_type_with_uuid<
+        T: Clone
+            + Serialize
+            + SerdeDiff
+            + for<'de> Deserialize<'de>
+            + Send
+            + Sync
+            + Default
+            + legion::storage::Component
+            + 'static,
+    >(
+        uuid: type_uuid::Bytes,
+        type_name: &'static str,
+    ) -> Self {
         Self {
             component_type_id: ComponentTypeId::of::<T>(),
-            uuid: T::UUID,
+            uuid,
             ty: TypeId::of::<T>(),
-            type_name: std::any::type_name::<T>(),
+            type_name,
             register_comp_fn: |layout| {
                 layout.register_component::<T>();
             },
@@ -449,6 +641,38 @@ impl ComponentRegistration {
                 )
                 .expect("failed to deserialize diff");
             },
+            try_apply_diff_fn: |d, world, entity| {
+                use serde::de::Error;
+                let mut entry = world
+                    .entry(entity)
+                    .ok_or_else(|| erased_serde::Error::custom("entity not found"))?;
+                let comp = entry
+                    .get_component_mut::<T>()
+                    .map_err(|_| erased_serde::Error::custom("component not found on entity"))?;
+
+                // Applied to a copy so that a diff that fails halfway leaves no partial changes
+                let mut changed = comp.clone();
+                <serde_diff::Apply<T> as serde::de::DeserializeSeed>::deserialize(
+                    serde_diff::Apply::deserializable(&mut changed),
+                    d,
+                )?;
+                *comp = changed;
+                Ok(())
+            },
+            validate_diff_fn: |d, world, entity| {
+                use serde::de::Error;
+                let entry = world
+                    .entry_ref(entity)
+                    .map_err(|_| erased_serde::Error::custom("entity not found"))?;
+                let mut comp = entry
+                    .get_component::<T>()
+                    .map_err(|_| erased_serde::Error::custom("component not found on entity"))?
+                    .clone();
+                <serde_diff::Apply<T> as serde::de::DeserializeSeed>::deserialize(
+                    serde_diff::Apply::deserializable(&mut comp),
+                    d,
+                )
+            },
             comp_clone_fn: |src_entity_range, src_arch, src_components, dst| unsafe {
                 let src_components = src_components.get(ComponentTypeId::of::<T>()).unwrap();
                 let src = src_components.downcast_ref::<T::Storage>().unwrap();
@@ -471,9 +695,59 @@ impl ComponentRegistration {
                     erased_serde::deserialize::<T>(d).expect("failed to deserialize component");
                 world.entry(entity).unwrap().add_component(comp);
             },
+            try_add_to_entity_fn: |d, world, entity| {
+                let comp = erased_serde::deserialize::<T>(d)?;
+                world.entry(entity).unwrap().add_component(comp);
+                Ok(())
+            },
             remove_from_entity_fn: |world, entity| {
                 world.entry(entity).unwrap().remove_component::<T>()
             },
+            copy_to_entity_fn: |src_world, src_entity, dst_world, dst_entity| {
+                let comp = src_world
+                    .entry_ref(src_entity)
+                    .ok()
+                    .and_then(|entry| entry.get_component::<T>().ok().cloned());
+
+                match comp {
+                    Some(comp) => {
+                        dst_world.entry(dst_entity).unwrap().add_component(comp);
+                        true
+                    }
+                    None => false,
+                }
+            },
+            map_entities_fn: None,
+            usage: ComponentUsage::default(),
+            display_name: None,
+            version: 0,
+        }
+    }
+
+    /// Same as `of`, but for components that hold references to other entities. The references are
+    /// remapped through `MapEntities` when the component is cloned into another world
+    pub fn of_map_entities<
+        T: TypeUuid
+            + Clone
+            + Serialize
+            + SerdeDiff
+            + for<'de> Deserialize<'de>
+            + Send
+            + Sync
+            + Default
+            + MapEntities
+            + legion::storage::Component
+            + 'static,
+    >() -> Self {
+        Self {
+            map_entities_fn: Some(|world, entity, entity_map| {
+                if let Some(mut entry) = world.entry(entity) {
+                    if let Ok(comp) = entry.get_component_mut::<T>() {
+                        comp.map_entities(entity_map);
+                    }
+                }
+            }),
+            ..Self::of::<T>()
         }
     }
 }
@@ -484,15 +758,49 @@ pub fn iter_component_registrations() -> impl Iterator<Item = &'static Component
     inventory::iter::<ComponentRegistration>.into_iter()
 }
 
+/// Registers a component type so that it is returned by `iter_component_registrations`:
+///  - `register_component_type!(T)`
+///  - `register_component_type!(T, MapEntities)` to remap the entity references it holds
+///  - `register_component_type!(T, usage = EditorOnly)` to set its ComponentUsage
+///  - `register_component_type!(T, MapEntities, usage = EditorOnly)` for both
 #[macro_export]
 macro_rules! register_component_type {
     ($component_type:ty) => {
         $crate::register_component_type!(legion_prefab; $component_type);
     };
+    ($component_type:ty, MapEntities) => {
+        $crate::register_component_type!(legion_prefab; $component_type, MapEntities);
+    };
+    ($component_type:ty, usage = $usage:ident) => {
+        $crate::register_component_type!(legion_prefab; $component_type, usage = $usage);
+    };
+    ($component_type:ty, MapEntities, usage = $usage:ident) => {
+        $crate::register_component_type!(legion_prefab; $component_type, MapEntities, usage = $usage);
+    };
     ($krate:ident; $component_type:ty) => {
         $crate::inventory::submit!{
             #![crate = $krate]
             $crate::ComponentRegistration::of::<$component_type>()
         }
     };
+    ($krate:ident; $component_type:ty, MapEntities) => {
+        $crate::inventory::submit!{
+            #![crate = $krate]
+            $crate::ComponentRegistration::of_map_entities::<$component_type>()
+        }
+    };
+    ($krate:ident; $component_type:ty, usage = $usage:ident) => {
+        $crate::inventory::submit!{
+            #![crate = $krate]
+            $crate::ComponentRegistration::of::<$component_type>()
+                .with_usage($crate::ComponentUsage::$usage)
+        }
+    };
+    ($krate:ident; $component_type:ty, MapEntities, usage = $usage:ident) => {
+        $crate::inventory::submit!{
+            #![crate = $krate]
+            $crate::ComponentRegistration::of_map_entities::<$component_type>()
+                .with_usage($crate::ComponentUsage::$usage)
+        }
+    };
 }
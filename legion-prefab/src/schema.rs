@@ -0,0 +1,251 @@
+//! JSON Schema (draft 7) descriptions of the prefab format, for editors and external tools that
+//! validate or autocomplete prefab files.
+//!
+//! Prefab files are RON, but the schema describes them through the serde data model, which is the
+//! same for RON and JSON: structs are objects, sequences are arrays and enum variants holding data
+//! are objects with the variant name as their only key. RON tooling can use it as is, and prefabs
+//! converted to JSON can be validated with any JSON Schema validator.
+
+use crate::dynamic_component::{dynamic_component_layout, DynamicFieldType};
+use crate::{component_to_value, ComponentRegistration};
+use legion::world::World;
+use prefab_format::ComponentTypeUuid;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+const UUID_PATTERN: &str =
+    "^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$";
+
+/// Returns a schema for the data of a single component type, as it appears in the `data` field of
+/// a prefab entity's component.
+///
+/// Dynamic component types are described exactly from their layout. Other component types are
+/// only known through serde, so their schema is inferred from the serialized default value: field
+/// names and value types are listed, but variants other than the default one of an enum, the
+/// elements of sequences that are empty by default and the contents of `None` values are left
+/// open. No fields are marked as required, since fields with `#[serde(default)]` can't be told
+/// apart from others.
+pub fn component_json_schema(registration: &ComponentRegistration) -> Value {
+    let mut schema = match dynamic_component_layout(registration) {
+        Some(layout) => {
+            let properties: Map<String, Value> = layout
+                .fields()
+                .iter()
+                .map(|field| (field.name.clone(), dynamic_field_schema(&field.field_type)))
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "additionalProperties": false,
+            })
+        }
+        None => {
+            let mut world = World::default();
+            let entity = world.extend(vec![()])[0];
+            registration.add_default_to_entity(&mut world, entity);
+            match component_to_value(registration, &world, entity) {
+                Ok(value) => value_schema(&value),
+                // Components that can't be represented as JSON (i.e. maps with non-string keys)
+                // accept anything
+                Err(_) => json!({}),
+            }
+        }
+    };
+
+    if let Value::Object(schema) = &mut schema {
+        schema.insert(
+            "title".to_string(),
+            Value::from(
+                registration
+                    .display_name()
+                    .unwrap_or_else(|| registration.type_name()),
+            ),
+        );
+        schema.insert(
+            "description".to_string(),
+            Value::from(format!(
+                "{} (version {})",
+                registration.type_name(),
+                registration.version()
+            )),
+        );
+    }
+
+    schema
+}
+
+/// Returns a schema for a whole prefab document. Entity components and prefab override targets
+/// must have one of the registered component types, and the data of each component is checked
+/// against the schema of its type. The schemas of the component types are listed under
+/// `definitions/components`, keyed by their UUID.
+///
+/// Override diffs are stored in serde-diff's command format, which depends on the component type
+/// in ways serde doesn't expose, so they accept anything.
+pub fn prefab_json_schema<S: BuildHasher>(
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, S>
+) -> Value {
+    // Sorted so that the same set of component types always produces the same schema
+    let mut registrations: Vec<_> = registered_components.iter().collect();
+    registrations.sort_by_key(|(uuid, _)| **uuid);
+
+    let mut component_schemas = Map::new();
+    let mut component_types = vec![];
+    let mut typed_components = vec![];
+    for (uuid, registration) in registrations {
+        let uuid = uuid::Uuid::from_bytes(*uuid).to_string();
+        component_schemas.insert(uuid.clone(), component_json_schema(registration));
+        component_types.push(Value::from(uuid.clone()));
+        typed_components.push(json!({
+            "properties": {
+                "type": { "const": uuid },
+                "data": { "$ref": format!("#/definitions/components/{}", uuid) },
+            },
+        }));
+    }
+
+    // Without any registered component types there is nothing to check component data against
+    let component_type = if component_types.is_empty() {
+        json!({ "$ref": "#/definitions/uuid" })
+    } else {
+        json!({ "enum": component_types })
+    };
+    let mut component = json!({
+        "type": "object",
+        "required": ["type", "data"],
+        "properties": {
+            "type": component_type,
+            "data": {},
+        },
+        "additionalProperties": false,
+    });
+    if !typed_components.is_empty() {
+        component["oneOf"] = Value::from(typed_components);
+    }
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Prefab",
+        "type": "object",
+        "required": ["id", "objects"],
+        "properties": {
+            "id": { "$ref": "#/definitions/uuid" },
+            "objects": {
+                "type": "array",
+                "items": { "$ref": "#/definitions/object" },
+            },
+        },
+        "additionalProperties": false,
+        "definitions": {
+            "uuid": {
+                "type": "string",
+                "pattern": UUID_PATTERN,
+            },
+            "object": {
+                "oneOf": [
+                    variant_schema("Entity", json!({ "$ref": "#/definitions/entity" })),
+                    variant_schema("PrefabRef", json!({ "$ref": "#/definitions/prefab_ref" })),
+                ],
+            },
+            "entity": {
+                "type": "object",
+                "required": ["id", "components"],
+                "properties": {
+                    "id": { "$ref": "#/definitions/uuid" },
+                    "components": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/component" },
+                    },
+                },
+                "additionalProperties": false,
+            },
+            "component": component,
+            "prefab_ref": {
+                "type": "object",
+                "required": ["prefab_id", "entity_overrides"],
+                "properties": {
+                    "prefab_id": { "$ref": "#/definitions/uuid" },
+                    "entity_overrides": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/entity_override" },
+                    },
+                },
+                "additionalProperties": false,
+            },
+            "entity_override": {
+                "type": "object",
+                "required": ["entity_id", "component_overrides"],
+                "properties": {
+                    "entity_id": { "$ref": "#/definitions/uuid" },
+                    "component_overrides": {
+                        "type": "array",
+                        "items": { "$ref": "#/definitions/component_override" },
+                    },
+                },
+                "additionalProperties": false,
+            },
+            "component_override": {
+                "type": "object",
+                "required": ["component_type", "diff"],
+                "properties": {
+                    "component_type": component_type,
+                    "diff": {},
+                },
+                "additionalProperties": false,
+            },
+            "components": component_schemas,
+        },
+    })
+}
+
+// An enum variant holding data, which serde represents as an object with a single key
+fn variant_schema(
+    name: &str,
+    data: Value,
+) -> Value {
+    let mut properties = Map::new();
+    properties.insert(name.to_string(), data);
+    json!({
+        "type": "object",
+        "required": [name],
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+fn dynamic_field_schema(field_type: &DynamicFieldType) -> Value {
+    match field_type {
+        DynamicFieldType::Bool => json!({ "type": "boolean" }),
+        DynamicFieldType::I64 => json!({ "type": "integer" }),
+        DynamicFieldType::U64 => json!({ "type": "integer", "minimum": 0 }),
+        DynamicFieldType::F32 | DynamicFieldType::F64 => json!({ "type": "number" }),
+        DynamicFieldType::String => json!({ "type": "string" }),
+        DynamicFieldType::List(element_type) => json!({
+            "type": "array",
+            "items": dynamic_field_schema(element_type),
+        }),
+    }
+}
+
+// Infers a schema from an example value
+fn value_schema(value: &Value) -> Value {
+    match value {
+        // A None value, which says nothing about the type of Some values
+        Value::Null => json!({}),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(array) => match array.first() {
+            Some(element) => json!({ "type": "array", "items": value_schema(element) }),
+            None => json!({ "type": "array" }),
+        },
+        Value::Object(object) => {
+            let properties: Map<String, Value> = object
+                .iter()
+                .map(|(name, value)| (name.clone(), value_schema(value)))
+                .collect();
+            json!({ "type": "object", "properties": properties })
+        }
+    }
+}
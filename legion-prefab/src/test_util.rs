@@ -0,0 +1,62 @@
+// Component types and helpers shared by the unit tests of this crate and legion-transaction. The
+// components are dynamic components so that the tests don't need the derive macros of type-uuid
+// and serde-diff
+
+use crate::{ComponentRegistration, DynamicComponentLayout, DynamicFieldType, FieldPath};
+use legion::storage::ComponentTypeId;
+use legion::*;
+use prefab_format::ComponentTypeUuid;
+use std::collections::HashMap;
+
+// A component with a single number field, `x`
+pub fn number_component(name: &str) -> ComponentRegistration {
+    let layout = DynamicComponentLayout::new(name).with_field("x", DynamicFieldType::F64);
+    crate::register_dynamic_component(layout).unwrap()
+}
+
+pub fn by_uuid(
+    registrations: &[&ComponentRegistration]
+) -> HashMap<ComponentTypeUuid, ComponentRegistration> {
+    registrations
+        .iter()
+        .map(|registration| (*registration.uuid(), (*registration).clone()))
+        .collect()
+}
+
+pub fn by_type_id(
+    registrations: &[&ComponentRegistration]
+) -> HashMap<ComponentTypeId, ComponentRegistration> {
+    registrations
+        .iter()
+        .map(|registration| (registration.component_type_id(), (*registration).clone()))
+        .collect()
+}
+
+// Adds the component to the entity, or replaces it, with `x` set to the given value
+pub fn set_number(
+    registration: &ComponentRegistration,
+    world: &mut World,
+    entity: Entity,
+    x: f64,
+) {
+    registration.add_default_to_entity(world, entity);
+    registration
+        .write_field(world, entity, &x_path(), x.into())
+        .unwrap();
+}
+
+// The value of `x`, or None if the entity doesn't have the component
+pub fn number(
+    registration: &ComponentRegistration,
+    world: &World,
+    entity: Entity,
+) -> Option<f64> {
+    registration
+        .read_field(world, entity, &x_path())
+        .ok()
+        .and_then(|value| value.as_f64())
+}
+
+fn x_path() -> FieldPath {
+    FieldPath::parse("x").unwrap()
+}
@@ -0,0 +1,617 @@
+use crate::cooking::{visit_prefab_refs, PrefabCookOrderError};
+use crate::format::{ComponentTypeUuid, EntityUuid, PrefabUuid};
+use crate::{ComponentRegistration, Prefab};
+use legion::storage::ComponentTypeId;
+use legion::*;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// A problem found while validating a prefab. Validation never stops at the first problem, so a
+/// single call can produce many of these.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefabDiagnostic {
+    /// The same entity UUID is used by more than one prefab in the dependency set
+    DuplicateEntityUuid {
+        entity: EntityUuid,
+        prefabs: Vec<PrefabUuid>,
+    },
+
+    /// A prefab references another prefab that is not in the dependency set
+    MissingPrefabRef {
+        prefab: PrefabUuid,
+        prefab_ref: PrefabUuid,
+    },
+
+    /// A prefab references itself, either directly or through other prefabs. The cycle is listed
+    /// in reference order, starting and ending with the same prefab
+    ReferenceCycle { cycle: Vec<PrefabUuid> },
+
+    /// An override targets an entity that does not exist in the referenced prefab (or any prefab
+    /// it references)
+    OverrideTargetsMissingEntity {
+        prefab: PrefabUuid,
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+    },
+
+    /// An override targets a component that the overridden entity does not have
+    OverrideTargetsMissingComponent {
+        prefab: PrefabUuid,
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+
+    /// An override uses a component type UUID that has no registration
+    UnregisteredOverrideComponent {
+        prefab: PrefabUuid,
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+
+    /// The diff stored in an override could not be deserialized and applied to the component, as it
+    /// is after the overrides of the prefabs cooked before this one
+    InvalidOverrideData {
+        prefab: PrefabUuid,
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        error: String,
+    },
+
+    /// An entity in a prefab's world has a component whose type is not registered
+    UnregisteredComponentType {
+        prefab: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeId,
+    },
+}
+
+fn fmt_uuid(uuid: &[u8; 16]) -> uuid::Uuid {
+    uuid::Uuid::from_bytes(*uuid)
+}
+
+impl std::fmt::Display for PrefabDiagnostic {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        match self {
+            PrefabDiagnostic::DuplicateEntityUuid { entity, prefabs } => {
+                write!(f, "entity {} is defined in multiple prefabs:", fmt_uuid(entity))?;
+                for prefab in prefabs {
+                    write!(f, " {}", fmt_uuid(prefab))?;
+                }
+                Ok(())
+            }
+            PrefabDiagnostic::MissingPrefabRef { prefab, prefab_ref } => write!(
+                f,
+                "prefab {} references prefab {} which was not found",
+                fmt_uuid(prefab),
+                fmt_uuid(prefab_ref)
+            ),
+            PrefabDiagnostic::ReferenceCycle { cycle } => {
+                write!(f, "prefab reference cycle:")?;
+                for (i, prefab) in cycle.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " ->")?;
+                    }
+                    write!(f, " {}", fmt_uuid(prefab))?;
+                }
+                Ok(())
+            }
+            PrefabDiagnostic::OverrideTargetsMissingEntity {
+                prefab,
+                prefab_ref,
+                entity,
+            } => write!(
+                f,
+                "prefab {} overrides entity {} which does not exist in prefab {}",
+                fmt_uuid(prefab),
+                fmt_uuid(entity),
+                fmt_uuid(prefab_ref)
+            ),
+            PrefabDiagnostic::OverrideTargetsMissingComponent {
+                prefab,
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "prefab {} overrides component {} on entity {} (from prefab {}) but the entity does not have that component",
+                fmt_uuid(prefab),
+                fmt_uuid(component_type),
+                fmt_uuid(entity),
+                fmt_uuid(prefab_ref)
+            ),
+            PrefabDiagnostic::UnregisteredOverrideComponent {
+                prefab,
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "prefab {} overrides component {} on entity {} (from prefab {}) but the component type is not registered",
+                fmt_uuid(prefab),
+                fmt_uuid(component_type),
+                fmt_uuid(entity),
+                fmt_uuid(prefab_ref)
+            ),
+            PrefabDiagnostic::InvalidOverrideData {
+                prefab,
+                prefab_ref,
+                entity,
+                component_type,
+                error,
+            } => write!(
+                f,
+                "prefab {} has an invalid override for component {} on entity {} (from prefab {}): {}",
+                fmt_uuid(prefab),
+                fmt_uuid(component_type),
+                fmt_uuid(entity),
+                fmt_uuid(prefab_ref),
+                error
+            ),
+            PrefabDiagnostic::UnregisteredComponentType {
+                prefab,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "entity {} in prefab {} has a component of unregistered type {:?}",
+                fmt_uuid(entity),
+                fmt_uuid(prefab),
+                component_type
+            ),
+        }
+    }
+}
+
+/// Checks a prefab and all the prefabs it references for problems that would otherwise only show
+/// up (usually as a panic) when cooking or spawning. prefab_lookup must contain the dependencies of
+/// the prefab. It may also contain the prefab itself.
+///
+/// Every prefab reachable from the given prefab is checked. An empty result means no problems were
+/// found.
+pub fn validate_prefab<T: BuildHasher, U: BuildHasher>(
+    prefab: &Prefab,
+    prefab_lookup: &HashMap<PrefabUuid, &Prefab, U>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> Vec<PrefabDiagnostic> {
+    let mut diagnostics = vec![];
+
+    // Make sure the prefab being validated is visible to the graph walk even if the caller didn't
+    // put it in the lookup
+    let mut all_prefabs: HashMap<PrefabUuid, &Prefab> = prefab_lookup
+        .iter()
+        .map(|(prefab_id, prefab)| (*prefab_id, *prefab))
+        .collect();
+    all_prefabs.insert(prefab.prefab_id(), prefab);
+
+    //
+    // Check the reference graph for missing prefabs and cycles. The visit order doubles as the list
+    // of prefabs to validate
+    //
+    let mut reachable_prefabs = vec![];
+    let mut errors = vec![];
+    visit_prefab_refs(
+        prefab.prefab_id(),
+        &all_prefabs,
+        &mut reachable_prefabs,
+        &mut errors,
+    );
+
+    for error in errors {
+        match error {
+            PrefabCookOrderError::MissingPrefab(missing_prefab) => {
+                // Report the missing prefab once for every prefab that references it
+                for referencing_prefab in &reachable_prefabs {
+                    if all_prefabs[referencing_prefab]
+                        .prefab_meta
+                        .prefab_refs
+                        .contains_key(&missing_prefab)
+                    {
+                        diagnostics.push(PrefabDiagnostic::MissingPrefabRef {
+                            prefab: *referencing_prefab,
+                            prefab_ref: missing_prefab,
+                        });
+                    }
+                }
+            }
+            PrefabCookOrderError::ReferenceCycle(cycle) => {
+                diagnostics.push(PrefabDiagnostic::ReferenceCycle { cycle });
+            }
+        }
+    }
+
+    //
+    // Find entity UUIDs that are defined by more than one prefab
+    //
+    let mut entity_owners: HashMap<EntityUuid, Vec<PrefabUuid>> = HashMap::new();
+    for prefab_id in &reachable_prefabs {
+        for entity_uuid in all_prefabs[prefab_id].prefab_meta.entities.keys() {
+            entity_owners
+                .entry(*entity_uuid)
+                .or_insert_with(Vec::new)
+                .push(*prefab_id);
+        }
+    }
+
+    let mut duplicate_entities: Vec<_> = entity_owners
+        .iter()
+        .filter(|(_, prefabs)| prefabs.len() > 1)
+        .collect();
+    duplicate_entities.sort_by_key(|(entity_uuid, _)| **entity_uuid);
+    for (entity_uuid, prefabs) in duplicate_entities {
+        diagnostics.push(PrefabDiagnostic::DuplicateEntityUuid {
+            entity: *entity_uuid,
+            prefabs: prefabs.clone(),
+        });
+    }
+
+    //
+    // Find components in prefab worlds that have no registration
+    //
+    let registrations_by_type_id: HashMap<ComponentTypeId, &ComponentRegistration> =
+        registered_components
+            .values()
+            .map(|registration| (registration.component_type_id(), registration))
+            .collect();
+
+    for prefab_id in &reachable_prefabs {
+        let prefab = all_prefabs[prefab_id];
+        let mut entities: Vec<_> = prefab.prefab_meta.entities.iter().collect();
+        entities.sort_by_key(|(entity_uuid, _)| **entity_uuid);
+        for (entity_uuid, entity) in entities {
+            if let Ok(entry) = prefab.world.entry_ref(*entity) {
+                for component_type in entry.archetype().layout().component_types() {
+                    if !registrations_by_type_id.contains_key(component_type) {
+                        diagnostics.push(PrefabDiagnostic::UnregisteredComponentType {
+                            prefab: *prefab_id,
+                            entity: *entity_uuid,
+                            component_type: *component_type,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    //
+    // Check that every override targets an entity/component that exists and that the override data
+    // can be applied to it. Overrides are applied to the entities the way cooking does, in cook
+    // order, so that each override is checked against the state left by the prefabs between it and
+    // the entity's own prefab rather than against the entity as that prefab defines it
+    //
+    let mut cooked_world = World::default();
+    let mut cooked_entities = HashMap::new();
+    for prefab_id in &reachable_prefabs {
+        let prefab = all_prefabs[prefab_id];
+        let mut entities: Vec<_> = prefab.prefab_meta.entities.iter().collect();
+        entities.sort_by_key(|(entity_uuid, _)| **entity_uuid);
+        for (entity_uuid, entity) in entities {
+            // Duplicate entities were already reported above
+            if cooked_entities.contains_key(entity_uuid) {
+                continue;
+            }
+
+            let cooked_entity = cooked_world.extend(vec![()])[0];
+            if let Ok(entry) = prefab.world.entry_ref(*entity) {
+                for component_type in entry.archetype().layout().component_types() {
+                    if let Some(registration) = registrations_by_type_id.get(component_type) {
+                        registration.copy_to_entity(
+                            &prefab.world,
+                            *entity,
+                            &mut cooked_world,
+                            cooked_entity,
+                        );
+                    }
+                }
+            }
+            cooked_entities.insert(*entity_uuid, cooked_entity);
+        }
+    }
+
+    for prefab_id in &reachable_prefabs {
+        let prefab = all_prefabs[prefab_id];
+        let mut prefab_refs: Vec<_> = prefab.prefab_meta.prefab_refs.iter().collect();
+        prefab_refs.sort_by_key(|(prefab_ref_id, _)| **prefab_ref_id);
+
+        for (prefab_ref_id, prefab_ref) in prefab_refs {
+            // Missing prefabs were already reported above
+            if !all_prefabs.contains_key(prefab_ref_id) {
+                continue;
+            }
+
+            // An override may target any entity in the referenced prefab or the prefabs it references
+            let mut referenced_prefabs = vec![];
+            visit_prefab_refs(
+                *prefab_ref_id,
+                &all_prefabs,
+                &mut referenced_prefabs,
+                &mut vec![],
+            );
+
+            let mut overrides: Vec<_> = prefab_ref.overrides.iter().collect();
+            overrides.sort_by_key(|(entity_uuid, _)| **entity_uuid);
+            for (entity_uuid, component_overrides) in overrides {
+                let is_referenced = referenced_prefabs.iter().any(|referenced_prefab_id| {
+                    all_prefabs[referenced_prefab_id]
+                        .prefab_meta
+                        .entities
+                        .contains_key(entity_uuid)
+                });
+
+                let target_entity = match cooked_entities.get(entity_uuid) {
+                    Some(target_entity) if is_referenced => *target_entity,
+                    _ => {
+                        diagnostics.push(PrefabDiagnostic::OverrideTargetsMissingEntity {
+                            prefab: *prefab_id,
+                            prefab_ref: *prefab_ref_id,
+                            entity: *entity_uuid,
+                        });
+                        continue;
+                    }
+                };
+
+                for component_override in component_overrides {
+                    let component_type = component_override.component_type;
+                    let registration = match registered_components.get(&component_type) {
+                        Some(registration) => registration,
+                        None => {
+                            diagnostics.push(PrefabDiagnostic::UnregisteredOverrideComponent {
+                                prefab: *prefab_id,
+                                prefab_ref: *prefab_ref_id,
+                                entity: *entity_uuid,
+                                component_type,
+                            });
+                            continue;
+                        }
+                    };
+
+                    let has_component = cooked_world
+                        .entry_ref(target_entity)
+                        .map(|entry| {
+                            entry
+                                .archetype()
+                                .layout()
+                                .component_types()
+                                .contains(&registration.component_type_id())
+                        })
+                        .unwrap_or(false);
+
+                    if !has_component {
+                        diagnostics.push(PrefabDiagnostic::OverrideTargetsMissingComponent {
+                            prefab: *prefab_id,
+                            prefab_ref: *prefab_ref_id,
+                            entity: *entity_uuid,
+                            component_type,
+                        });
+                        continue;
+                    }
+
+                    let result = ron::de::Deserializer::from_str(&component_override.data)
+                        .map_err(|e| e.to_string())
+                        .and_then(|mut deserializer| {
+                            let mut de = erased_serde::Deserializer::erase(&mut deserializer);
+                            registration
+                                .try_apply_diff(&mut de, &mut cooked_world, target_entity)
+                                .map_err(|e| e.to_string())
+                        });
+
+                    if let Err(error) = result {
+                        diagnostics.push(PrefabDiagnostic::InvalidOverrideData {
+                            prefab: *prefab_id,
+                            prefab_ref: *prefab_ref_id,
+                            entity: *entity_uuid,
+                            component_type,
+                            error,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{by_uuid, number_component, set_number};
+    use crate::{ComponentOverride, DiffSingleResult, PrefabMeta, PrefabRef};
+
+    fn prefab(
+        id: u8,
+        entity: Option<(u8, &ComponentRegistration)>,
+    ) -> Prefab {
+        let mut world = World::default();
+        let mut entities = HashMap::new();
+        if let Some((entity_uuid, registration)) = entity {
+            let entity = world.extend(vec![()])[0];
+            set_number(registration, &mut world, entity, 1.0);
+            entities.insert([entity_uuid; 16], entity);
+        }
+
+        Prefab {
+            world,
+            prefab_meta: PrefabMeta {
+                id: [id; 16],
+                prefab_refs: HashMap::new(),
+                entities,
+            },
+        }
+    }
+
+    fn add_override(
+        prefab: &mut Prefab,
+        prefab_ref: u8,
+        entity_uuid: u8,
+        component_type: ComponentTypeUuid,
+        data: String,
+    ) {
+        prefab
+            .prefab_meta
+            .prefab_refs
+            .entry([prefab_ref; 16])
+            .or_insert_with(|| PrefabRef {
+                overrides: HashMap::new(),
+            })
+            .overrides
+            .entry([entity_uuid; 16])
+            .or_insert_with(Vec::new)
+            .push(ComponentOverride {
+                component_type,
+                data,
+            });
+    }
+
+    // Override data that changes the number component from `from` to `to`
+    fn number_override(
+        registration: &ComponentRegistration,
+        from: f64,
+        to: f64,
+    ) -> String {
+        let mut world = World::default();
+        let from_entity = world.extend(vec![()])[0];
+        set_number(registration, &mut world, from_entity, from);
+        let to_entity = world.extend(vec![()])[0];
+        set_number(registration, &mut world, to_entity, to);
+
+        let mut data = vec![];
+        let mut ron_ser = ron::ser::Serializer::new(&mut data, None, true).unwrap();
+        let mut erased = erased_serde::Serializer::erase(&mut ron_ser);
+        let result = registration.diff_single(
+            &mut erased,
+            &world,
+            Some(from_entity),
+            &world,
+            Some(to_entity),
+        );
+        assert!(result == DiffSingleResult::Change);
+        String::from_utf8(data).unwrap()
+    }
+
+    fn lookup<'a>(prefabs: &[&'a Prefab]) -> HashMap<PrefabUuid, &'a Prefab> {
+        prefabs
+            .iter()
+            .map(|prefab| (prefab.prefab_id(), *prefab))
+            .collect()
+    }
+
+    #[test]
+    fn reports_missing_prefab_refs() {
+        let position = number_component("ValidationMissingRefPosition");
+        let mut root = prefab(1, None);
+        add_override(
+            &mut root,
+            9,
+            2,
+            *position.uuid(),
+            number_override(&position, 1.0, 2.0),
+        );
+
+        let diagnostics = validate_prefab(&root, &lookup(&[]), &by_uuid(&[&position]));
+        assert_eq!(
+            diagnostics,
+            vec![PrefabDiagnostic::MissingPrefabRef {
+                prefab: [1; 16],
+                prefab_ref: [9; 16],
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_unregistered_components() {
+        let position = number_component("ValidationUnregisteredPosition");
+        let base = prefab(2, Some((3, &position)));
+        let mut root = prefab(1, None);
+        add_override(&mut root, 2, 3, [7; 16], "()".to_string());
+
+        let diagnostics = validate_prefab(&root, &lookup(&[&base]), &by_uuid(&[]));
+        assert_eq!(
+            diagnostics,
+            vec![
+                PrefabDiagnostic::UnregisteredComponentType {
+                    prefab: [2; 16],
+                    entity: [3; 16],
+                    component_type: position.component_type_id(),
+                },
+                PrefabDiagnostic::UnregisteredOverrideComponent {
+                    prefab: [1; 16],
+                    prefab_ref: [2; 16],
+                    entity: [3; 16],
+                    component_type: [7; 16],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_override_data() {
+        let position = number_component("ValidationInvalidDataPosition");
+        let base = prefab(2, Some((3, &position)));
+        let mut root = prefab(1, None);
+        add_override(&mut root, 2, 3, *position.uuid(), "not a diff".to_string());
+
+        let diagnostics = validate_prefab(&root, &lookup(&[&base]), &by_uuid(&[&position]));
+        assert_eq!(diagnostics.len(), 1);
+        match &diagnostics[0] {
+            PrefabDiagnostic::InvalidOverrideData {
+                prefab,
+                prefab_ref,
+                entity,
+                component_type,
+                ..
+            } => {
+                assert_eq!(*prefab, [1; 16]);
+                assert_eq!(*prefab_ref, [2; 16]);
+                assert_eq!(*entity, [3; 16]);
+                assert_eq!(component_type, position.uuid());
+            }
+            diagnostic => panic!("unexpected diagnostic {:?}", diagnostic),
+        }
+    }
+
+    #[test]
+    fn validates_overrides_of_stacked_refs() {
+        let position = number_component("ValidationStackedPosition");
+        let nested = prefab(3, Some((4, &position)));
+        let mut base = prefab(2, None);
+        add_override(
+            &mut base,
+            3,
+            4,
+            *position.uuid(),
+            number_override(&position, 1.0, 2.0),
+        );
+        let mut root = prefab(1, None);
+        add_override(
+            &mut root,
+            2,
+            4,
+            *position.uuid(),
+            number_override(&position, 2.0, 3.0),
+        );
+        add_override(
+            &mut root,
+            2,
+            5,
+            *position.uuid(),
+            number_override(&position, 2.0, 3.0),
+        );
+
+        let diagnostics =
+            validate_prefab(&root, &lookup(&[&base, &nested]), &by_uuid(&[&position]));
+        assert_eq!(
+            diagnostics,
+            vec![PrefabDiagnostic::OverrideTargetsMissingEntity {
+                prefab: [1; 16],
+                prefab_ref: [2; 16],
+                entity: [5; 16],
+            }]
+        );
+    }
+}
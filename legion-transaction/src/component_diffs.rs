@@ -1,20 +1,25 @@
-use prefab_format::{ComponentTypeUuid, EntityUuid};
+use prefab_format::{ComponentTypeUuid, EntityUuid, PrefabUuid};
 use legion_prefab::CookedPrefab;
 use legion_prefab::Prefab;
+use legion_prefab::{ComponentOverride, PrefabRef};
 use std::collections::HashMap;
+use std::collections::HashSet;
 use legion::*;
 use legion_prefab::DiffSingleResult;
 use legion_prefab::ComponentRegistration;
-use legion_prefab::CopyCloneImpl;
+use legion_prefab::{CloneMergeImpl, CopyCloneImpl};
 use std::hash::BuildHasher;
+use serde::{Deserialize, Serialize};
+use crate::diff_encoding::codec_for_diff;
+use crate::{DiffEncoding, DiffPayloadCodec};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum EntityDiffOp {
     Add,
     Remove,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EntityDiff {
     entity_uuid: EntityUuid,
     op: EntityDiffOp,
@@ -38,10 +43,10 @@ impl EntityDiff {
 }
 
 // This is somewhat of a mirror of DiffSingleResult
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ComponentDiffOp {
-    Change(Vec<u8>),
-    Add(Vec<u8>),
+    Change(#[serde(with = "crate::wire_format::payload")] Vec<u8>),
+    Add(#[serde(with = "crate::wire_format::payload")] Vec<u8>),
     Remove,
 }
 
@@ -59,7 +64,7 @@ impl ComponentDiffOp {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ComponentDiff {
     entity_uuid: EntityUuid,
     component_type: ComponentTypeUuid,
@@ -102,23 +107,39 @@ impl ComponentDiff {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WorldDiff {
     entity_diffs: Vec<EntityDiff>,
     component_diffs: Vec<ComponentDiff>,
+    encoding: DiffEncoding,
 }
 
 impl WorldDiff {
+    /// Creates a diff whose component payloads are encoded with bincode
     pub fn new(
         entity_diffs: Vec<EntityDiff>,
         component_diffs: Vec<ComponentDiff>,
+    ) -> WorldDiff {
+        Self::new_with_encoding(entity_diffs, component_diffs, DiffEncoding::Bincode)
+    }
+
+    pub fn new_with_encoding(
+        entity_diffs: Vec<EntityDiff>,
+        component_diffs: Vec<ComponentDiff>,
+        encoding: DiffEncoding,
     ) -> WorldDiff {
         WorldDiff {
             entity_diffs,
             component_diffs,
+            encoding,
         }
     }
 
+    /// The encoding of the component payloads
+    pub fn encoding(&self) -> &DiffEncoding {
+        &self.encoding
+    }
+
     pub fn has_changes(&self) -> bool {
         !self.entity_diffs.is_empty() || !self.component_diffs.is_empty()
     }
@@ -130,25 +151,398 @@ impl WorldDiff {
     pub fn component_diffs(&self) -> &Vec<ComponentDiff> {
         &self.component_diffs
     }
+
+    /// Combines this diff with one that is applied after it into a single diff with the same
+    /// effect. Operations keep their relative order. An entity or component that is added and then
+    /// removed again cancels out, and changes to a component that is later added or removed are
+    /// dropped since they are overwritten.
+    ///
+    /// This assumes that components are only added to entities that don't have them yet, which is
+    /// always the case for diffs produced by a transaction. Both diffs must use the same encoding,
+    /// otherwise an error is returned and both are dropped.
+    pub fn coalesce(
+        self,
+        next: WorldDiff,
+    ) -> Result<WorldDiff, CoalesceError> {
+        if self.encoding != next.encoding {
+            return Err(CoalesceError::EncodingMismatch {
+                first: self.encoding,
+                next: next.encoding,
+            });
+        }
+        let encoding = self.encoding;
+
+        // Entity ops, with cancelled ones set to None. For each entity, the indices of its live ops
+        let mut entity_diffs: Vec<Option<EntityDiff>> = vec![];
+        let mut entity_ops: HashMap<EntityUuid, Vec<usize>> = HashMap::new();
+
+        // Component ops of the first diff are skipped when applying it if their entity has been
+        // removed, and ops on removed entities are wasted in either diff. Ops of the first diff
+        // are also wasted if the next one removes their entity, even if it adds it again
+        let mut removed_after_first = HashSet::new();
+        let first_entity_diff_count = self.entity_diffs.len();
+        let removed_in_next: HashSet<EntityUuid> = next
+            .entity_diffs
+            .iter()
+            .filter(|entity_diff| entity_diff.op == EntityDiffOp::Remove)
+            .map(|entity_diff| entity_diff.entity_uuid)
+            .collect();
+
+        for (i, entity_diff) in self
+            .entity_diffs
+            .into_iter()
+            .chain(next.entity_diffs)
+            .enumerate()
+        {
+            let uuid = entity_diff.entity_uuid;
+            let ops = entity_ops.entry(uuid).or_insert_with(Vec::new);
+            let cancels_add = match (&entity_diff.op, ops.last()) {
+                (EntityDiffOp::Remove, Some(last)) => {
+                    entity_diffs[*last].as_ref().unwrap().op == EntityDiffOp::Add
+                }
+                _ => false,
+            };
+
+            if cancels_add {
+                let last = ops.pop().unwrap();
+                entity_diffs[last] = None;
+            } else {
+                ops.push(entity_diffs.len());
+                entity_diffs.push(Some(entity_diff));
+            }
+
+            if i + 1 == first_entity_diff_count {
+                removed_after_first = removed_entities(&entity_diffs, &entity_ops);
+            }
+        }
+
+        let removed_after_next = removed_entities(&entity_diffs, &entity_ops);
+
+        // Component ops, with dropped ones set to None. For each (entity, component type), the
+        // indices of its live ops and whether the component existed before them
+        let mut component_diffs: Vec<Option<ComponentDiff>> = vec![];
+        let mut component_ops: HashMap<(EntityUuid, ComponentTypeUuid), (Vec<usize>, bool)> =
+            HashMap::new();
+
+        let first_component_diffs = self.component_diffs.into_iter().filter(|component_diff| {
+            !removed_after_first.contains(&component_diff.entity_uuid)
+                && !removed_in_next.contains(&component_diff.entity_uuid)
+        });
+        for component_diff in first_component_diffs.chain(next.component_diffs) {
+            if removed_after_next.contains(&component_diff.entity_uuid) {
+                continue;
+            }
+
+            let key = (component_diff.entity_uuid, component_diff.component_type);
+            // The first op seen for a component tells whether it existed before both diffs
+            let entry = component_ops.entry(key);
+            let ops = match component_diff.op {
+                ComponentDiffOp::Change(_) => &mut entry.or_insert_with(|| (vec![], true)).0,
+                ComponentDiffOp::Add(_) | ComponentDiffOp::Remove => {
+                    let existed_before = component_diff.op == ComponentDiffOp::Remove;
+                    let (ops, existed_before) = entry.or_insert_with(|| (vec![], existed_before));
+
+                    // Adding or removing overwrites whatever happened to the component before
+                    for i in ops.drain(..) {
+                        component_diffs[i] = None;
+                    }
+
+                    // Adding and then removing a component cancels out
+                    if !*existed_before && component_diff.op == ComponentDiffOp::Remove {
+                        continue;
+                    }
+                    ops
+                }
+            };
+
+            ops.push(component_diffs.len());
+            component_diffs.push(Some(component_diff));
+        }
+
+        Ok(WorldDiff::new_with_encoding(
+            entity_diffs.into_iter().filter_map(|x| x).collect(),
+            component_diffs.into_iter().filter_map(|x| x).collect(),
+            encoding,
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub enum CoalesceError {
+    /// The payloads of the diffs are encoded differently, so they can't be combined into one diff
+    EncodingMismatch {
+        first: DiffEncoding,
+        next: DiffEncoding,
+    },
+}
+
+// Entities whose most recent live op is a removal
+fn removed_entities(
+    entity_diffs: &[Option<EntityDiff>],
+    entity_ops: &HashMap<EntityUuid, Vec<usize>>,
+) -> HashSet<EntityUuid> {
+    entity_ops
+        .iter()
+        .filter(|(_, ops)| match ops.last() {
+            Some(last) => entity_diffs[*last].as_ref().unwrap().op == EntityDiffOp::Remove,
+            None => false,
+        })
+        .map(|(uuid, _)| *uuid)
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum ApplyDiffError {
+    /// The diff uses a custom encoding, so it must be applied with its codec
+    UnknownEncoding(DiffEncoding),
+    /// A component payload of the diff could not be decoded or doesn't apply to the component,
+    /// i.e. because it is corrupt or the component type changed since the diff was made
+    InvalidPayload {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        error: erased_serde::Error,
+    },
 }
 
 #[derive(Debug)]
 pub enum ApplyDiffToPrefabError {
-    PrefabHasOverrides,
+    /// The diff changes an entity that isn't in the prefab, and the prefab references a prefab
+    /// that was not provided, so the entity may belong to it
+    MissingReferencedPrefab(PrefabUuid),
+    /// Entities that come from a referenced prefab can only be changed, not removed
+    InheritedEntityRemoved(EntityUuid),
+    /// Components of entities that come from a referenced prefab can only be changed, not added
+    InheritedComponentAdded {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+    /// Components of entities that come from a referenced prefab can only be changed, not removed
+    InheritedComponentRemoved {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+    /// An existing override of the prefab is not valid RON or doesn't apply to the referenced
+    /// prefab's component, i.e. because it was edited by hand or the component type changed
+    InvalidOverride {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        message: String,
+    },
+    /// The diff itself can't be applied
+    InvalidDiff(ApplyDiffError),
 }
 
-/// Applies a world diff to a prefab
-///
-/// This is currently only supported for prefabs that have no overrides. If there is an override,
-/// None will be returned
+impl From<ApplyDiffError> for ApplyDiffToPrefabError {
+    fn from(error: ApplyDiffError) -> Self {
+        ApplyDiffToPrefabError::InvalidDiff(error)
+    }
+}
+
+/// Applies a world diff to a prefab that doesn't reference other prefabs, or whose referenced
+/// entities are not affected by the diff. See `apply_diff_to_prefab_with_refs`
 pub fn apply_diff_to_prefab<S: BuildHasher, T: BuildHasher>(
     prefab: &Prefab,
     diff: &WorldDiff,
     registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
     clone_impl: CopyCloneImpl<S>,
 ) -> Result<Prefab, ApplyDiffToPrefabError> {
-    if !prefab.prefab_meta.prefab_refs.is_empty() {
-        return Err(ApplyDiffToPrefabError::PrefabHasOverrides);
+    let referenced_prefabs: HashMap<PrefabUuid, &CookedPrefab> = HashMap::new();
+    apply_diff_to_prefab_with_refs(
+        prefab,
+        diff,
+        &referenced_prefabs,
+        registered_components,
+        clone_impl,
+    )
+}
+
+/// Applies a world diff to a prefab that may reference other prefabs
+///
+/// Changes to the prefab's own entities are made in its world. Changes to components of entities
+/// that come from a referenced prefab are merged into the prefab's override for that component,
+/// so `referenced_prefabs` must contain the cooked form of every prefab the diff reaches into.
+/// Inherited entities and their components can't be added or removed by an override, so diffs
+/// that do this are rejected.
+pub fn apply_diff_to_prefab_with_refs<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
+    prefab: &Prefab,
+    diff: &WorldDiff,
+    referenced_prefabs: &HashMap<PrefabUuid, &CookedPrefab, U>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    clone_impl: CopyCloneImpl<S>,
+) -> Result<Prefab, ApplyDiffToPrefabError> {
+    let codec = codec_for_diff(diff.encoding())?;
+    let mut local_entities: HashSet<EntityUuid> =
+        prefab.prefab_meta.entities.keys().cloned().collect();
+
+    for entity_diff in &diff.entity_diffs {
+        match entity_diff.op() {
+            EntityDiffOp::Add => {
+                local_entities.insert(*entity_diff.entity_uuid());
+            }
+            EntityDiffOp::Remove => {
+                if !local_entities.contains(entity_diff.entity_uuid())
+                    && find_prefab_ref(prefab, referenced_prefabs, entity_diff.entity_uuid())?
+                        .is_some()
+                {
+                    return Err(ApplyDiffToPrefabError::InheritedEntityRemoved(
+                        *entity_diff.entity_uuid(),
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut overrides: HashMap<PrefabUuid, HashMap<EntityUuid, Vec<ComponentOverride>>> = prefab
+        .prefab_meta
+        .prefab_refs
+        .iter()
+        .map(|(prefab_ref_id, prefab_ref)| {
+            let overrides = prefab_ref
+                .overrides
+                .iter()
+                .map(|(entity, component_overrides)| {
+                    let component_overrides = component_overrides
+                        .iter()
+                        .map(|component_override| ComponentOverride {
+                            component_type: component_override.component_type,
+                            data: component_override.data.clone(),
+                        })
+                        .collect();
+                    (*entity, component_overrides)
+                })
+                .collect();
+            (*prefab_ref_id, overrides)
+        })
+        .collect();
+
+    // Inherited components that have been changed, holding their new value. The override is
+    // recomputed from these once all changes have been applied
+    let mut changed_world = World::default();
+    let mut changed_components = HashMap::new();
+
+    for component_diff in &diff.component_diffs {
+        let entity_uuid = *component_diff.entity_uuid();
+        let component_type = *component_diff.component_type();
+        if local_entities.contains(&entity_uuid) {
+            continue;
+        }
+
+        let prefab_ref_id = match find_prefab_ref(prefab, referenced_prefabs, &entity_uuid)? {
+            Some(prefab_ref_id) => prefab_ref_id,
+            None => continue,
+        };
+        let registration = match registered_components.get(&component_type) {
+            Some(registration) => registration,
+            None => continue,
+        };
+
+        let data = match component_diff.op() {
+            ComponentDiffOp::Change(data) => data,
+            ComponentDiffOp::Add(_) => {
+                return Err(ApplyDiffToPrefabError::InheritedComponentAdded {
+                    entity: entity_uuid,
+                    component_type,
+                })
+            }
+            ComponentDiffOp::Remove => {
+                return Err(ApplyDiffToPrefabError::InheritedComponentRemoved {
+                    entity: entity_uuid,
+                    component_type,
+                })
+            }
+        };
+
+        let changed_entity = match changed_components.get(&(entity_uuid, component_type)) {
+            Some((_, changed_entity)) => *changed_entity,
+            None => {
+                // Start from the referenced prefab's value with the existing override applied,
+                // which is the value the diff was made against
+                let cooked_prefab = referenced_prefabs[&prefab_ref_id];
+                let changed_entity = changed_world.extend(vec![()])[0];
+                if !registration.copy_to_entity(
+                    &cooked_prefab.world,
+                    cooked_prefab.entities[&entity_uuid],
+                    &mut changed_world,
+                    changed_entity,
+                ) {
+                    continue;
+                }
+
+                let existing_override =
+                    overrides[&prefab_ref_id]
+                        .get(&entity_uuid)
+                        .and_then(|component_overrides| {
+                            component_overrides.iter().find(|component_override| {
+                                component_override.component_type == component_type
+                            })
+                        });
+                if let Some(existing_override) = existing_override {
+                    let invalid_override =
+                        |message: String| ApplyDiffToPrefabError::InvalidOverride {
+                            prefab_ref: prefab_ref_id,
+                            entity: entity_uuid,
+                            component_type,
+                            message,
+                        };
+
+                    let mut deserializer = ron::de::Deserializer::from_str(&existing_override.data)
+                        .map_err(|e| invalid_override(e.to_string()))?;
+                    let mut de_erased = erased_serde::Deserializer::erase(&mut deserializer);
+                    registration
+                        .try_apply_diff(&mut de_erased, &mut changed_world, changed_entity)
+                        .map_err(|e| invalid_override(e.to_string()))?;
+                }
+
+                changed_components.insert(
+                    (entity_uuid, component_type),
+                    (prefab_ref_id, changed_entity),
+                );
+                changed_entity
+            }
+        };
+
+        codec
+            .deserialize(data, &mut |de| {
+                registration.try_apply_diff(de, &mut changed_world, changed_entity)
+            })
+            .map_err(|error| ApplyDiffError::InvalidPayload {
+                entity: entity_uuid,
+                component_type,
+                error,
+            })?;
+    }
+
+    for ((entity_uuid, component_type), (prefab_ref_id, changed_entity)) in changed_components {
+        let cooked_prefab = referenced_prefabs[&prefab_ref_id];
+        let registration = &registered_components[&component_type];
+
+        let mut data = vec![];
+        let mut ron_ser = ron::ser::Serializer::new(&mut data, None, true).unwrap();
+        let mut ser_erased = erased_serde::Serializer::erase(&mut ron_ser);
+        let result = registration.diff_single(
+            &mut ser_erased,
+            &cooked_prefab.world,
+            Some(cooked_prefab.entities[&entity_uuid]),
+            &changed_world,
+            Some(changed_entity),
+        );
+
+        let component_overrides = overrides
+            .get_mut(&prefab_ref_id)
+            .unwrap()
+            .entry(entity_uuid)
+            .or_insert_with(Vec::new);
+        component_overrides
+            .retain(|component_override| component_override.component_type != component_type);
+
+        // If the component is back to the referenced prefab's value, the override is dropped
+        if result == DiffSingleResult::Change {
+            component_overrides.push(ComponentOverride {
+                component_type,
+                data: String::from_utf8(data).expect("Ron should be utf-8"),
+            });
+        }
     }
 
     let (new_world, uuid_to_new_entities) = apply_diff(
@@ -157,11 +551,19 @@ pub fn apply_diff_to_prefab<S: BuildHasher, T: BuildHasher>(
         diff,
         registered_components,
         clone_impl,
-    );
+    )?;
+
+    let prefab_refs = overrides
+        .into_iter()
+        .map(|(prefab_ref_id, mut overrides)| {
+            overrides.retain(|_, component_overrides| !component_overrides.is_empty());
+            (prefab_ref_id, PrefabRef { overrides })
+        })
+        .collect();
 
     let prefab_meta = legion_prefab::PrefabMeta {
         id: prefab.prefab_meta.id,
-        prefab_refs: Default::default(),
+        prefab_refs,
         entities: uuid_to_new_entities,
     };
 
@@ -171,39 +573,101 @@ pub fn apply_diff_to_prefab<S: BuildHasher, T: BuildHasher>(
     })
 }
 
+// Finds the referenced prefab that an entity comes from. A prefab that already overrides the
+// entity is preferred so that existing overrides are merged into rather than duplicated
+fn find_prefab_ref<U: BuildHasher>(
+    prefab: &Prefab,
+    referenced_prefabs: &HashMap<PrefabUuid, &CookedPrefab, U>,
+    entity_uuid: &EntityUuid,
+) -> Result<Option<PrefabUuid>, ApplyDiffToPrefabError> {
+    let mut prefab_ref_ids: Vec<_> = prefab.prefab_meta.prefab_refs.keys().cloned().collect();
+    prefab_ref_ids.sort();
+
+    let overriding = prefab_ref_ids.iter().find(|prefab_ref_id| {
+        prefab.prefab_meta.prefab_refs[*prefab_ref_id]
+            .overrides
+            .contains_key(entity_uuid)
+    });
+
+    let mut missing = None;
+    for prefab_ref_id in overriding.into_iter().chain(&prefab_ref_ids) {
+        match referenced_prefabs.get(prefab_ref_id) {
+            Some(cooked_prefab) => {
+                if cooked_prefab.entities.contains_key(entity_uuid) {
+                    return Ok(Some(*prefab_ref_id));
+                }
+            }
+            None => {
+                missing.get_or_insert(*prefab_ref_id);
+            }
+        }
+    }
+
+    match missing {
+        Some(prefab_ref_id) => Err(ApplyDiffToPrefabError::MissingReferencedPrefab(
+            prefab_ref_id,
+        )),
+        None => Ok(None),
+    }
+}
+
 /// Applies a world diff to a cooked prefab
 pub fn apply_diff_to_cooked_prefab<S: BuildHasher, T: BuildHasher>(
     cooked_prefab: &CookedPrefab,
     diff: &WorldDiff,
     registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
     clone_impl: CopyCloneImpl<S>,
-) -> CookedPrefab {
+) -> Result<CookedPrefab, ApplyDiffError> {
     let (new_world, uuid_to_new_entities) = apply_diff(
         &cooked_prefab.world,
         &cooked_prefab.entities,
         diff,
         registered_components,
         clone_impl,
-    );
+    )?;
 
-    CookedPrefab {
+    Ok(CookedPrefab {
         world: new_world,
         entities: uuid_to_new_entities,
-    }
+    })
 }
 
+/// Applies a world diff to a copy of the world. Changes to entities that are not in
+/// `uuid_to_entity` are skipped (`apply_diff_to_prefab_with_refs` turns them into overrides)
+///
+/// The diff's payloads are decoded according to its encoding, which must be a built-in one. Use
+/// `apply_diff_with_codec` for diffs with a custom encoding
 pub fn apply_diff<S: BuildHasher, U: BuildHasher, T: BuildHasher>(
+    world: &World,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, T>,
+    diff: &WorldDiff,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, U>,
+    clone_impl: CopyCloneImpl<S>,
+) -> Result<(World, HashMap<EntityUuid, Entity>), ApplyDiffError> {
+    apply_diff_with_codec(
+        world,
+        uuid_to_entity,
+        diff,
+        registered_components,
+        clone_impl,
+        codec_for_diff(diff.encoding())?,
+    )
+}
+
+/// Same as `apply_diff`, but decodes the payloads with the given codec
+pub fn apply_diff_with_codec<S: BuildHasher, U: BuildHasher, T: BuildHasher>(
     world: &World,
     uuid_to_entity: &HashMap<EntityUuid, Entity, T>,
     diff: &WorldDiff,
     registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, U>,
     mut clone_impl: CopyCloneImpl<S>,
-) -> (World, HashMap<EntityUuid, Entity>) {
+    codec: &dyn DiffPayloadCodec,
+) -> Result<(World, HashMap<EntityUuid, Entity>), ApplyDiffError> {
     // Create an empty world to populate
     let mut new_world = World::default();
 
     // Copy everything from the opened prefab into the new world as a baseline
-    let result_mappings = new_world.clone_from(world, &legion::query::any(), &mut clone_impl);
+    let result_mappings = clone_impl.clone_world(&mut new_world, world, &legion::query::any());
 
     let mut uuid_to_new_entities = HashMap::default();
     for (uuid, prefab_entity) in uuid_to_entity {
@@ -222,8 +686,6 @@ pub fn apply_diff<S: BuildHasher, U: BuildHasher, T: BuildHasher>(
                 {
                     new_world.remove(*new_prefab_entity);
                     uuid_to_new_entities.remove(entity_diff.entity_uuid());
-                } else {
-                    //TODO: Produce a remove override
                 }
             }
         }
@@ -234,47 +696,894 @@ pub fn apply_diff<S: BuildHasher, U: BuildHasher, T: BuildHasher>(
             if let Some(component_registration) =
                 registered_components.get(component_diff.component_type())
             {
-                match component_diff.op() {
-                    ComponentDiffOp::Change(data) => {
-                        //TODO: Detect if we need to make the change in the world or as an override
-                        let mut deserializer =
-                            bincode::Deserializer::<bincode::de::read::SliceReader, _>::from_slice(
-                                data.as_slice(),
-                                bincode::config::DefaultOptions::new(),
-                            );
-                        let mut de_erased = erased_serde::Deserializer::erase(&mut deserializer);
-
-                        component_registration.apply_diff(
-                            &mut de_erased,
+                let result = match component_diff.op() {
+                    ComponentDiffOp::Change(data) => codec.deserialize(data, &mut |de| {
+                        component_registration.try_apply_diff(
+                            de,
                             &mut new_world,
                             *new_prefab_entity,
-                        );
-                    }
-                    ComponentDiffOp::Add(data) => {
-                        //TODO: Detect if we need to make the change in the world or as an override
-                        let mut deserializer =
-                            bincode::Deserializer::<bincode::de::read::SliceReader, _>::from_slice(
-                                data,
-                                bincode::config::DefaultOptions::new(),
-                            );
-                        let mut de_erased = erased_serde::Deserializer::erase(&mut deserializer);
-
-                        component_registration.add_to_entity(
-                            &mut de_erased,
+                        )
+                    }),
+                    ComponentDiffOp::Add(data) => codec.deserialize(data, &mut |de| {
+                        component_registration.try_add_to_entity(
+                            de,
                             &mut new_world,
                             *new_prefab_entity,
-                        );
-                    }
+                        )
+                    }),
                     ComponentDiffOp::Remove => {
-                        //TODO: Detect if we need to make the change in the world or as an override
-                        //TODO: propagate error
                         component_registration
                             .remove_from_entity(&mut new_world, *new_prefab_entity);
+                        Ok(())
+                    }
+                };
+                result.map_err(|error| invalid_payload(component_diff, error))?;
+            }
+        }
+    }
+
+    Ok((new_world, uuid_to_new_entities))
+}
+
+/// Applies a world diff directly to a world and its UUID map, and returns the diff that undoes it.
+///
+/// Unlike `apply_diff`, nothing is copied except the components the diff touches, so the cost is
+/// proportional to the size of the diff rather than the size of the world. The inverse diff uses
+/// the same encoding as the diff, which must be a built-in one. Use `apply_diff_in_place_with_codec`
+/// for diffs with a custom encoding.
+///
+/// If a payload can't be decoded, the changes made so far are undone and the error is returned,
+/// so the world is left as it was.
+pub fn apply_diff_in_place<S: BuildHasher, T: BuildHasher>(
+    world: &mut World,
+    uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+    diff: &WorldDiff,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> Result<WorldDiff, ApplyDiffError> {
+    apply_diff_in_place_with_codec(
+        world,
+        uuid_to_entity,
+        diff,
+        registered_components,
+        codec_for_diff(diff.encoding())?,
+    )
+}
+
+/// Same as `apply_diff_in_place`, but decodes the payloads and encodes the inverse diff with the
+/// given codec
+pub fn apply_diff_in_place_with_codec<S: BuildHasher, T: BuildHasher>(
+    world: &mut World,
+    uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+    diff: &WorldDiff,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    codec: &dyn DiffPayloadCodec,
+) -> Result<WorldDiff, ApplyDiffError> {
+    let mut inverse_entity_diffs = vec![];
+    let mut inverse_component_diffs = vec![];
+
+    // Holds copies of components before they are changed so that the inverse change can be diffed
+    let mut scratch_world = World::default();
+
+    // Used to find the registrations of a removed entity's components. Only built if the diff
+    // removes entities
+    let mut registrations_by_type_id = None;
+
+    for entity_diff in &diff.entity_diffs {
+        match entity_diff.op() {
+            EntityDiffOp::Add => {
+                // Replacing the mapping would leak the existing entity, and the inverse diff would
+                // remove the wrong one
+                if uuid_to_entity.contains_key(entity_diff.entity_uuid()) {
+                    log::warn!(
+                        "skipped adding entity {}, which already exists",
+                        uuid::Uuid::from_bytes(*entity_diff.entity_uuid())
+                    );
+                    continue;
+                }
+
+                let new_entity = world.extend(vec![()]);
+                uuid_to_entity.insert(*entity_diff.entity_uuid(), new_entity[0]);
+                inverse_entity_diffs.push(EntityDiff::new(
+                    *entity_diff.entity_uuid(),
+                    EntityDiffOp::Remove,
+                ));
+            }
+            EntityDiffOp::Remove => {
+                if let Some(entity) = uuid_to_entity.get(entity_diff.entity_uuid()).cloned() {
+                    // Restoring the entity requires restoring all of its components
+                    let registrations_by_type_id =
+                        registrations_by_type_id.get_or_insert_with(|| {
+                            registered_components
+                                .iter()
+                                .map(|(component_type, registration)| {
+                                    (
+                                        registration.component_type_id(),
+                                        (*component_type, registration),
+                                    )
+                                })
+                                .collect::<HashMap<_, _>>()
+                        });
+                    let component_type_ids =
+                        legion_prefab::diff_component_types(world, None, world, Some(entity));
+                    for component_type_id in component_type_ids {
+                        let (component_type, registration) =
+                            match registrations_by_type_id.get(&component_type_id) {
+                                Some(registration) => *registration,
+                                None => continue,
+                            };
+
+                        let (result, data) =
+                            diff_component(registration, world, None, world, Some(entity), codec);
+                        if result == DiffSingleResult::Add {
+                            inverse_component_diffs.push(ComponentDiff::new(
+                                *entity_diff.entity_uuid(),
+                                component_type,
+                                ComponentDiffOp::Add(data),
+                            ));
+                        }
                     }
+
+                    world.remove(entity);
+                    uuid_to_entity.remove(entity_diff.entity_uuid());
+                    inverse_entity_diffs.push(EntityDiff::new(
+                        *entity_diff.entity_uuid(),
+                        EntityDiffOp::Add,
+                    ));
+                }
+            }
+        }
+    }
+
+    // Component diffs are undone in the opposite order they were applied
+    let mut inverse_component_changes = vec![];
+    for component_diff in &diff.component_diffs {
+        let entity = match uuid_to_entity.get(component_diff.entity_uuid()) {
+            Some(entity) => *entity,
+            None => continue,
+        };
+        let registration = match registered_components.get(component_diff.component_type()) {
+            Some(registration) => registration,
+            None => continue,
+        };
+
+        // The current value of the component, if it has one, so that it can be restored
+        let (existing, existing_data) =
+            diff_component(registration, world, None, world, Some(entity), codec);
+        let existing_data = if existing == DiffSingleResult::Add {
+            Some(existing_data)
+        } else {
+            None
+        };
+
+        let inverse_op = match component_diff.op() {
+            ComponentDiffOp::Change(data) => {
+                let existing_data = match existing_data {
+                    Some(existing_data) => existing_data,
+                    None => continue,
+                };
+
+                // The component is only written once the whole payload has been read, so it is
+                // unchanged if this fails
+                let scratch_entity = scratch_world.extend(vec![()])[0];
+                let result = codec
+                    .deserialize(&existing_data, &mut |de| {
+                        registration.try_add_to_entity(de, &mut scratch_world, scratch_entity)
+                    })
+                    .and_then(|()| {
+                        codec.deserialize(data, &mut |de| {
+                            registration.try_apply_diff(de, world, entity)
+                        })
+                    });
+                if let Err(error) = result {
+                    return Err(undo_partial(
+                        world,
+                        uuid_to_entity,
+                        inverse_entity_diffs,
+                        inverse_component_diffs,
+                        inverse_component_changes,
+                        registered_components,
+                        codec,
+                        invalid_payload(component_diff, error),
+                    ));
                 }
+
+                let (result, inverse_data) = diff_component(
+                    registration,
+                    world,
+                    Some(entity),
+                    &scratch_world,
+                    Some(scratch_entity),
+                    codec,
+                );
+                ComponentDiffOp::from_diff_single_result(result, inverse_data)
+            }
+            ComponentDiffOp::Add(data) => {
+                if let Err(error) = codec.deserialize(data, &mut |de| {
+                    registration.try_add_to_entity(de, world, entity)
+                }) {
+                    return Err(undo_partial(
+                        world,
+                        uuid_to_entity,
+                        inverse_entity_diffs,
+                        inverse_component_diffs,
+                        inverse_component_changes,
+                        registered_components,
+                        codec,
+                        invalid_payload(component_diff, error),
+                    ));
+                }
+
+                // Adding a component that already exists replaces it
+                match existing_data {
+                    Some(existing_data) => Some(ComponentDiffOp::Add(existing_data)),
+                    None => Some(ComponentDiffOp::Remove),
+                }
+            }
+            ComponentDiffOp::Remove => {
+                let existing_data = match existing_data {
+                    Some(existing_data) => existing_data,
+                    None => continue,
+                };
+
+                registration.remove_from_entity(world, entity);
+                Some(ComponentDiffOp::Add(existing_data))
+            }
+        };
+
+        if let Some(inverse_op) = inverse_op {
+            inverse_component_changes.push(ComponentDiff::new(
+                *component_diff.entity_uuid(),
+                *component_diff.component_type(),
+                inverse_op,
+            ));
+        }
+    }
+
+    Ok(inverse_diff(
+        inverse_entity_diffs,
+        inverse_component_diffs,
+        inverse_component_changes,
+        codec,
+    ))
+}
+
+fn inverse_diff(
+    mut inverse_entity_diffs: Vec<EntityDiff>,
+    mut inverse_component_diffs: Vec<ComponentDiff>,
+    mut inverse_component_changes: Vec<ComponentDiff>,
+    codec: &dyn DiffPayloadCodec,
+) -> WorldDiff {
+    // Entity diffs are undone in the opposite order too, otherwise removing and re-adding an
+    // entity would be undone by adding it (which is skipped) and then removing it
+    inverse_entity_diffs.reverse();
+    inverse_component_changes.reverse();
+    inverse_component_diffs.extend(inverse_component_changes);
+
+    WorldDiff::new_with_encoding(
+        inverse_entity_diffs,
+        inverse_component_diffs,
+        codec.encoding(),
+    )
+}
+
+// Reverts the part of a diff that was applied before `error` happened. The inverse was produced
+// from the world itself, so it can always be applied
+#[allow(clippy::too_many_arguments)]
+fn undo_partial<S: BuildHasher, T: BuildHasher>(
+    world: &mut World,
+    uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+    inverse_entity_diffs: Vec<EntityDiff>,
+    inverse_component_diffs: Vec<ComponentDiff>,
+    inverse_component_changes: Vec<ComponentDiff>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    codec: &dyn DiffPayloadCodec,
+    error: ApplyDiffError,
+) -> ApplyDiffError {
+    let inverse = inverse_diff(
+        inverse_entity_diffs,
+        inverse_component_diffs,
+        inverse_component_changes,
+        codec,
+    );
+    let _ = apply_diff_in_place_with_codec(
+        world,
+        uuid_to_entity,
+        &inverse,
+        registered_components,
+        codec,
+    );
+    error
+}
+
+fn invalid_payload(
+    component_diff: &ComponentDiff,
+    error: erased_serde::Error,
+) -> ApplyDiffError {
+    ApplyDiffError::InvalidPayload {
+        entity: *component_diff.entity_uuid(),
+        component_type: *component_diff.component_type(),
+        error,
+    }
+}
+
+pub(crate) fn diff_component(
+    registration: &ComponentRegistration,
+    src_world: &World,
+    src_entity: Option<Entity>,
+    dst_world: &World,
+    dst_entity: Option<Entity>,
+    codec: &dyn DiffPayloadCodec,
+) -> (DiffSingleResult, Vec<u8>) {
+    let mut data = vec![];
+    let mut result = DiffSingleResult::NoChange;
+    codec.serialize(&mut data, &mut |ser| {
+        result = registration.diff_single(ser, src_world, src_entity, dst_world, dst_entity);
+    });
+    (result, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion_prefab::test_util::{by_type_id, by_uuid, number, number_component, set_number};
+    use legion_prefab::PrefabMeta;
+    use crate::BincodeCodec;
+    use serde_diff::SerdeDiff;
+    use type_uuid::TypeUuid;
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "5bd4e6b4-1f8e-4d4c-9f1e-7a0c2b3d9e51"]
+    struct Transform {
+        x: f32,
+        y: f32,
+    }
+
+    fn entity_diff(
+        uuid: u8,
+        op: EntityDiffOp,
+    ) -> EntityDiff {
+        EntityDiff::new([uuid; 16], op)
+    }
+
+    fn component_diff(
+        uuid: u8,
+        op: ComponentDiffOp,
+    ) -> ComponentDiff {
+        ComponentDiff::new([uuid; 16], [0xc0; 16], op)
+    }
+
+    #[test]
+    fn coalesce_cancels_added_then_removed_entity() {
+        let first = WorldDiff::new(
+            vec![entity_diff(1, EntityDiffOp::Add)],
+            vec![component_diff(1, ComponentDiffOp::Add(vec![1]))],
+        );
+        let next = WorldDiff::new(
+            vec![
+                entity_diff(1, EntityDiffOp::Remove),
+                entity_diff(2, EntityDiffOp::Add),
+            ],
+            vec![],
+        );
+
+        let coalesced = first.coalesce(next).unwrap();
+        assert_eq!(coalesced.entity_diffs().len(), 1);
+        assert_eq!(coalesced.entity_diffs()[0].entity_uuid(), &[2; 16]);
+        assert!(coalesced.component_diffs().is_empty());
+    }
+
+    #[test]
+    fn coalesce_keeps_removal_of_existing_entity() {
+        let first = WorldDiff::new(
+            vec![],
+            vec![component_diff(1, ComponentDiffOp::Change(vec![1]))],
+        );
+        let next = WorldDiff::new(vec![entity_diff(1, EntityDiffOp::Remove)], vec![]);
+
+        let coalesced = first.coalesce(next).unwrap();
+        assert_eq!(coalesced.entity_diffs().len(), 1);
+        assert_eq!(coalesced.entity_diffs()[0].op(), &EntityDiffOp::Remove);
+        assert!(coalesced.component_diffs().is_empty());
+    }
+
+    #[test]
+    fn coalesce_drops_changes_overwritten_by_removal() {
+        let first = WorldDiff::new(
+            vec![],
+            vec![
+                component_diff(1, ComponentDiffOp::Change(vec![1])),
+                component_diff(2, ComponentDiffOp::Change(vec![2])),
+            ],
+        );
+        let next = WorldDiff::new(
+            vec![],
+            vec![
+                component_diff(1, ComponentDiffOp::Change(vec![3])),
+                component_diff(1, ComponentDiffOp::Remove),
+            ],
+        );
+
+        let coalesced = first.coalesce(next).unwrap();
+        let ops: Vec<_> = coalesced
+            .component_diffs()
+            .iter()
+            .map(|component_diff| (component_diff.entity_uuid()[0], component_diff.op().clone()))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (2, ComponentDiffOp::Change(vec![2])),
+                (1, ComponentDiffOp::Remove),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_cancels_added_then_removed_component() {
+        let first = WorldDiff::new(
+            vec![],
+            vec![component_diff(1, ComponentDiffOp::Add(vec![1]))],
+        );
+        let next = WorldDiff::new(
+            vec![],
+            vec![
+                component_diff(1, ComponentDiffOp::Change(vec![2])),
+                component_diff(1, ComponentDiffOp::Remove),
+            ],
+        );
+
+        assert!(!first.coalesce(next).unwrap().has_changes());
+    }
+
+    #[test]
+    fn coalesce_rejects_different_encodings() {
+        let first = WorldDiff::new(vec![entity_diff(1, EntityDiffOp::Add)], vec![]);
+        let next = WorldDiff::new_with_encoding(vec![], vec![], DiffEncoding::Ron);
+
+        match first.coalesce(next) {
+            Err(CoalesceError::EncodingMismatch { first, next }) => {
+                assert_eq!(first, DiffEncoding::Bincode);
+                assert_eq!(next, DiffEncoding::Ron);
+            }
+            Ok(_) => panic!("diffs with different encodings were coalesced"),
+        }
+    }
+
+    #[test]
+    fn inverse_of_change_restores_value() {
+        let position = number_component("InverseChangePosition");
+        let registered_components = by_uuid(&[&position]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&position, &mut world, entity, 1.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let mut changed_world = World::default();
+        let changed_entity = changed_world.extend(vec![()])[0];
+        set_number(&position, &mut changed_world, changed_entity, 2.0);
+        let (result, data) = diff_component(
+            &position,
+            &world,
+            Some(entity),
+            &changed_world,
+            Some(changed_entity),
+            &BincodeCodec,
+        );
+        let op = ComponentDiffOp::from_diff_single_result(result, data).unwrap();
+        let diff = WorldDiff::new(
+            vec![],
+            vec![ComponentDiff::new([1; 16], *position.uuid(), op)],
+        );
+
+        let inverse = apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        )
+        .unwrap();
+        assert_eq!(number(&position, &world, entity), Some(2.0));
+
+        apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &inverse,
+            &registered_components,
+        )
+        .unwrap();
+        assert_eq!(number(&position, &world, entity), Some(1.0));
+    }
+
+    #[test]
+    fn inverse_of_remove_restores_entity_components() {
+        let position = number_component("InverseRemovePosition");
+        let velocity = number_component("InverseRemoveVelocity");
+        let registered_components = by_uuid(&[&position, &velocity]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&position, &mut world, entity, 1.0);
+        set_number(&velocity, &mut world, entity, 3.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let diff = WorldDiff::new(vec![EntityDiff::new([1; 16], EntityDiffOp::Remove)], vec![]);
+        let inverse = apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        )
+        .unwrap();
+        assert!(!world.contains(entity));
+        assert!(uuid_to_entity.is_empty());
+        assert_eq!(inverse.component_diffs().len(), 2);
+
+        apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &inverse,
+            &registered_components,
+        )
+        .unwrap();
+        let restored = uuid_to_entity[&[1; 16]];
+        assert_eq!(number(&position, &world, restored), Some(1.0));
+        assert_eq!(number(&velocity, &world, restored), Some(3.0));
+    }
+
+    #[test]
+    fn adding_existing_entity_is_skipped() {
+        let registered_components = by_uuid(&[]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let diff = WorldDiff::new(vec![EntityDiff::new([1; 16], EntityDiffOp::Add)], vec![]);
+        let inverse = apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        )
+        .unwrap();
+
+        assert_eq!(world.len(), 1);
+        assert_eq!(uuid_to_entity[&[1; 16]], entity);
+        assert!(!inverse.has_changes());
+    }
+
+    #[test]
+    fn custom_encoding_without_codec_is_an_error() {
+        let registered_components = by_uuid(&[]);
+        let mut world = World::default();
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+
+        let diff = WorldDiff::new_with_encoding(
+            vec![EntityDiff::new([1; 16], EntityDiffOp::Add)],
+            vec![],
+            DiffEncoding::Custom("compressed".to_string()),
+        );
+        match apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        ) {
+            Err(ApplyDiffError::UnknownEncoding(encoding)) => {
+                assert_eq!(encoding, DiffEncoding::Custom("compressed".to_string()))
+            }
+            _ => panic!("a diff with a custom encoding was applied without its codec"),
+        }
+        assert_eq!(world.len(), 0);
+    }
+
+    #[test]
+    fn invalid_payload_leaves_world_unchanged() {
+        let position = number_component("InvalidPayloadPosition");
+        let registered_components = by_uuid(&[&position]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&position, &mut world, entity, 1.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let diff = WorldDiff::new(
+            vec![
+                EntityDiff::new([1; 16], EntityDiffOp::Remove),
+                EntityDiff::new([2; 16], EntityDiffOp::Add),
+            ],
+            vec![ComponentDiff::new(
+                [2; 16],
+                *position.uuid(),
+                ComponentDiffOp::Add(vec![]),
+            )],
+        );
+        match apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        ) {
+            Err(ApplyDiffError::InvalidPayload {
+                entity,
+                component_type,
+                ..
+            }) => {
+                assert_eq!(entity, [2; 16]);
+                assert_eq!(component_type, *position.uuid());
             }
+            _ => panic!("a diff with an empty payload was applied"),
         }
+
+        assert_eq!(world.len(), 1);
+        assert_eq!(uuid_to_entity.len(), 1);
+        let restored = uuid_to_entity[&[1; 16]];
+        assert_eq!(number(&position, &world, restored), Some(1.0));
+    }
+
+    #[test]
+    fn inverse_of_remove_then_add_restores_entity() {
+        let position = number_component("InverseRemoveAddPosition");
+        let registered_components = by_uuid(&[&position]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&position, &mut world, entity, 1.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let diff = WorldDiff::new(
+            vec![
+                EntityDiff::new([1; 16], EntityDiffOp::Remove),
+                EntityDiff::new([1; 16], EntityDiffOp::Add),
+            ],
+            vec![],
+        );
+        let inverse = apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        )
+        .unwrap();
+        let readded = uuid_to_entity[&[1; 16]];
+        assert_eq!(number(&position, &world, readded), None);
+
+        apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &inverse,
+            &registered_components,
+        )
+        .unwrap();
+        assert_eq!(world.len(), 1);
+        let restored = uuid_to_entity[&[1; 16]];
+        assert_eq!(number(&position, &world, restored), Some(1.0));
+    }
+
+    #[test]
+    fn struct_change_only_writes_changed_fields() {
+        let transform = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&transform]);
+
+        let mut before_world = World::default();
+        let before_entity = before_world.extend(vec![(Transform { x: 1.0, y: 2.0 },)])[0];
+        let mut after_world = World::default();
+        let after_entity = after_world.extend(vec![(Transform { x: 5.0, y: 2.0 },)])[0];
+        let (result, data) = diff_component(
+            &transform,
+            &before_world,
+            Some(before_entity),
+            &after_world,
+            Some(after_entity),
+            &BincodeCodec,
+        );
+        let op = ComponentDiffOp::from_diff_single_result(result, data).unwrap();
+        let diff = WorldDiff::new(
+            vec![],
+            vec![ComponentDiff::new([1; 16], Transform::UUID, op)],
+        );
+
+        // `y` differs from the world the diff was made from, and isn't touched by it
+        let mut world = World::default();
+        let entity = world.extend(vec![(Transform { x: 1.0, y: 7.0 },)])[0];
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+        let transform_of = |world: &World| {
+            world
+                .entry_ref(entity)
+                .unwrap()
+                .get_component::<Transform>()
+                .unwrap()
+                .clone()
+        };
+
+        let inverse = apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &diff,
+            &registered_components,
+        )
+        .unwrap();
+        assert_eq!(transform_of(&world), Transform { x: 5.0, y: 7.0 });
+
+        apply_diff_in_place(
+            &mut world,
+            &mut uuid_to_entity,
+            &inverse,
+            &registered_components,
+        )
+        .unwrap();
+        assert_eq!(transform_of(&world), Transform { x: 1.0, y: 7.0 });
+    }
+
+    const REFERENCED_PREFAB: PrefabUuid = [2; 16];
+    const INHERITED_ENTITY: EntityUuid = [3; 16];
+
+    // A diff that changes the transform of the inherited entity
+    fn inherited_transform_diff(
+        registration: &ComponentRegistration,
+        from: Transform,
+        to: Transform,
+    ) -> WorldDiff {
+        let mut world = World::default();
+        let from_entity = world.extend(vec![(from,)])[0];
+        let to_entity = world.extend(vec![(to,)])[0];
+        let (result, data) = diff_component(
+            registration,
+            &world,
+            Some(from_entity),
+            &world,
+            Some(to_entity),
+            &BincodeCodec,
+        );
+        let op = ComponentDiffOp::from_diff_single_result(result, data).unwrap();
+        WorldDiff::new(
+            vec![],
+            vec![ComponentDiff::new(INHERITED_ENTITY, Transform::UUID, op)],
+        )
+    }
+
+    // The referenced prefab, cooked, with the inherited entity at Transform { x: 1.0, y: 2.0 }
+    fn referenced_prefab() -> CookedPrefab {
+        let mut world = World::default();
+        let entity = world.extend(vec![(Transform { x: 1.0, y: 2.0 },)])[0];
+        let mut entities = HashMap::new();
+        entities.insert(INHERITED_ENTITY, entity);
+        CookedPrefab { world, entities }
     }
 
-    (new_world, uuid_to_new_entities)
+    // A prefab without entities of its own that references the referenced prefab
+    fn referencing_prefab(component_overrides: Vec<ComponentOverride>) -> Prefab {
+        let mut overrides = HashMap::new();
+        if !component_overrides.is_empty() {
+            overrides.insert(INHERITED_ENTITY, component_overrides);
+        }
+        let mut prefab_refs = HashMap::new();
+        prefab_refs.insert(REFERENCED_PREFAB, PrefabRef { overrides });
+
+        Prefab {
+            world: World::default(),
+            prefab_meta: PrefabMeta {
+                id: [1; 16],
+                prefab_refs,
+                entities: HashMap::new(),
+            },
+        }
+    }
+
+    fn apply_override(
+        registration: &ComponentRegistration,
+        transform: Transform,
+        data: &str,
+    ) -> Transform {
+        let mut world = World::default();
+        let entity = world.extend(vec![(transform,)])[0];
+        let mut deserializer = ron::de::Deserializer::from_str(data).unwrap();
+        let mut de_erased = erased_serde::Deserializer::erase(&mut deserializer);
+        registration
+            .try_apply_diff(&mut de_erased, &mut world, entity)
+            .unwrap();
+        world
+            .entry_ref(entity)
+            .unwrap()
+            .get_component::<Transform>()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn diff_on_inherited_entity_becomes_override() {
+        let transform = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&transform]);
+        let components = by_type_id(&[&transform]);
+        let cooked_prefab = referenced_prefab();
+        let mut referenced_prefabs = HashMap::new();
+        referenced_prefabs.insert(REFERENCED_PREFAB, &cooked_prefab);
+
+        let diff = inherited_transform_diff(
+            &transform,
+            Transform { x: 1.0, y: 2.0 },
+            Transform { x: 5.0, y: 2.0 },
+        );
+        let prefab = apply_diff_to_prefab_with_refs(
+            &referencing_prefab(vec![]),
+            &diff,
+            &referenced_prefabs,
+            &registered_components,
+            CopyCloneImpl::new(&components),
+        )
+        .unwrap();
+
+        // The inherited entity is not copied into the prefab's own world
+        assert_eq!(prefab.world.len(), 0);
+        assert!(prefab.prefab_meta.entities.is_empty());
+
+        let overrides = &prefab.prefab_meta.prefab_refs[&REFERENCED_PREFAB].overrides;
+        let component_overrides = &overrides[&INHERITED_ENTITY];
+        assert_eq!(component_overrides.len(), 1);
+        assert_eq!(component_overrides[0].component_type, Transform::UUID);
+        assert_eq!(
+            apply_override(
+                &transform,
+                Transform { x: 1.0, y: 2.0 },
+                &component_overrides[0].data
+            ),
+            Transform { x: 5.0, y: 2.0 }
+        );
+    }
+
+    #[test]
+    fn override_matching_referenced_prefab_is_dropped() {
+        let transform = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&transform]);
+        let components = by_type_id(&[&transform]);
+        let cooked_prefab = referenced_prefab();
+        let mut referenced_prefabs = HashMap::new();
+        referenced_prefabs.insert(REFERENCED_PREFAB, &cooked_prefab);
+
+        // Start from a prefab that overrides x, then change it back to the referenced value
+        let overridden = apply_diff_to_prefab_with_refs(
+            &referencing_prefab(vec![]),
+            &inherited_transform_diff(
+                &transform,
+                Transform { x: 1.0, y: 2.0 },
+                Transform { x: 5.0, y: 2.0 },
+            ),
+            &referenced_prefabs,
+            &registered_components,
+            CopyCloneImpl::new(&components),
+        )
+        .unwrap();
+        let component_overrides = overridden.prefab_meta.prefab_refs[&REFERENCED_PREFAB].overrides
+            [&INHERITED_ENTITY]
+            .iter()
+            .map(|component_override| ComponentOverride {
+                component_type: component_override.component_type,
+                data: component_override.data.clone(),
+            })
+            .collect();
+
+        let prefab = apply_diff_to_prefab_with_refs(
+            &referencing_prefab(component_overrides),
+            &inherited_transform_diff(
+                &transform,
+                Transform { x: 5.0, y: 2.0 },
+                Transform { x: 1.0, y: 2.0 },
+            ),
+            &referenced_prefabs,
+            &registered_components,
+            CopyCloneImpl::new(&components),
+        )
+        .unwrap();
+
+        assert!(prefab.prefab_meta.prefab_refs[&REFERENCED_PREFAB]
+            .overrides
+            .is_empty());
+    }
 }
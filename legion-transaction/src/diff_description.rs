@@ -0,0 +1,473 @@
+use crate::diff_encoding::codec_for_diff;
+use crate::{ComponentDiffOp, EntityDiffOp, WorldDiff};
+use legion::*;
+use legion_prefab::{ComponentRegistration, FieldChange, FieldPathElement};
+use prefab_format::{ComponentTypeUuid, EntityUuid};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+
+/// A readable description of a single operation in a WorldDiff
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorldDiffEntry {
+    EntityAdded {
+        entity: EntityUuid,
+    },
+    EntityRemoved {
+        entity: EntityUuid,
+    },
+    ComponentAdded {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    ComponentRemoved {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    /// A field of a component changed. A change that touches several fields produces one of these
+    /// per field
+    FieldChanged {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+        change: FieldChange,
+    },
+    /// A component changed, but the change could not be broken down into fields, i.e. because the
+    /// component's previous value isn't known
+    ComponentChanged {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    /// The diff touches a component type that isn't registered, so nothing more is known about it
+    UnknownComponent {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+}
+
+impl WorldDiffEntry {
+    pub fn entity(&self) -> &EntityUuid {
+        match self {
+            WorldDiffEntry::EntityAdded { entity }
+            | WorldDiffEntry::EntityRemoved { entity }
+            | WorldDiffEntry::ComponentAdded { entity, .. }
+            | WorldDiffEntry::ComponentRemoved { entity, .. }
+            | WorldDiffEntry::FieldChanged { entity, .. }
+            | WorldDiffEntry::ComponentChanged { entity, .. }
+            | WorldDiffEntry::UnknownComponent { entity, .. } => entity,
+        }
+    }
+}
+
+impl fmt::Display for WorldDiffEntry {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "Entity {} ", short_uuid(self.entity()))?;
+        match self {
+            WorldDiffEntry::EntityAdded { .. } => write!(f, "added entity"),
+            WorldDiffEntry::EntityRemoved { .. } => write!(f, "removed entity"),
+            WorldDiffEntry::ComponentAdded { type_name, .. } => {
+                write!(f, "added {}", short_type_name(type_name))
+            }
+            WorldDiffEntry::ComponentRemoved { type_name, .. } => {
+                write!(f, "removed {}", short_type_name(type_name))
+            }
+            WorldDiffEntry::FieldChanged {
+                type_name, change, ..
+            } => {
+                // Fields are joined to the type name with a dot, indices follow it directly
+                let separator = match change.path.elements().first() {
+                    Some(FieldPathElement::Field(_)) => ".",
+                    _ => "",
+                };
+                write!(f, "{}{}{}", short_type_name(type_name), separator, change)
+            }
+            WorldDiffEntry::ComponentChanged { type_name, .. } => {
+                write!(f, "changed {}", short_type_name(type_name))
+            }
+            WorldDiffEntry::UnknownComponent { component_type, .. } => write!(
+                f,
+                "unregistered component {}",
+                uuid::Uuid::from_bytes(*component_type)
+            ),
+        }
+    }
+}
+
+/// Readable descriptions of all the operations in a WorldDiff, in the order they are applied.
+/// Displays as one entry per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldDiffDescription {
+    entries: Vec<WorldDiffEntry>,
+}
+
+impl WorldDiffDescription {
+    pub fn entries(&self) -> &[WorldDiffEntry] {
+        &self.entries
+    }
+}
+
+impl fmt::Display for WorldDiffDescription {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes a WorldDiff in readable form. `world` and `uuid_to_entity` must be the state the
+/// diff applies to (i.e. the before world of the transaction that produced it), since the previous
+/// values of changed fields come from there. Changes to components that aren't in the world, and
+/// changes whose payload can't be decoded (i.e. because the diff uses a custom encoding), are
+/// described without their fields. The world is not modified.
+pub fn describe_world_diff<S: BuildHasher, T: BuildHasher>(
+    world: &World,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, S>,
+    diff: &WorldDiff,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> WorldDiffDescription {
+    let codec = codec_for_diff(diff.encoding()).ok();
+    let mut entries = vec![];
+
+    for entity_diff in diff.entity_diffs() {
+        let entity = *entity_diff.entity_uuid();
+        entries.push(match entity_diff.op() {
+            EntityDiffOp::Add => WorldDiffEntry::EntityAdded { entity },
+            EntityDiffOp::Remove => WorldDiffEntry::EntityRemoved { entity },
+        });
+    }
+
+    // The value of each component as of the diff operation being described. Filled in from the
+    // world the first time a component is touched
+    let mut scratch_world = World::default();
+    let mut previous_world = World::default();
+    let mut current_values: HashMap<(EntityUuid, ComponentTypeUuid), Option<Entity>> =
+        HashMap::new();
+
+    for component_diff in diff.component_diffs() {
+        let entity = *component_diff.entity_uuid();
+        let component_type = *component_diff.component_type();
+        let registration = match registered_components.get(&component_type) {
+            Some(registration) => registration,
+            None => {
+                entries.push(WorldDiffEntry::UnknownComponent {
+                    entity,
+                    component_type,
+                });
+                continue;
+            }
+        };
+        let type_name = registration.type_name();
+
+        let current = current_values
+            .entry((entity, component_type))
+            .or_insert_with(|| {
+                let world_entity = uuid_to_entity.get(&entity)?;
+                let scratch_entity = scratch_world.extend(vec![()])[0];
+                if registration.copy_to_entity(
+                    world,
+                    *world_entity,
+                    &mut scratch_world,
+                    scratch_entity,
+                ) {
+                    Some(scratch_entity)
+                } else {
+                    None
+                }
+            });
+
+        match component_diff.op() {
+            ComponentDiffOp::Add(data) => {
+                let scratch_entity = scratch_world.extend(vec![()])[0];
+                let added = codec.map_or(false, |codec| {
+                    codec
+                        .deserialize(data, &mut |de| {
+                            registration.try_add_to_entity(de, &mut scratch_world, scratch_entity)
+                        })
+                        .is_ok()
+                });
+                *current = if added { Some(scratch_entity) } else { None };
+
+                entries.push(WorldDiffEntry::ComponentAdded {
+                    entity,
+                    component_type,
+                    type_name,
+                });
+            }
+            ComponentDiffOp::Remove => {
+                *current = None;
+
+                entries.push(WorldDiffEntry::ComponentRemoved {
+                    entity,
+                    component_type,
+                    type_name,
+                });
+            }
+            ComponentDiffOp::Change(data) => {
+                let current_entity = match *current {
+                    Some(current_entity) => current_entity,
+                    None => {
+                        entries.push(WorldDiffEntry::ComponentChanged {
+                            entity,
+                            component_type,
+                            type_name,
+                        });
+                        continue;
+                    }
+                };
+
+                // Keep the value from before the change so that the fields can be compared
+                let previous_entity = previous_world.extend(vec![()])[0];
+                registration.copy_to_entity(
+                    &scratch_world,
+                    current_entity,
+                    &mut previous_world,
+                    previous_entity,
+                );
+                let applied = codec.map_or(false, |codec| {
+                    codec
+                        .deserialize(data, &mut |de| {
+                            registration.try_apply_diff(de, &mut scratch_world, current_entity)
+                        })
+                        .is_ok()
+                });
+                if !applied {
+                    // Later changes to the component can't be compared against its value either
+                    *current = None;
+                    entries.push(WorldDiffEntry::ComponentChanged {
+                        entity,
+                        component_type,
+                        type_name,
+                    });
+                    continue;
+                }
+
+                match legion_prefab::diff_component_fields(
+                    registration,
+                    &previous_world,
+                    previous_entity,
+                    &scratch_world,
+                    current_entity,
+                ) {
+                    Ok(changes) => entries.extend(changes.into_iter().map(|change| {
+                        WorldDiffEntry::FieldChanged {
+                            entity,
+                            component_type,
+                            type_name,
+                            change,
+                        }
+                    })),
+                    Err(_) => entries.push(WorldDiffEntry::ComponentChanged {
+                        entity,
+                        component_type,
+                        type_name,
+                    }),
+                }
+            }
+        }
+    }
+
+    WorldDiffDescription { entries }
+}
+
+// The first few hex digits of a UUID, enough to tell entities apart in a list
+fn short_uuid(uuid: &[u8; 16]) -> String {
+    format!(
+        "{:02x}{:02x}{:02x}{:02x}…",
+        uuid[0], uuid[1], uuid[2], uuid[3]
+    )
+}
+
+// Strips the module paths from a type name, including those of generic arguments, so that
+// "game::components::Position2D" becomes "Position2D"
+fn short_type_name(type_name: &str) -> String {
+    fn last_segment(path: &str) -> &str {
+        path.rsplit("::").next().unwrap_or(path)
+    }
+
+    let mut short = String::new();
+    let mut segment_start = 0;
+    for (i, c) in type_name.char_indices() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            continue;
+        }
+
+        short.push_str(last_segment(&type_name[segment_start..i]));
+        short.push(c);
+        segment_start = i + c.len_utf8();
+    }
+    short.push_str(last_segment(&type_name[segment_start..]));
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component_diffs::diff_component;
+    use legion_prefab::test_util::{by_uuid, number_component, set_number};
+    use crate::{BincodeCodec, ComponentDiff, EntityDiff};
+    use legion_prefab::FieldPath;
+
+    #[test]
+    fn short_type_name_strips_paths() {
+        assert_eq!(
+            short_type_name("game::components::Position2D"),
+            "Position2D"
+        );
+        assert_eq!(short_type_name("alloc::vec::Vec<game::Foo>"), "Vec<Foo>");
+        assert_eq!(
+            short_type_name("std::collections::HashMap<u32, game::Foo>"),
+            "HashMap<u32, Foo>"
+        );
+        assert_eq!(short_type_name("Position"), "Position");
+    }
+
+    #[test]
+    fn short_uuid_shows_four_bytes() {
+        let mut uuid = [0; 16];
+        uuid[..4].copy_from_slice(&[0x12, 0x34, 0xab, 0xcd]);
+        assert_eq!(short_uuid(&uuid), "1234abcd…");
+    }
+
+    #[test]
+    fn describes_entity_and_component_operations() {
+        let position = number_component("DescribePosition");
+        let velocity = number_component("DescribeVelocity");
+        let registered_components = by_uuid(&[&position, &velocity]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&velocity, &mut world, entity, 1.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let mut added_world = World::default();
+        let added_entity = added_world.extend(vec![()])[0];
+        set_number(&position, &mut added_world, added_entity, 3.0);
+        let (_, added_data) = diff_component(
+            &position,
+            &world,
+            None,
+            &added_world,
+            Some(added_entity),
+            &BincodeCodec,
+        );
+
+        let diff = WorldDiff::new(
+            vec![
+                EntityDiff::new([2; 16], EntityDiffOp::Add),
+                EntityDiff::new([3; 16], EntityDiffOp::Remove),
+            ],
+            vec![
+                ComponentDiff::new([1; 16], *position.uuid(), ComponentDiffOp::Add(added_data)),
+                ComponentDiff::new([1; 16], *velocity.uuid(), ComponentDiffOp::Remove),
+                ComponentDiff::new([1; 16], [0xff; 16], ComponentDiffOp::Remove),
+            ],
+        );
+
+        let description =
+            describe_world_diff(&world, &uuid_to_entity, &diff, &registered_components);
+        assert_eq!(
+            description.entries(),
+            &[
+                WorldDiffEntry::EntityAdded { entity: [2; 16] },
+                WorldDiffEntry::EntityRemoved { entity: [3; 16] },
+                WorldDiffEntry::ComponentAdded {
+                    entity: [1; 16],
+                    component_type: *position.uuid(),
+                    type_name: position.type_name(),
+                },
+                WorldDiffEntry::ComponentRemoved {
+                    entity: [1; 16],
+                    component_type: *velocity.uuid(),
+                    type_name: velocity.type_name(),
+                },
+                WorldDiffEntry::UnknownComponent {
+                    entity: [1; 16],
+                    component_type: [0xff; 16],
+                },
+            ]
+        );
+
+        let lines: Vec<String> = description
+            .entries()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(lines[0], "Entity 02020202… added entity");
+        assert_eq!(lines[1], "Entity 03030303… removed entity");
+        assert_eq!(lines[2], "Entity 01010101… added DescribePosition");
+        assert_eq!(lines[3], "Entity 01010101… removed DescribeVelocity");
+        assert!(lines[4].starts_with("Entity 01010101… unregistered component ffffffff"));
+    }
+
+    #[test]
+    fn describes_changed_fields() {
+        let position = number_component("DescribeChangePosition");
+        let registered_components = by_uuid(&[&position]);
+
+        let mut world = World::default();
+        let entity = world.extend(vec![()])[0];
+        set_number(&position, &mut world, entity, 1.0);
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = HashMap::new();
+        uuid_to_entity.insert([1; 16], entity);
+
+        let mut changed_world = World::default();
+        let changed_entity = changed_world.extend(vec![()])[0];
+        set_number(&position, &mut changed_world, changed_entity, 2.0);
+        let (result, data) = diff_component(
+            &position,
+            &world,
+            Some(entity),
+            &changed_world,
+            Some(changed_entity),
+            &BincodeCodec,
+        );
+        let op = ComponentDiffOp::from_diff_single_result(result, data).unwrap();
+        let diff = WorldDiff::new(
+            vec![],
+            vec![ComponentDiff::new([1; 16], *position.uuid(), op)],
+        );
+
+        let description =
+            describe_world_diff(&world, &uuid_to_entity, &diff, &registered_components);
+        assert_eq!(
+            description.entries(),
+            &[WorldDiffEntry::FieldChanged {
+                entity: [1; 16],
+                component_type: *position.uuid(),
+                type_name: position.type_name(),
+                change: FieldChange {
+                    path: FieldPath::parse("x").unwrap(),
+                    before: Some(1.0.into()),
+                    after: Some(2.0.into()),
+                },
+            }]
+        );
+        assert_eq!(
+            description.to_string(),
+            "Entity 01010101… DescribeChangePosition.x: 1.0 → 2.0\n"
+        );
+
+        // Without the component in the world, the change can't be broken down into fields
+        let description =
+            describe_world_diff(&world, &HashMap::new(), &diff, &registered_components);
+        assert_eq!(
+            description.entries(),
+            &[WorldDiffEntry::ComponentChanged {
+                entity: [1; 16],
+                component_type: *position.uuid(),
+                type_name: position.type_name(),
+            }]
+        );
+    }
+}
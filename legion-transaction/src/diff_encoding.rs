@@ -0,0 +1,122 @@
+use crate::ApplyDiffError;
+use serde::{Deserialize, Serialize};
+
+/// The format of the serde_diff data stored in `ComponentDiffOp` payloads. Recorded in each
+/// `WorldDiff` so that the payloads can be decoded with the codec they were written with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffEncoding {
+    /// bincode with DefaultOptions. Compact, suitable for undo and sending over the network
+    Bincode,
+    /// RON text. Larger, but readable when logged
+    Ron,
+    /// Written by a user-provided codec, identified by name
+    Custom(String),
+}
+
+impl Default for DiffEncoding {
+    fn default() -> Self {
+        DiffEncoding::Bincode
+    }
+}
+
+/// Encodes and decodes component diff payloads. Serialization is done through a callback because
+/// the erased serializer borrows the concrete one, which only lives for the duration of the call.
+pub trait DiffPayloadCodec: Send + Sync {
+    /// The encoding recorded in diffs produced with this codec
+    fn encoding(&self) -> DiffEncoding;
+
+    /// Appends the data written by `serialize_fn` to `data`
+    fn serialize(
+        &self,
+        data: &mut Vec<u8>,
+        serialize_fn: &mut dyn FnMut(&mut dyn erased_serde::Serializer),
+    );
+
+    /// Calls `deserialize_fn` with a deserializer reading from `data`. Fails if `data` can't be
+    /// read in this encoding, or with the error returned by `deserialize_fn`
+    fn deserialize(
+        &self,
+        data: &[u8],
+        deserialize_fn: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error>;
+}
+
+pub struct BincodeCodec;
+
+impl DiffPayloadCodec for BincodeCodec {
+    fn encoding(&self) -> DiffEncoding {
+        DiffEncoding::Bincode
+    }
+
+    fn serialize(
+        &self,
+        data: &mut Vec<u8>,
+        serialize_fn: &mut dyn FnMut(&mut dyn erased_serde::Serializer),
+    ) {
+        let mut ser = bincode::Serializer::new(data, bincode::config::DefaultOptions::new());
+        serialize_fn(&mut erased_serde::Serializer::erase(&mut ser));
+    }
+
+    fn deserialize(
+        &self,
+        data: &[u8],
+        deserialize_fn: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        let mut deserializer =
+            bincode::Deserializer::<bincode::de::read::SliceReader, _>::from_slice(
+                data,
+                bincode::config::DefaultOptions::new(),
+            );
+        deserialize_fn(&mut erased_serde::Deserializer::erase(&mut deserializer))
+    }
+}
+
+pub struct RonCodec;
+
+impl DiffPayloadCodec for RonCodec {
+    fn encoding(&self) -> DiffEncoding {
+        DiffEncoding::Ron
+    }
+
+    fn serialize(
+        &self,
+        data: &mut Vec<u8>,
+        serialize_fn: &mut dyn FnMut(&mut dyn erased_serde::Serializer),
+    ) {
+        let mut ser = ron::ser::Serializer::new(data, None, true).unwrap();
+        serialize_fn(&mut erased_serde::Serializer::erase(&mut ser));
+    }
+
+    fn deserialize(
+        &self,
+        data: &[u8],
+        deserialize_fn: &mut dyn FnMut(
+            &mut dyn erased_serde::Deserializer,
+        ) -> Result<(), erased_serde::Error>,
+    ) -> Result<(), erased_serde::Error> {
+        let mut deserializer = ron::de::Deserializer::from_bytes(data)
+            .map_err(<erased_serde::Error as serde::de::Error>::custom)?;
+        deserialize_fn(&mut erased_serde::Deserializer::erase(&mut deserializer))
+    }
+}
+
+/// Returns the codec for a built-in encoding, or None for custom encodings
+pub fn builtin_diff_codec(encoding: &DiffEncoding) -> Option<&'static dyn DiffPayloadCodec> {
+    match encoding {
+        DiffEncoding::Bincode => Some(&BincodeCodec),
+        DiffEncoding::Ron => Some(&RonCodec),
+        DiffEncoding::Custom(_) => None,
+    }
+}
+
+// Used by functions that decode a diff without being given a codec. Diffs with a custom encoding
+// must be applied with their codec
+pub(crate) fn codec_for_diff(
+    encoding: &DiffEncoding
+) -> Result<&'static dyn DiffPayloadCodec, ApplyDiffError> {
+    builtin_diff_codec(encoding).ok_or_else(|| ApplyDiffError::UnknownEncoding(encoding.clone()))
+}
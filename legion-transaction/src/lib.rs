@@ -5,10 +5,24 @@ pub use component_diffs::ComponentDiffOp;
 pub use component_diffs::EntityDiff;
 pub use component_diffs::EntityDiffOp;
 pub use component_diffs::WorldDiff;
+pub use component_diffs::CoalesceError;
 pub use component_diffs::apply_diff;
+pub use component_diffs::apply_diff_with_codec;
+pub use component_diffs::apply_diff_in_place;
+pub use component_diffs::apply_diff_in_place_with_codec;
 pub use component_diffs::apply_diff_to_prefab;
+pub use component_diffs::apply_diff_to_prefab_with_refs;
 pub use component_diffs::apply_diff_to_cooked_prefab;
 pub use component_diffs::ApplyDiffToPrefabError;
+pub use component_diffs::ApplyDiffError;
+
+// Encodings of the serde_diff data stored in component diffs
+mod diff_encoding;
+pub use diff_encoding::DiffEncoding;
+pub use diff_encoding::DiffPayloadCodec;
+pub use diff_encoding::BincodeCodec;
+pub use diff_encoding::RonCodec;
+pub use diff_encoding::builtin_diff_codec;
 
 // Generates diffs by comparing legion worlds
 mod transactions;
@@ -16,3 +30,58 @@ pub use transactions::TransactionBuilder;
 pub use transactions::Transaction;
 pub use transactions::TransactionDiffs;
 pub use transactions::TransactionEntityInfo;
+pub use transactions::RegisterNewEntityError;
+
+// Transactions that modify a world in place, snapshotting components as they change
+mod tracked_transaction;
+pub use tracked_transaction::TrackedTransaction;
+
+// Compares prefabs by entity and component type UUID
+mod prefab_diff;
+pub use prefab_diff::PrefabDiff;
+pub use prefab_diff::PrefabDiffEntry;
+pub use prefab_diff::diff_prefabs;
+pub use prefab_diff::diff_cooked_prefabs;
+pub use prefab_diff::diff_prefabs_with_codec;
+pub use prefab_diff::diff_cooked_prefabs_with_codec;
+
+// Readable descriptions of world diffs, i.e. for history panels
+mod diff_description;
+pub use diff_description::WorldDiffDescription;
+pub use diff_description::WorldDiffEntry;
+pub use diff_description::describe_world_diff;
+
+// Three-way merge of uncooked prefabs
+mod prefab_merge;
+pub use prefab_merge::MergeConflict;
+pub use prefab_merge::MergeSide;
+pub use prefab_merge::PrefabMergeResult;
+pub use prefab_merge::merge_prefabs;
+
+// Versioned serialization of diffs for persisting or sending them
+mod wire_format;
+pub use wire_format::DiffFormatError;
+pub use wire_format::DIFF_FORMAT_MAGIC;
+pub use wire_format::DIFF_FORMAT_VERSION;
+pub use wire_format::world_diff_to_bytes;
+pub use wire_format::world_diff_from_bytes;
+pub use wire_format::transaction_diffs_to_bytes;
+pub use wire_format::transaction_diffs_from_bytes;
+
+// Streams diffs to a world in another process
+mod live_link;
+pub use live_link::LiveLinkMessage;
+pub use live_link::LiveLinkError;
+pub use live_link::LiveLinkSender;
+pub use live_link::LiveLinkSenderEvent;
+pub use live_link::LiveLinkReceiver;
+pub use live_link::LiveLinkReceiverEvent;
+pub use live_link::LiveLinkSessionId;
+pub use live_link::MAX_LIVE_LINK_MESSAGE_LEN;
+pub use live_link::read_live_link_message;
+pub use live_link::write_live_link_message;
+pub use live_link::world_to_diff;
+
+// Undo/redo stack of committed transactions
+mod undo_history;
+pub use undo_history::UndoHistory;
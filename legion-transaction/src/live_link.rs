@@ -0,0 +1,706 @@
+//! Streams world diffs from one process to another, i.e. from an editor to a running game.
+//!
+//! The sender (editor) assigns every diff a sequence number and keeps it until the receiver (game)
+//! acknowledges that it has been applied. Whenever the receiver connects, it sends a hello with the
+//! last sequence number it applied. If the sender still has every diff after that, it resends them.
+//! Otherwise (i.e. the game restarted, or the editor restarted and the sequence numbers no longer
+//! mean the same thing), the sender must send a reset containing the complete state of the
+//! entities, which replaces everything the receiver got from previous diffs.
+//!
+//! Messages are framed with a little-endian u32 length followed by the message encoded with
+//! bincode's DefaultOptions. Any `Read`/`Write` pair can be used as the transport, i.e. both
+//! halves of a TcpStream or a pipe.
+
+use crate::component_diffs::diff_component;
+use crate::{
+    builtin_diff_codec, ApplyDiffError, BincodeCodec, ComponentDiff, ComponentDiffOp, DiffEncoding,
+    EntityDiff, EntityDiffOp, WorldDiff,
+};
+use crate::DIFF_FORMAT_VERSION;
+use bincode::Options;
+use legion::*;
+use legion_prefab::{ComponentRegistration, DiffSingleResult};
+use prefab_format::{ComponentTypeUuid, EntityUuid};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+
+/// Identifies a sender. Sequence numbers are only meaningful within a session
+pub type LiveLinkSessionId = [u8; 16];
+
+/// Messages larger than this are rejected rather than allocated
+pub const MAX_LIVE_LINK_MESSAGE_LEN: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LiveLinkMessage {
+    /// Sent by the receiver when it connects
+    Hello {
+        version: u32,
+        session: Option<LiveLinkSessionId>,
+        last_applied: u64,
+    },
+    /// A diff to apply on top of the previous one
+    Diff {
+        session: LiveLinkSessionId,
+        sequence: u64,
+        diff: WorldDiff,
+    },
+    /// Replaces all entities the receiver got from this link with the entities added by the diff
+    Reset {
+        session: LiveLinkSessionId,
+        sequence: u64,
+        diff: WorldDiff,
+    },
+    /// Sent by the receiver once a diff or reset has been applied
+    Ack { sequence: u64 },
+}
+
+#[derive(Debug)]
+pub enum LiveLinkError {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    /// The other side announced a message larger than MAX_LIVE_LINK_MESSAGE_LEN
+    MessageTooLarge(usize),
+    /// The receiver uses a different DIFF_FORMAT_VERSION
+    UnsupportedVersion(u32),
+    /// A diff arrived before the diffs it builds on. The receiver should reconnect
+    SequenceGap {
+        expected: u64,
+        received: u64,
+    },
+    /// The message is not valid for this side of the link
+    UnexpectedMessage,
+    /// A diff was sent by a different session than the one the receiver was last reset by, so its
+    /// sequence number doesn't follow the diffs the receiver applied. The receiver should
+    /// reconnect
+    SessionMismatch {
+        expected: Option<LiveLinkSessionId>,
+        received: LiveLinkSessionId,
+    },
+    /// The diff uses a custom encoding, which the receiver can't decode. Nothing was applied
+    UnsupportedEncoding(DiffEncoding),
+    /// A diff could not be applied to the receiver's world. The world is left as it was before
+    /// the diff, or empty if the diff was a reset
+    InvalidDiff(ApplyDiffError),
+}
+
+impl From<io::Error> for LiveLinkError {
+    fn from(error: io::Error) -> Self {
+        LiveLinkError::Io(error)
+    }
+}
+
+impl From<bincode::Error> for LiveLinkError {
+    fn from(error: bincode::Error) -> Self {
+        LiveLinkError::Bincode(error)
+    }
+}
+
+impl From<ApplyDiffError> for LiveLinkError {
+    fn from(error: ApplyDiffError) -> Self {
+        LiveLinkError::InvalidDiff(error)
+    }
+}
+
+pub fn write_live_link_message<W: Write>(
+    writer: &mut W,
+    message: &LiveLinkMessage,
+) -> Result<(), LiveLinkError> {
+    let data = bincode::DefaultOptions::new().serialize(message)?;
+    if data.len() > MAX_LIVE_LINK_MESSAGE_LEN {
+        return Err(LiveLinkError::MessageTooLarge(data.len()));
+    }
+
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Blocks until a complete message has been read
+pub fn read_live_link_message<R: Read>(reader: &mut R) -> Result<LiveLinkMessage, LiveLinkError> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_LIVE_LINK_MESSAGE_LEN {
+        return Err(LiveLinkError::MessageTooLarge(len));
+    }
+
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data)?;
+    Ok(bincode::DefaultOptions::new().deserialize(&data)?)
+}
+
+/// What happened as a result of a message received by the sender
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveLinkSenderEvent {
+    /// The receiver applied every diff up to and including this sequence number
+    Acknowledged(u64),
+    /// The receiver (re)connected and the diffs it was missing have been resent
+    Resynchronized { resent: usize },
+    /// The receiver (re)connected but its state can't be brought up to date with diffs. Send a
+    /// reset with `send_reset`
+    ResetRequired,
+}
+
+/// The editor side of a live link
+pub struct LiveLinkSender {
+    session: LiveLinkSessionId,
+    next_sequence: u64,
+    // Diffs up to this sequence number are no longer kept, so a receiver that hasn't applied them
+    // must be reset
+    acknowledged: u64,
+    // Diffs that have been sent but not acknowledged, in sequence order
+    unacknowledged: VecDeque<(u64, WorldDiff)>,
+}
+
+impl Default for LiveLinkSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LiveLinkSender {
+    pub fn new() -> Self {
+        LiveLinkSender {
+            session: *uuid::Uuid::new_v4().as_bytes(),
+            next_sequence: 1,
+            acknowledged: 0,
+            unacknowledged: VecDeque::new(),
+        }
+    }
+
+    pub fn session(&self) -> &LiveLinkSessionId {
+        &self.session
+    }
+
+    /// Number of diffs waiting to be acknowledged. These are kept so that they can be resent
+    pub fn unacknowledged_count(&self) -> usize {
+        self.unacknowledged.len()
+    }
+
+    /// Sends a diff and returns its sequence number. The diff is kept until it is acknowledged, so
+    /// even if writing it fails (i.e. because the receiver is disconnected) it is resent when the
+    /// receiver reconnects. The receiver can only decode diffs with a built-in encoding
+    pub fn send_diff<W: Write>(
+        &mut self,
+        writer: &mut W,
+        diff: WorldDiff,
+    ) -> Result<u64, LiveLinkError> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let message = LiveLinkMessage::Diff {
+            session: self.session,
+            sequence,
+            diff,
+        };
+        let result = write_live_link_message(writer, &message);
+        if let LiveLinkMessage::Diff { diff, .. } = message {
+            self.unacknowledged.push_back((sequence, diff));
+        }
+
+        result.map(|_| sequence)
+    }
+
+    /// Replaces the receiver's state. `diff` should add every entity the receiver should have
+    /// along with all of its components, see `world_to_diff`. Diffs that were waiting to be
+    /// acknowledged are dropped since the reset supersedes them.
+    pub fn send_reset<W: Write>(
+        &mut self,
+        writer: &mut W,
+        diff: WorldDiff,
+    ) -> Result<u64, LiveLinkError> {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.unacknowledged.clear();
+
+        // Until the reset is acknowledged, a receiver that reconnects must be reset again
+        self.acknowledged = sequence;
+
+        write_live_link_message(
+            writer,
+            &LiveLinkMessage::Reset {
+                session: self.session,
+                sequence,
+                diff,
+            },
+        )?;
+        Ok(sequence)
+    }
+
+    /// Handles a message from the receiver, resending diffs if it reconnected
+    pub fn handle_message<W: Write>(
+        &mut self,
+        writer: &mut W,
+        message: LiveLinkMessage,
+    ) -> Result<LiveLinkSenderEvent, LiveLinkError> {
+        match message {
+            LiveLinkMessage::Ack { sequence } => {
+                self.acknowledge(sequence);
+                Ok(LiveLinkSenderEvent::Acknowledged(sequence))
+            }
+            LiveLinkMessage::Hello {
+                version,
+                session,
+                last_applied,
+            } => {
+                if version != DIFF_FORMAT_VERSION {
+                    return Err(LiveLinkError::UnsupportedVersion(version));
+                }
+
+                // The receiver must have applied everything that is no longer kept
+                if session != Some(self.session) || last_applied < self.acknowledged {
+                    return Ok(LiveLinkSenderEvent::ResetRequired);
+                }
+
+                self.acknowledge(last_applied);
+                for (sequence, diff) in &self.unacknowledged {
+                    write_live_link_message(
+                        writer,
+                        &LiveLinkMessage::Diff {
+                            session: self.session,
+                            sequence: *sequence,
+                            diff: diff.clone(),
+                        },
+                    )?;
+                }
+
+                Ok(LiveLinkSenderEvent::Resynchronized {
+                    resent: self.unacknowledged.len(),
+                })
+            }
+            LiveLinkMessage::Diff { .. } | LiveLinkMessage::Reset { .. } => {
+                Err(LiveLinkError::UnexpectedMessage)
+            }
+        }
+    }
+
+    fn acknowledge(
+        &mut self,
+        sequence: u64,
+    ) {
+        // A late ack from before a reset must not move the acknowledged sequence backwards
+        if sequence <= self.acknowledged {
+            return;
+        }
+
+        self.acknowledged = sequence;
+        while let Some((front_sequence, _)) = self.unacknowledged.front() {
+            if *front_sequence > sequence {
+                break;
+            }
+            self.unacknowledged.pop_front();
+        }
+    }
+}
+
+/// What happened as a result of a message received by the receiver
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiveLinkReceiverEvent {
+    /// A diff was applied to the world
+    Applied(u64),
+    /// The world was reset to the state sent by the sender
+    Reset(u64),
+    /// A diff that had already been applied was received again and ignored
+    Duplicate(u64),
+}
+
+/// The game side of a live link. Applies received diffs to a world, keeping track of the entities
+/// by UUID.
+#[derive(Default)]
+pub struct LiveLinkReceiver {
+    session: Option<LiveLinkSessionId>,
+    last_applied: u64,
+    uuid_to_entity: HashMap<EntityUuid, Entity>,
+}
+
+impl LiveLinkReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entities created or updated through the link
+    pub fn uuid_to_entity(&self) -> &HashMap<EntityUuid, Entity> {
+        &self.uuid_to_entity
+    }
+
+    pub fn last_applied(&self) -> u64 {
+        self.last_applied
+    }
+
+    /// Must be sent whenever a connection is (re)established, before anything else
+    pub fn send_hello<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), LiveLinkError> {
+        write_live_link_message(
+            writer,
+            &LiveLinkMessage::Hello {
+                version: DIFF_FORMAT_VERSION,
+                session: self.session,
+                last_applied: self.last_applied,
+            },
+        )
+    }
+
+    /// Applies a message from the sender to the world and acknowledges it. Messages that can't be
+    /// applied are not acknowledged and leave the world as it was, except for resets whose payloads
+    /// can't be decoded, which leave the receiver without any entities from the link
+    pub fn handle_message<W: Write, S: BuildHasher>(
+        &mut self,
+        writer: &mut W,
+        message: LiveLinkMessage,
+        world: &mut World,
+        registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, S>,
+    ) -> Result<LiveLinkReceiverEvent, LiveLinkError> {
+        let event = match message {
+            LiveLinkMessage::Diff {
+                session,
+                sequence,
+                diff,
+            } => {
+                if self.session != Some(session) {
+                    return Err(LiveLinkError::SessionMismatch {
+                        expected: self.session,
+                        received: session,
+                    });
+                }
+
+                if sequence <= self.last_applied {
+                    LiveLinkReceiverEvent::Duplicate(sequence)
+                } else if sequence != self.last_applied + 1 {
+                    return Err(LiveLinkError::SequenceGap {
+                        expected: self.last_applied + 1,
+                        received: sequence,
+                    });
+                } else {
+                    check_encoding(&diff)?;
+                    crate::apply_diff_in_place(
+                        world,
+                        &mut self.uuid_to_entity,
+                        &diff,
+                        registered_components,
+                    )?;
+                    self.last_applied = sequence;
+                    LiveLinkReceiverEvent::Applied(sequence)
+                }
+            }
+            LiveLinkMessage::Reset {
+                session,
+                sequence,
+                diff,
+            } => {
+                // Checked before the existing entities are removed so that a reset that can't be
+                // decoded at all doesn't empty the world
+                check_encoding(&diff)?;
+                for (_, entity) in self.uuid_to_entity.drain() {
+                    world.remove(entity);
+                }
+
+                // The world no longer matches any session, so the next Hello asks for a new reset
+                self.session = None;
+                self.last_applied = 0;
+                crate::apply_diff_in_place(
+                    world,
+                    &mut self.uuid_to_entity,
+                    &diff,
+                    registered_components,
+                )?;
+                self.session = Some(session);
+                self.last_applied = sequence;
+                LiveLinkReceiverEvent::Reset(sequence)
+            }
+            LiveLinkMessage::Hello { .. } | LiveLinkMessage::Ack { .. } => {
+                return Err(LiveLinkError::UnexpectedMessage)
+            }
+        };
+
+        write_live_link_message(
+            writer,
+            &LiveLinkMessage::Ack {
+                sequence: self.last_applied,
+            },
+        )?;
+        Ok(event)
+    }
+}
+
+fn check_encoding(diff: &WorldDiff) -> Result<(), LiveLinkError> {
+    match builtin_diff_codec(diff.encoding()) {
+        Some(_) => Ok(()),
+        None => Err(LiveLinkError::UnsupportedEncoding(diff.encoding().clone())),
+    }
+}
+
+/// Creates a diff that adds the given entities with all of their registered components, i.e. to
+/// send with `LiveLinkSender::send_reset`
+pub fn world_to_diff<S: BuildHasher, T: BuildHasher>(
+    world: &World,
+    uuid_to_entity: &HashMap<EntityUuid, Entity, S>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> WorldDiff {
+    let registrations_by_type_id: HashMap<_, _> = registered_components
+        .iter()
+        .map(|(component_type, registration)| {
+            (
+                registration.component_type_id(),
+                (*component_type, registration),
+            )
+        })
+        .collect();
+
+    let mut entity_diffs = vec![];
+    let mut component_diffs = vec![];
+
+    for (entity_uuid, entity) in uuid_to_entity {
+        entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
+
+        let component_types =
+            legion_prefab::diff_component_types(world, None, world, Some(*entity));
+        for component_type_id in component_types {
+            let (component_type, registration) =
+                match registrations_by_type_id.get(&component_type_id) {
+                    Some(registration) => *registration,
+                    None => continue,
+                };
+
+            let (result, data) = diff_component(
+                registration,
+                world,
+                None,
+                world,
+                Some(*entity),
+                &BincodeCodec,
+            );
+            if result == DiffSingleResult::Add {
+                component_diffs.push(ComponentDiff::new(
+                    *entity_uuid,
+                    component_type,
+                    ComponentDiffOp::Add(data),
+                ));
+            }
+        }
+    }
+
+    WorldDiff::new(entity_diffs, component_diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion_prefab::test_util::{by_uuid, number, number_component, set_number};
+
+    // Reads the next message written to an in-memory pipe and removes it
+    fn receive(pipe: &mut Vec<u8>) -> LiveLinkMessage {
+        let mut reader = pipe.as_slice();
+        let message = read_live_link_message(&mut reader).unwrap();
+        let consumed = pipe.len() - reader.len();
+        pipe.drain(..consumed);
+        message
+    }
+
+    struct Game {
+        receiver: LiveLinkReceiver,
+        world: World,
+        registered_components: HashMap<ComponentTypeUuid, ComponentRegistration>,
+    }
+
+    impl Game {
+        fn handle(
+            &mut self,
+            to_sender: &mut Vec<u8>,
+            to_receiver: &mut Vec<u8>,
+        ) -> Result<LiveLinkReceiverEvent, LiveLinkError> {
+            let message = receive(to_receiver);
+            self.receiver.handle_message(
+                to_sender,
+                message,
+                &mut self.world,
+                &self.registered_components,
+            )
+        }
+    }
+
+    #[test]
+    fn hello_diff_ack_reset_round_trip() {
+        let position = number_component("LiveLinkPosition");
+
+        let mut editor_world = World::default();
+        let first = editor_world.extend(vec![()])[0];
+        set_number(&position, &mut editor_world, first, 1.0);
+        let second = editor_world.extend(vec![()])[0];
+        set_number(&position, &mut editor_world, second, 2.0);
+        let registered_components = by_uuid(&[&position]);
+        let only = |uuid: u8, entity: Entity| {
+            let mut uuid_to_entity = HashMap::new();
+            uuid_to_entity.insert([uuid; 16], entity);
+            world_to_diff(&editor_world, &uuid_to_entity, &registered_components)
+        };
+
+        let mut game = Game {
+            receiver: LiveLinkReceiver::new(),
+            world: World::default(),
+            registered_components: registered_components.clone(),
+        };
+        let mut sender = LiveLinkSender::new();
+        let mut to_sender = vec![];
+        let mut to_receiver = vec![];
+
+        // A receiver that has never been reset must be
+        game.receiver.send_hello(&mut to_sender).unwrap();
+        let hello = receive(&mut to_sender);
+        assert_eq!(
+            sender.handle_message(&mut to_receiver, hello).unwrap(),
+            LiveLinkSenderEvent::ResetRequired
+        );
+
+        sender.send_reset(&mut to_receiver, only(1, first)).unwrap();
+        assert_eq!(
+            game.handle(&mut to_sender, &mut to_receiver).unwrap(),
+            LiveLinkReceiverEvent::Reset(1)
+        );
+        let ack = receive(&mut to_sender);
+        assert_eq!(
+            sender.handle_message(&mut to_receiver, ack).unwrap(),
+            LiveLinkSenderEvent::Acknowledged(1)
+        );
+
+        sender.send_diff(&mut to_receiver, only(2, second)).unwrap();
+        assert_eq!(sender.unacknowledged_count(), 1);
+        assert_eq!(
+            game.handle(&mut to_sender, &mut to_receiver).unwrap(),
+            LiveLinkReceiverEvent::Applied(2)
+        );
+        let ack = receive(&mut to_sender);
+        assert_eq!(
+            sender.handle_message(&mut to_receiver, ack).unwrap(),
+            LiveLinkSenderEvent::Acknowledged(2)
+        );
+        assert_eq!(sender.unacknowledged_count(), 0);
+
+        let uuid_to_entity = game.receiver.uuid_to_entity().clone();
+        assert_eq!(uuid_to_entity.len(), 2);
+        assert_eq!(
+            number(&position, &game.world, uuid_to_entity[&[1; 16]]),
+            Some(1.0)
+        );
+        assert_eq!(
+            number(&position, &game.world, uuid_to_entity[&[2; 16]]),
+            Some(2.0)
+        );
+
+        // Reconnecting to the same sender needs nothing resent
+        game.receiver.send_hello(&mut to_sender).unwrap();
+        let hello = receive(&mut to_sender);
+        assert_eq!(
+            sender.handle_message(&mut to_receiver, hello).unwrap(),
+            LiveLinkSenderEvent::Resynchronized { resent: 0 }
+        );
+
+        // A restarted sender resets the receiver, replacing everything it got before
+        let mut sender = LiveLinkSender::new();
+        game.receiver.send_hello(&mut to_sender).unwrap();
+        let hello = receive(&mut to_sender);
+        assert_eq!(
+            sender.handle_message(&mut to_receiver, hello).unwrap(),
+            LiveLinkSenderEvent::ResetRequired
+        );
+        sender
+            .send_reset(&mut to_receiver, only(2, second))
+            .unwrap();
+        assert_eq!(
+            game.handle(&mut to_sender, &mut to_receiver).unwrap(),
+            LiveLinkReceiverEvent::Reset(1)
+        );
+        receive(&mut to_sender);
+
+        assert_eq!(game.world.len(), 1);
+        let uuid_to_entity = game.receiver.uuid_to_entity();
+        assert_eq!(uuid_to_entity.len(), 1);
+        assert_eq!(
+            number(&position, &game.world, uuid_to_entity[&[2; 16]]),
+            Some(2.0)
+        );
+        assert!(to_sender.is_empty());
+        assert!(to_receiver.is_empty());
+    }
+
+    #[test]
+    fn diff_from_another_session_is_rejected() {
+        let mut game = Game {
+            receiver: LiveLinkReceiver::new(),
+            world: World::default(),
+            registered_components: HashMap::new(),
+        };
+        let mut to_sender = vec![];
+        let mut to_receiver = vec![];
+
+        let mut sender = LiveLinkSender::new();
+        sender
+            .send_reset(&mut to_receiver, WorldDiff::new(vec![], vec![]))
+            .unwrap();
+        game.handle(&mut to_sender, &mut to_receiver).unwrap();
+        receive(&mut to_sender);
+
+        let mut other_sender = LiveLinkSender::new();
+        other_sender
+            .send_reset(&mut vec![], WorldDiff::new(vec![], vec![]))
+            .unwrap();
+        other_sender
+            .send_diff(
+                &mut to_receiver,
+                WorldDiff::new(vec![EntityDiff::new([1; 16], EntityDiffOp::Add)], vec![]),
+            )
+            .unwrap();
+        match game.handle(&mut to_sender, &mut to_receiver) {
+            Err(LiveLinkError::SessionMismatch { expected, received }) => {
+                assert_eq!(expected, Some(*sender.session()));
+                assert_eq!(received, *other_sender.session());
+            }
+            _ => panic!("a diff from another session was applied"),
+        }
+
+        assert_eq!(game.world.len(), 0);
+        assert_eq!(game.receiver.last_applied(), 1);
+        assert!(to_sender.is_empty());
+    }
+
+    #[test]
+    fn custom_encoding_is_rejected_without_resetting() {
+        let mut game = Game {
+            receiver: LiveLinkReceiver::new(),
+            world: World::default(),
+            registered_components: HashMap::new(),
+        };
+        let mut to_sender = vec![];
+        let mut to_receiver = vec![];
+
+        let mut sender = LiveLinkSender::new();
+        sender
+            .send_reset(
+                &mut to_receiver,
+                WorldDiff::new(vec![EntityDiff::new([1; 16], EntityDiffOp::Add)], vec![]),
+            )
+            .unwrap();
+        game.handle(&mut to_sender, &mut to_receiver).unwrap();
+        receive(&mut to_sender);
+
+        let custom = DiffEncoding::Custom("compressed".to_string());
+        sender
+            .send_reset(
+                &mut to_receiver,
+                WorldDiff::new_with_encoding(vec![], vec![], custom.clone()),
+            )
+            .unwrap();
+        match game.handle(&mut to_sender, &mut to_receiver) {
+            Err(LiveLinkError::UnsupportedEncoding(encoding)) => assert_eq!(encoding, custom),
+            _ => panic!("a reset with a custom encoding was applied"),
+        }
+
+        assert_eq!(game.world.len(), 1);
+        assert_eq!(game.receiver.uuid_to_entity().len(), 1);
+        assert_eq!(game.receiver.last_applied(), 1);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::component_diffs::diff_component;
+use crate::{BincodeCodec, ComponentDiff, DiffPayloadCodec, EntityDiff, EntityDiffOp, WorldDiff};
+use legion::*;
+use legion_prefab::{ComponentRegistration, CookedPrefab, FieldChange, Prefab, PrefabRef};
+use legion_prefab::DiffSingleResult;
+use prefab_format::{ComponentTypeUuid, EntityUuid, PrefabUuid};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::hash::BuildHasher;
+
+/// A single difference between two prefabs, identified by entity and component type UUIDs
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefabDiffEntry {
+    EntityAdded {
+        entity: EntityUuid,
+    },
+    EntityRemoved {
+        entity: EntityUuid,
+    },
+    ComponentAdded {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    ComponentRemoved {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    /// The component exists in both prefabs but its value changed. `changes` is empty if the
+    /// component could not be inspected field by field
+    ComponentChanged {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+        changes: Vec<FieldChange>,
+    },
+    PrefabRefAdded {
+        prefab_ref: PrefabUuid,
+    },
+    PrefabRefRemoved {
+        prefab_ref: PrefabUuid,
+    },
+    OverrideAdded {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+    OverrideRemoved {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+    OverrideChanged {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+}
+
+impl fmt::Display for PrefabDiffEntry {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self {
+            PrefabDiffEntry::EntityAdded { entity } => {
+                write!(f, "+ entity {}", uuid_string(entity))
+            }
+            PrefabDiffEntry::EntityRemoved { entity } => {
+                write!(f, "- entity {}", uuid_string(entity))
+            }
+            PrefabDiffEntry::ComponentAdded {
+                entity, type_name, ..
+            } => write!(f, "+ entity {} {}", uuid_string(entity), type_name),
+            PrefabDiffEntry::ComponentRemoved {
+                entity, type_name, ..
+            } => write!(f, "- entity {} {}", uuid_string(entity), type_name),
+            PrefabDiffEntry::ComponentChanged {
+                entity,
+                type_name,
+                changes,
+                ..
+            } => {
+                write!(f, "~ entity {} {}", uuid_string(entity), type_name)?;
+                for change in changes {
+                    write!(f, "\n    {}", change)?;
+                }
+                Ok(())
+            }
+            PrefabDiffEntry::PrefabRefAdded { prefab_ref } => {
+                write!(f, "+ prefab ref {}", uuid_string(prefab_ref))
+            }
+            PrefabDiffEntry::PrefabRefRemoved { prefab_ref } => {
+                write!(f, "- prefab ref {}", uuid_string(prefab_ref))
+            }
+            PrefabDiffEntry::OverrideAdded {
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "+ override {} entity {} component {}",
+                uuid_string(prefab_ref),
+                uuid_string(entity),
+                uuid_string(component_type)
+            ),
+            PrefabDiffEntry::OverrideRemoved {
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "- override {} entity {} component {}",
+                uuid_string(prefab_ref),
+                uuid_string(entity),
+                uuid_string(component_type)
+            ),
+            PrefabDiffEntry::OverrideChanged {
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "~ override {} entity {} component {}",
+                uuid_string(prefab_ref),
+                uuid_string(entity),
+                uuid_string(component_type)
+            ),
+        }
+    }
+}
+
+/// The result of comparing two prefabs. The entries describe the differences in readable form and
+/// the world diff can be applied to the "before" prefab (with `apply_diff_to_prefab` or
+/// `apply_diff_to_cooked_prefab`) to produce the entity data of the "after" prefab. Changes to
+/// prefab refs and overrides are only reported as entries.
+pub struct PrefabDiff {
+    entries: Vec<PrefabDiffEntry>,
+    world_diff: WorldDiff,
+}
+
+impl PrefabDiff {
+    pub fn entries(&self) -> &[PrefabDiffEntry] {
+        &self.entries
+    }
+
+    pub fn world_diff(&self) -> &WorldDiff {
+        &self.world_diff
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for PrefabDiff {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two uncooked prefabs, including their prefab refs and overrides. The payloads of the
+/// world diff are encoded with bincode
+pub fn diff_prefabs<T: BuildHasher>(
+    before: &Prefab,
+    after: &Prefab,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> PrefabDiff {
+    diff_prefabs_with_codec(before, after, registered_components, &BincodeCodec)
+}
+
+/// Same as `diff_prefabs`, but encodes the payloads of the world diff with the given codec
+pub fn diff_prefabs_with_codec<T: BuildHasher>(
+    before: &Prefab,
+    after: &Prefab,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    codec: &dyn DiffPayloadCodec,
+) -> PrefabDiff {
+    let mut entries = vec![];
+    let world_diff = diff_worlds(
+        &before.world,
+        &before.prefab_meta.entities,
+        &after.world,
+        &after.prefab_meta.entities,
+        registered_components,
+        codec,
+        &mut entries,
+    );
+
+    diff_prefab_refs(
+        &before.prefab_meta.prefab_refs,
+        &after.prefab_meta.prefab_refs,
+        &mut entries,
+    );
+
+    PrefabDiff {
+        entries,
+        world_diff,
+    }
+}
+
+/// Compares two cooked prefabs. The payloads of the world diff are encoded with bincode
+pub fn diff_cooked_prefabs<T: BuildHasher>(
+    before: &CookedPrefab,
+    after: &CookedPrefab,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> PrefabDiff {
+    diff_cooked_prefabs_with_codec(before, after, registered_components, &BincodeCodec)
+}
+
+/// Same as `diff_cooked_prefabs`, but encodes the payloads of the world diff with the given codec
+pub fn diff_cooked_prefabs_with_codec<T: BuildHasher>(
+    before: &CookedPrefab,
+    after: &CookedPrefab,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    codec: &dyn DiffPayloadCodec,
+) -> PrefabDiff {
+    let mut entries = vec![];
+    let world_diff = diff_worlds(
+        &before.world,
+        &before.entities,
+        &after.world,
+        &after.entities,
+        registered_components,
+        codec,
+        &mut entries,
+    );
+
+    PrefabDiff {
+        entries,
+        world_diff,
+    }
+}
+
+fn diff_worlds<S: BuildHasher, T: BuildHasher, U: BuildHasher>(
+    before_world: &World,
+    before_entities: &HashMap<EntityUuid, Entity, S>,
+    after_world: &World,
+    after_entities: &HashMap<EntityUuid, Entity, T>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, U>,
+    codec: &dyn DiffPayloadCodec,
+    entries: &mut Vec<PrefabDiffEntry>,
+) -> WorldDiff {
+    let mut entity_diffs = vec![];
+    let mut component_diffs = vec![];
+
+    // Sort everything so that the output is stable
+    let entity_uuids: BTreeSet<_> = before_entities
+        .keys()
+        .chain(after_entities.keys())
+        .cloned()
+        .collect();
+
+    // Only the component types on an entity are diffed, so look registrations up by type id
+    let registrations_by_type_id: HashMap<_, _> = registered_components
+        .iter()
+        .map(|(component_type, registration)| {
+            (
+                registration.component_type_id(),
+                (*component_type, registration),
+            )
+        })
+        .collect();
+
+    for entity_uuid in entity_uuids {
+        let before_entity = before_entities.get(&entity_uuid).cloned();
+        let after_entity = after_entities.get(&entity_uuid).cloned();
+
+        match (before_entity, after_entity) {
+            (Some(_), None) => {
+                // Removing the entity removes its components too, so no component diffs are needed
+                entity_diffs.push(EntityDiff::new(entity_uuid, EntityDiffOp::Remove));
+                entries.push(PrefabDiffEntry::EntityRemoved {
+                    entity: entity_uuid,
+                });
+                continue;
+            }
+            (None, Some(_)) => {
+                entity_diffs.push(EntityDiff::new(entity_uuid, EntityDiffOp::Add));
+                entries.push(PrefabDiffEntry::EntityAdded {
+                    entity: entity_uuid,
+                });
+            }
+            _ => {}
+        }
+
+        let mut registrations: Vec<_> = legion_prefab::diff_component_types(
+            before_world,
+            before_entity,
+            after_world,
+            after_entity,
+        )
+        .iter()
+        .filter_map(|component_type_id| registrations_by_type_id.get(component_type_id))
+        .collect();
+        registrations.sort_by_key(|(component_type, _)| *component_type);
+
+        for (component_type, registration) in registrations {
+            let (result, data) = diff_component(
+                registration,
+                before_world,
+                before_entity,
+                after_world,
+                after_entity,
+                codec,
+            );
+
+            let entity = entity_uuid;
+            let component_type = *component_type;
+            let type_name = registration.type_name();
+            match result {
+                DiffSingleResult::NoChange => continue,
+                DiffSingleResult::Add => entries.push(PrefabDiffEntry::ComponentAdded {
+                    entity,
+                    component_type,
+                    type_name,
+                }),
+                DiffSingleResult::Remove => entries.push(PrefabDiffEntry::ComponentRemoved {
+                    entity,
+                    component_type,
+                    type_name,
+                }),
+                DiffSingleResult::Change => {
+                    let changes = legion_prefab::diff_component_fields(
+                        registration,
+                        before_world,
+                        before_entity.unwrap(),
+                        after_world,
+                        after_entity.unwrap(),
+                    )
+                    .unwrap_or_default();
+
+                    entries.push(PrefabDiffEntry::ComponentChanged {
+                        entity,
+                        component_type,
+                        type_name,
+                        changes,
+                    });
+                }
+            }
+
+            component_diffs.push(
+                ComponentDiff::new_from_diff_single_result(entity, component_type, result, data)
+                    .unwrap(),
+            );
+        }
+    }
+
+    WorldDiff::new_with_encoding(entity_diffs, component_diffs, codec.encoding())
+}
+
+fn diff_prefab_refs<S: BuildHasher, T: BuildHasher>(
+    before: &HashMap<PrefabUuid, PrefabRef, S>,
+    after: &HashMap<PrefabUuid, PrefabRef, T>,
+    entries: &mut Vec<PrefabDiffEntry>,
+) {
+    let prefab_ref_ids: BTreeSet<_> = before.keys().chain(after.keys()).cloned().collect();
+    for prefab_ref in prefab_ref_ids {
+        let before_ref = before.get(&prefab_ref);
+        let after_ref = after.get(&prefab_ref);
+
+        match (before_ref, after_ref) {
+            (Some(_), None) => {
+                entries.push(PrefabDiffEntry::PrefabRefRemoved { prefab_ref });
+                continue;
+            }
+            (None, Some(_)) => entries.push(PrefabDiffEntry::PrefabRefAdded { prefab_ref }),
+            _ => {}
+        }
+
+        let before_overrides = override_data(before_ref);
+        let after_overrides = override_data(after_ref);
+        let keys: BTreeSet<_> = before_overrides
+            .keys()
+            .chain(after_overrides.keys())
+            .cloned()
+            .collect();
+
+        for (entity, component_type) in keys {
+            let entry = match (
+                before_overrides.get(&(entity, component_type)),
+                after_overrides.get(&(entity, component_type)),
+            ) {
+                (Some(_), None) => PrefabDiffEntry::OverrideRemoved {
+                    prefab_ref,
+                    entity,
+                    component_type,
+                },
+                (None, Some(_)) => PrefabDiffEntry::OverrideAdded {
+                    prefab_ref,
+                    entity,
+                    component_type,
+                },
+                (Some(before_data), Some(after_data)) if before_data != after_data => {
+                    PrefabDiffEntry::OverrideChanged {
+                        prefab_ref,
+                        entity,
+                        component_type,
+                    }
+                }
+                _ => continue,
+            };
+            entries.push(entry);
+        }
+    }
+}
+
+fn override_data(prefab_ref: Option<&PrefabRef>) -> HashMap<(EntityUuid, ComponentTypeUuid), &str> {
+    let mut data = HashMap::new();
+    if let Some(prefab_ref) = prefab_ref {
+        for (entity, overrides) in &prefab_ref.overrides {
+            for component_override in overrides {
+                data.insert(
+                    (*entity, component_override.component_type),
+                    component_override.data.as_str(),
+                );
+            }
+        }
+    }
+    data
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    uuid::Uuid::from_bytes(*uuid).to_string()
+}
@@ -0,0 +1,1133 @@
+use legion::storage::ComponentTypeId;
+use legion::*;
+use legion::world::EntityHasher;
+use legion_prefab::{
+    CloneMergeImpl, ComponentOverride, ComponentRegistration, CopyCloneImpl, DiffSingleResult,
+    FieldChange, FieldPath, Prefab, PrefabMeta, PrefabRef,
+};
+use prefab_format::{ComponentTypeUuid, EntityUuid, PrefabUuid};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::hash::BuildHasher;
+
+/// Identifies one of the two sides being merged
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MergeSide {
+    Ours,
+    Theirs,
+}
+
+/// A change that could not be merged automatically. In every case "ours" is kept in the merged
+/// prefab.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeConflict {
+    /// Both sides changed the same field of a component to different values
+    Field {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+        path: FieldPath,
+        base: Option<Value>,
+        ours: Option<Value>,
+        theirs: Option<Value>,
+    },
+    /// Both sides added the component with different values, or the component could not be merged
+    /// field by field
+    Component {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+    },
+    /// One side removed the component while the other changed it
+    ComponentRemoved {
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+        removed_by: MergeSide,
+    },
+    /// One side removed the entity while the other changed it
+    EntityRemoved {
+        entity: EntityUuid,
+        removed_by: MergeSide,
+    },
+    /// Both sides changed the same field of an override to different values
+    OverrideField {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+        type_name: &'static str,
+        path: FieldPath,
+        base: Option<Value>,
+        ours: Option<Value>,
+        theirs: Option<Value>,
+    },
+    /// Both sides changed the same override and it could not be merged field by field, or one side
+    /// removed the override while the other changed it
+    Override {
+        prefab_ref: PrefabUuid,
+        entity: EntityUuid,
+        component_type: ComponentTypeUuid,
+    },
+    /// One side removed the prefab ref while the other changed its overrides
+    PrefabRefRemoved {
+        prefab_ref: PrefabUuid,
+        removed_by: MergeSide,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        fn value_string(value: &Option<Value>) -> String {
+            match value {
+                Some(value) => value.to_string(),
+                None => "(none)".to_string(),
+            }
+        }
+
+        match self {
+            MergeConflict::Field {
+                entity,
+                type_name,
+                path,
+                base,
+                ours,
+                theirs,
+                ..
+            } => write!(
+                f,
+                "entity {} {}.{}: base {}, ours {}, theirs {}",
+                uuid_string(entity),
+                type_name,
+                path,
+                value_string(base),
+                value_string(ours),
+                value_string(theirs)
+            ),
+            MergeConflict::Component {
+                entity, type_name, ..
+            } => write!(
+                f,
+                "entity {} {}: changed on both sides",
+                uuid_string(entity),
+                type_name
+            ),
+            MergeConflict::ComponentRemoved {
+                entity,
+                type_name,
+                removed_by,
+                ..
+            } => write!(
+                f,
+                "entity {} {}: removed by {} and changed by {}",
+                uuid_string(entity),
+                type_name,
+                side_name(*removed_by),
+                side_name(other_side(*removed_by))
+            ),
+            MergeConflict::EntityRemoved { entity, removed_by } => write!(
+                f,
+                "entity {}: removed by {} and changed by {}",
+                uuid_string(entity),
+                side_name(*removed_by),
+                side_name(other_side(*removed_by))
+            ),
+            MergeConflict::OverrideField {
+                prefab_ref,
+                entity,
+                type_name,
+                path,
+                base,
+                ours,
+                theirs,
+                ..
+            } => write!(
+                f,
+                "override {} entity {} {}.{}: base {}, ours {}, theirs {}",
+                uuid_string(prefab_ref),
+                uuid_string(entity),
+                type_name,
+                path,
+                value_string(base),
+                value_string(ours),
+                value_string(theirs)
+            ),
+            MergeConflict::Override {
+                prefab_ref,
+                entity,
+                component_type,
+            } => write!(
+                f,
+                "override {} entity {} component {}: changed on both sides",
+                uuid_string(prefab_ref),
+                uuid_string(entity),
+                uuid_string(component_type)
+            ),
+            MergeConflict::PrefabRefRemoved {
+                prefab_ref,
+                removed_by,
+            } => write!(
+                f,
+                "prefab ref {}: removed by {} and changed by {}",
+                uuid_string(prefab_ref),
+                side_name(*removed_by),
+                side_name(other_side(*removed_by))
+            ),
+        }
+    }
+}
+
+pub struct PrefabMergeResult {
+    /// The merged prefab. Where there were conflicts, it contains the data from "ours"
+    pub prefab: Prefab,
+
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merges the changes made in two prefabs since a common base prefab. Entities are matched by UUID
+/// and components by type UUID. When both sides changed the same component, the fields changed by
+/// each side are found with serde_diff and combined. Changes to the same field, or a removal on one
+/// side combined with a change on the other, are reported as conflicts and resolved in favor of
+/// "ours". Overrides on prefab refs are merged the same way per (entity, component type), see
+/// merge_override_fields for when they can be merged field by field.
+pub fn merge_prefabs<S: BuildHasher, T: BuildHasher>(
+    base: &Prefab,
+    ours: &Prefab,
+    theirs: &Prefab,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    mut clone_impl: CopyCloneImpl<S>,
+) -> PrefabMergeResult {
+    let mut conflicts = vec![];
+
+    let base_entities = &base.prefab_meta.entities;
+    let ours_entities = &ours.prefab_meta.entities;
+    let theirs_entities = &theirs.prefab_meta.entities;
+
+    // Start from ours and bring in changes from theirs
+    let mut world = World::default();
+    let result_mappings = clone_impl.clone_world(&mut world, &ours.world, &legion::query::any());
+    let mut entities: HashMap<EntityUuid, Entity> = ours_entities
+        .iter()
+        .map(|(uuid, entity)| (*uuid, result_mappings[entity]))
+        .collect();
+
+    // Entities added by theirs are cloned first so that every entity of the merged prefab exists
+    // before entity references are pointed at them
+    let theirs_added: Vec<_> = theirs_entities
+        .iter()
+        .filter(|(uuid, _)| {
+            !base_entities.contains_key(*uuid) && !ours_entities.contains_key(*uuid)
+        })
+        .map(|(uuid, entity)| (*uuid, *entity))
+        .collect();
+    let theirs_added_mappings = clone_impl.clone_entities(
+        &mut world,
+        &theirs.world,
+        theirs_added.iter().map(|(_, entity)| *entity),
+    );
+    for (uuid, entity) in &theirs_added {
+        if let Some(new_entity) = theirs_added_mappings.get(entity) {
+            entities.insert(*uuid, *new_entity);
+        }
+    }
+
+    // References from the added entities to the other entities of theirs
+    let components_by_type_id: HashMap<_, _> = registered_components
+        .values()
+        .map(|registration| (registration.component_type_id(), registration.clone()))
+        .collect();
+    legion_prefab::map_entity_refs_in(
+        &mut world,
+        theirs_added_mappings.values().cloned(),
+        &merged_entity_map(theirs_entities, &entities),
+        &components_by_type_id,
+    );
+
+    // The prefabs are compared through copies whose entity references point at the merged
+    // entities. Otherwise a reference to the same entity would differ between the prefabs, and
+    // components copied from theirs would point at entities in theirs
+    let base_source = MergeSource::new(base, &entities, &mut clone_impl, &components_by_type_id);
+    let ours_source = MergeSource::new(ours, &entities, &mut clone_impl, &components_by_type_id);
+    let theirs_source =
+        MergeSource::new(theirs, &entities, &mut clone_impl, &components_by_type_id);
+
+    // Only the component types on the entities being merged are compared, so look registrations
+    // up by type id
+    let registrations_by_type_id: HashMap<_, _> = registered_components
+        .iter()
+        .map(|(component_type, registration)| {
+            (
+                registration.component_type_id(),
+                (*component_type, registration),
+            )
+        })
+        .collect();
+
+    let entity_uuids: BTreeSet<_> = base_source
+        .entities
+        .keys()
+        .chain(ours_source.entities.keys())
+        .chain(theirs_source.entities.keys())
+        .cloned()
+        .collect();
+
+    for entity_uuid in entity_uuids {
+        let base_entity = base_source.entities.get(&entity_uuid).cloned();
+        let ours_entity = ours_source.entities.get(&entity_uuid).cloned();
+        let theirs_entity = theirs_source.entities.get(&entity_uuid).cloned();
+
+        match (ours_entity, theirs_entity) {
+            (Some(ours_entity), Some(theirs_entity)) => {
+                let result_entity = entities[&entity_uuid];
+                let mut component_types = legion_prefab::diff_component_types(
+                    &ours_source.world,
+                    Some(ours_entity),
+                    &theirs_source.world,
+                    Some(theirs_entity),
+                );
+                component_types.extend(legion_prefab::diff_component_types(
+                    &base_source.world,
+                    base_entity,
+                    &base_source.world,
+                    None,
+                ));
+                for (component_type, registration) in
+                    entity_registrations(&registrations_by_type_id, component_types)
+                {
+                    merge_component(
+                        entity_uuid,
+                        component_type,
+                        registration,
+                        &base_source.world,
+                        base_entity,
+                        &ours_source.world,
+                        ours_entity,
+                        &theirs_source.world,
+                        theirs_entity,
+                        &mut world,
+                        result_entity,
+                        &mut conflicts,
+                    );
+                }
+            }
+            (Some(ours_entity), None) => {
+                // Removed by theirs, unless it was added by ours
+                if let Some(base_entity) = base_entity {
+                    if entity_changed(
+                        &registrations_by_type_id,
+                        &base_source.world,
+                        base_entity,
+                        &ours_source.world,
+                        ours_entity,
+                    ) {
+                        conflicts.push(MergeConflict::EntityRemoved {
+                            entity: entity_uuid,
+                            removed_by: MergeSide::Theirs,
+                        });
+                    } else {
+                        world.remove(entities[&entity_uuid]);
+                        entities.remove(&entity_uuid);
+                    }
+                }
+            }
+            (None, Some(theirs_entity)) => {
+                if let Some(base_entity) = base_entity {
+                    // Removed by ours, which wins if theirs changed it
+                    if entity_changed(
+                        &registrations_by_type_id,
+                        &base_source.world,
+                        base_entity,
+                        &theirs_source.world,
+                        theirs_entity,
+                    ) {
+                        conflicts.push(MergeConflict::EntityRemoved {
+                            entity: entity_uuid,
+                            removed_by: MergeSide::Ours,
+                        });
+                    }
+                }
+
+                // Otherwise added by theirs, and already cloned above
+            }
+            (None, None) => {
+                // Removed on both sides
+            }
+        }
+    }
+
+    let prefab_refs = merge_prefab_refs(
+        &base.prefab_meta.prefab_refs,
+        &ours.prefab_meta.prefab_refs,
+        &theirs.prefab_meta.prefab_refs,
+        registered_components,
+        &mut conflicts,
+    );
+
+    let prefab = Prefab {
+        world,
+        prefab_meta: PrefabMeta {
+            id: ours.prefab_meta.id,
+            prefab_refs,
+            entities,
+        },
+    };
+
+    PrefabMergeResult { prefab, conflicts }
+}
+
+// A copy of one of the prefabs being merged, with the entity references inside its components
+// pointing at the entities of the merged prefab
+struct MergeSource {
+    world: World,
+    entities: HashMap<EntityUuid, Entity>,
+}
+
+impl MergeSource {
+    fn new<S: BuildHasher>(
+        prefab: &Prefab,
+        merged_entities: &HashMap<EntityUuid, Entity>,
+        clone_impl: &mut CopyCloneImpl<S>,
+        components_by_type_id: &HashMap<ComponentTypeId, ComponentRegistration>,
+    ) -> Self {
+        let mut world = World::default();
+        let result_mappings =
+            clone_impl.clone_world(&mut world, &prefab.world, &legion::query::any());
+        let entities: HashMap<EntityUuid, Entity> = prefab
+            .prefab_meta
+            .entities
+            .iter()
+            .map(|(uuid, entity)| (*uuid, result_mappings[entity]))
+            .collect();
+
+        legion_prefab::map_entity_refs_in(
+            &mut world,
+            result_mappings.values().cloned(),
+            &merged_entity_map(&entities, merged_entities),
+            components_by_type_id,
+        );
+
+        MergeSource { world, entities }
+    }
+}
+
+// Maps the entities of a prefab to the entities of the merged prefab with the same UUIDs
+fn merged_entity_map(
+    entities: &HashMap<EntityUuid, Entity>,
+    merged_entities: &HashMap<EntityUuid, Entity>,
+) -> HashMap<Entity, Entity, EntityHasher> {
+    entities
+        .iter()
+        .filter_map(|(uuid, entity)| {
+            merged_entities
+                .get(uuid)
+                .map(|merged_entity| (*entity, *merged_entity))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn merge_component(
+    entity_uuid: EntityUuid,
+    component_type: ComponentTypeUuid,
+    registration: &ComponentRegistration,
+    base_world: &World,
+    base_entity: Option<Entity>,
+    ours_world: &World,
+    ours_entity: Entity,
+    theirs_world: &World,
+    theirs_entity: Entity,
+    result_world: &mut World,
+    result_entity: Entity,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let type_name = registration.type_name();
+    let ours_result = diff_result(
+        registration,
+        base_world,
+        base_entity,
+        ours_world,
+        Some(ours_entity),
+    );
+    let theirs_result = diff_result(
+        registration,
+        base_world,
+        base_entity,
+        theirs_world,
+        Some(theirs_entity),
+    );
+
+    match (ours_result, theirs_result) {
+        (_, DiffSingleResult::NoChange) | (DiffSingleResult::Remove, DiffSingleResult::Remove) => {}
+        (DiffSingleResult::NoChange, DiffSingleResult::Remove) => {
+            registration.remove_from_entity(result_world, result_entity);
+        }
+        (DiffSingleResult::NoChange, _) => {
+            registration.copy_to_entity(theirs_world, theirs_entity, result_world, result_entity);
+        }
+        (DiffSingleResult::Remove, _) => conflicts.push(MergeConflict::ComponentRemoved {
+            entity: entity_uuid,
+            component_type,
+            type_name,
+            removed_by: MergeSide::Ours,
+        }),
+        (_, DiffSingleResult::Remove) => conflicts.push(MergeConflict::ComponentRemoved {
+            entity: entity_uuid,
+            component_type,
+            type_name,
+            removed_by: MergeSide::Theirs,
+        }),
+        (DiffSingleResult::Change, DiffSingleResult::Change) => {
+            // base_entity must exist for both sides to have changed the component
+            let merged = merge_component_fields(
+                entity_uuid,
+                component_type,
+                registration,
+                base_world,
+                base_entity.unwrap(),
+                ours_world,
+                ours_entity,
+                theirs_world,
+                theirs_entity,
+                conflicts,
+            );
+
+            // The merged value may not deserialize, i.e. if both sides changed an enum into
+            // different variants whose fields were combined. Ours is kept unchanged in that case
+            let written = match merged {
+                Ok(Some(value)) => {
+                    let mut de_erased = erased_serde::Deserializer::erase(value);
+                    registration
+                        .try_add_to_entity(&mut de_erased, result_world, result_entity)
+                        .is_ok()
+                }
+                Ok(None) => true,
+                Err(_) => false,
+            };
+
+            if !written {
+                conflicts.push(MergeConflict::Component {
+                    entity: entity_uuid,
+                    component_type,
+                    type_name,
+                });
+            }
+        }
+        _ => {
+            // Added on both sides, which is only a conflict if the values differ
+            let same = diff_result(
+                registration,
+                ours_world,
+                Some(ours_entity),
+                theirs_world,
+                Some(theirs_entity),
+            ) == DiffSingleResult::NoChange;
+
+            if !same {
+                conflicts.push(MergeConflict::Component {
+                    entity: entity_uuid,
+                    component_type,
+                    type_name,
+                });
+            }
+        }
+    }
+}
+
+// Applies the fields theirs changed to ours' component value. Returns None if there was nothing to
+// apply from theirs
+#[allow(clippy::too_many_arguments)]
+fn merge_component_fields(
+    entity_uuid: EntityUuid,
+    component_type: ComponentTypeUuid,
+    registration: &ComponentRegistration,
+    base_world: &World,
+    base_entity: Entity,
+    ours_world: &World,
+    ours_entity: Entity,
+    theirs_world: &World,
+    theirs_entity: Entity,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Result<Option<Value>, serde_json::Error> {
+    let ours_changes = legion_prefab::diff_component_fields(
+        registration,
+        base_world,
+        base_entity,
+        ours_world,
+        ours_entity,
+    )?;
+    let theirs_changes = legion_prefab::diff_component_fields(
+        registration,
+        base_world,
+        base_entity,
+        theirs_world,
+        theirs_entity,
+    )?;
+
+    let ours_value = legion_prefab::component_to_value(registration, ours_world, ours_entity)?;
+    let mut merged = ours_value.clone();
+    let mut applied_any = false;
+    let mut field_conflicts = vec![];
+
+    for theirs_change in &theirs_changes {
+        let overlapping: Vec<&FieldChange> = ours_changes
+            .iter()
+            .filter(|ours_change| {
+                ours_change.path.starts_with(&theirs_change.path)
+                    || theirs_change.path.starts_with(&ours_change.path)
+            })
+            .collect();
+
+        if overlapping.is_empty() {
+            if legion_prefab::apply_field_change(&mut merged, theirs_change) {
+                applied_any = true;
+            } else {
+                field_conflicts.push(theirs_change);
+            }
+        } else if !overlapping
+            .iter()
+            .all(|ours_change| *ours_change == theirs_change)
+        {
+            field_conflicts.push(theirs_change);
+        }
+    }
+
+    for theirs_change in field_conflicts {
+        conflicts.push(MergeConflict::Field {
+            entity: entity_uuid,
+            component_type,
+            type_name: registration.type_name(),
+            path: theirs_change.path.clone(),
+            base: theirs_change.before.clone(),
+            ours: theirs_change.path.find(&ours_value).cloned(),
+            theirs: theirs_change.after.clone(),
+        });
+    }
+
+    Ok(if applied_any { Some(merged) } else { None })
+}
+
+fn diff_result(
+    registration: &ComponentRegistration,
+    src_world: &World,
+    src_entity: Option<Entity>,
+    dst_world: &World,
+    dst_entity: Option<Entity>,
+) -> DiffSingleResult {
+    let mut data = vec![];
+    let mut ser = bincode::Serializer::new(&mut data, bincode::config::DefaultOptions::new());
+    let mut ser_erased = erased_serde::Serializer::erase(&mut ser);
+    registration.diff_single(
+        &mut ser_erased,
+        src_world,
+        src_entity,
+        dst_world,
+        dst_entity,
+    )
+}
+
+type RegistrationsByTypeId<'a> =
+    HashMap<ComponentTypeId, (ComponentTypeUuid, &'a ComponentRegistration)>;
+
+// The registrations of the given component types, sorted by UUID so that conflicts are reported in
+// a stable order. Unregistered and duplicate types are skipped
+fn entity_registrations<'a>(
+    registrations_by_type_id: &RegistrationsByTypeId<'a>,
+    component_types: Vec<ComponentTypeId>,
+) -> Vec<(ComponentTypeUuid, &'a ComponentRegistration)> {
+    let mut registrations: Vec<_> = component_types
+        .iter()
+        .filter_map(|component_type_id| registrations_by_type_id.get(component_type_id).cloned())
+        .collect();
+    registrations.sort_by_key(|(component_type, _)| *component_type);
+    registrations.dedup_by_key(|(component_type, _)| *component_type);
+    registrations
+}
+
+fn entity_changed(
+    registrations_by_type_id: &RegistrationsByTypeId,
+    src_world: &World,
+    src_entity: Entity,
+    dst_world: &World,
+    dst_entity: Entity,
+) -> bool {
+    let component_types = legion_prefab::diff_component_types(
+        src_world,
+        Some(src_entity),
+        dst_world,
+        Some(dst_entity),
+    );
+    entity_registrations(registrations_by_type_id, component_types)
+        .iter()
+        .any(|(_, registration)| {
+            diff_result(
+                registration,
+                src_world,
+                Some(src_entity),
+                dst_world,
+                Some(dst_entity),
+            ) != DiffSingleResult::NoChange
+        })
+}
+
+type OverrideKey = (EntityUuid, ComponentTypeUuid);
+
+fn merge_prefab_refs<T: BuildHasher>(
+    base: &HashMap<PrefabUuid, PrefabRef>,
+    ours: &HashMap<PrefabUuid, PrefabRef>,
+    theirs: &HashMap<PrefabUuid, PrefabRef>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> HashMap<PrefabUuid, PrefabRef> {
+    let mut merged = HashMap::new();
+
+    let prefab_ref_ids: BTreeSet<_> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect();
+
+    for prefab_ref in prefab_ref_ids {
+        let base_overrides = base.get(&prefab_ref).map(override_data);
+        let ours_overrides = ours.get(&prefab_ref).map(override_data);
+        let theirs_overrides = theirs.get(&prefab_ref).map(override_data);
+
+        let overrides = if theirs_overrides == base_overrides {
+            ours_overrides.map(owned_overrides)
+        } else if ours_overrides == base_overrides {
+            theirs_overrides.map(owned_overrides)
+        } else {
+            match (ours_overrides, theirs_overrides) {
+                (Some(ours_overrides), Some(theirs_overrides)) => Some(merge_overrides(
+                    prefab_ref,
+                    base_overrides.unwrap_or_default(),
+                    ours_overrides,
+                    theirs_overrides,
+                    registered_components,
+                    conflicts,
+                )),
+                (None, None) => None,
+                (ours_overrides, _) => {
+                    // One side removed the prefab ref and the other changed it
+                    let removed_by = if ours_overrides.is_none() {
+                        MergeSide::Ours
+                    } else {
+                        MergeSide::Theirs
+                    };
+                    conflicts.push(MergeConflict::PrefabRefRemoved {
+                        prefab_ref,
+                        removed_by,
+                    });
+                    ours_overrides.map(owned_overrides)
+                }
+            }
+        };
+
+        if let Some(overrides) = overrides {
+            merged.insert(prefab_ref, build_prefab_ref(overrides));
+        }
+    }
+
+    merged
+}
+
+fn merge_overrides<T: BuildHasher>(
+    prefab_ref: PrefabUuid,
+    base: BTreeMap<OverrideKey, &str>,
+    ours: BTreeMap<OverrideKey, &str>,
+    theirs: BTreeMap<OverrideKey, &str>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> BTreeMap<OverrideKey, String> {
+    let mut merged = BTreeMap::new();
+    let keys: BTreeSet<_> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .cloned()
+        .collect();
+
+    for key in keys {
+        let base_data = base.get(&key).cloned();
+        let ours_data = ours.get(&key).cloned();
+        let theirs_data = theirs.get(&key).cloned();
+
+        let data = if theirs_data == base_data || theirs_data == ours_data {
+            ours_data.map(str::to_string)
+        } else if ours_data == base_data {
+            theirs_data.map(str::to_string)
+        } else {
+            let registration = registered_components.get(&key.1);
+            let merged_data = match (registration, ours_data, theirs_data) {
+                (Some(registration), Some(ours_data), Some(theirs_data)) => merge_override_fields(
+                    prefab_ref,
+                    key,
+                    registration,
+                    base_data,
+                    ours_data,
+                    theirs_data,
+                    conflicts,
+                ),
+                _ => None,
+            };
+
+            if merged_data.is_none() {
+                conflicts.push(MergeConflict::Override {
+                    prefab_ref,
+                    entity: key.0,
+                    component_type: key.1,
+                });
+            }
+            merged_data.or_else(|| ours_data.map(str::to_string))
+        };
+
+        if let Some(data) = data {
+            merged.insert(key, data);
+        }
+    }
+
+    merged
+}
+
+// Merges two overrides of the same component field by field, the same way components are merged.
+// Overrides are diffs against the component in the referenced prefab, which isn't known here, so
+// they are applied to default instances of the component instead. That shows the fields an
+// override sets unless it sets one to its default value, so each override must be exactly the diff
+// from the default instance to its own. Returns None if that isn't the case for one of them, or the
+// overrides can't be applied, in which case the caller keeps ours and reports a conflict
+fn merge_override_fields(
+    prefab_ref: PrefabUuid,
+    (entity_uuid, component_type): OverrideKey,
+    registration: &ComponentRegistration,
+    base_data: Option<&str>,
+    ours_data: &str,
+    theirs_data: &str,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<String> {
+    let mut world = World::default();
+    let default_entity = override_entity(&mut world, registration, None)?;
+    let base_entity = override_entity(&mut world, registration, base_data)?;
+    let ours_entity = override_entity(&mut world, registration, Some(ours_data))?;
+    let theirs_entity = override_entity(&mut world, registration, Some(theirs_data))?;
+
+    let overrides = [
+        (base_entity, base_data),
+        (ours_entity, Some(ours_data)),
+        (theirs_entity, Some(theirs_data)),
+    ];
+    for (entity, data) in overrides.iter() {
+        if let Some(data) = data {
+            if override_diff(registration, &world, default_entity, *entity)?.as_str() != *data {
+                return None;
+            }
+        }
+    }
+
+    let mut field_conflicts = vec![];
+    let merged = merge_component_fields(
+        entity_uuid,
+        component_type,
+        registration,
+        &world,
+        base_entity,
+        &world,
+        ours_entity,
+        &world,
+        theirs_entity,
+        &mut field_conflicts,
+    )
+    .ok()?;
+
+    let merged_data = match merged {
+        Some(merged) => {
+            let merged_entity = world.push(());
+            let mut de_erased = erased_serde::Deserializer::erase(merged);
+            registration
+                .try_add_to_entity(&mut de_erased, &mut world, merged_entity)
+                .ok()?;
+            override_diff(registration, &world, default_entity, merged_entity)?
+        }
+        None => ours_data.to_string(),
+    };
+
+    for field_conflict in field_conflicts {
+        if let MergeConflict::Field {
+            type_name,
+            path,
+            base,
+            ours,
+            theirs,
+            ..
+        } = field_conflict
+        {
+            conflicts.push(MergeConflict::OverrideField {
+                prefab_ref,
+                entity: entity_uuid,
+                component_type,
+                type_name,
+                path,
+                base,
+                ours,
+                theirs,
+            });
+        }
+    }
+
+    Some(merged_data)
+}
+
+// Adds an entity with a default instance of the component and applies the override to it
+fn override_entity(
+    world: &mut World,
+    registration: &ComponentRegistration,
+    data: Option<&str>,
+) -> Option<Entity> {
+    let entity = world.push(());
+    registration.add_default_to_entity(world, entity);
+    if let Some(data) = data {
+        let mut de = ron::de::Deserializer::from_str(data).ok()?;
+        let mut de_erased = erased_serde::Deserializer::erase(&mut de);
+        registration
+            .try_apply_diff(&mut de_erased, world, entity)
+            .ok()?;
+    }
+    Some(entity)
+}
+
+// Serializes the diff between two components as override data, the way overrides are created
+fn override_diff(
+    registration: &ComponentRegistration,
+    world: &World,
+    src_entity: Entity,
+    dst_entity: Entity,
+) -> Option<String> {
+    let mut data = vec![];
+    let mut ron_ser = ron::ser::Serializer::new(&mut data, None, true).ok()?;
+    let mut ser_erased = erased_serde::Serializer::erase(&mut ron_ser);
+    let result = registration.diff_single(
+        &mut ser_erased,
+        world,
+        Some(src_entity),
+        world,
+        Some(dst_entity),
+    );
+
+    match result {
+        DiffSingleResult::Change => String::from_utf8(data).ok(),
+        _ => None,
+    }
+}
+
+fn override_data(prefab_ref: &PrefabRef) -> BTreeMap<OverrideKey, &str> {
+    let mut data = BTreeMap::new();
+    for (entity, overrides) in &prefab_ref.overrides {
+        for component_override in overrides {
+            data.insert(
+                (*entity, component_override.component_type),
+                component_override.data.as_str(),
+            );
+        }
+    }
+    data
+}
+
+fn owned_overrides(overrides: BTreeMap<OverrideKey, &str>) -> BTreeMap<OverrideKey, String> {
+    overrides
+        .into_iter()
+        .map(|(key, data)| (key, data.to_string()))
+        .collect()
+}
+
+fn build_prefab_ref(overrides: BTreeMap<OverrideKey, String>) -> PrefabRef {
+    let mut prefab_ref = PrefabRef {
+        overrides: HashMap::new(),
+    };
+    for ((entity, component_type), data) in overrides {
+        prefab_ref
+            .overrides
+            .entry(entity)
+            .or_insert_with(Vec::new)
+            .push(ComponentOverride {
+                component_type,
+                data,
+            });
+    }
+    prefab_ref
+}
+
+fn other_side(side: MergeSide) -> MergeSide {
+    match side {
+        MergeSide::Ours => MergeSide::Theirs,
+        MergeSide::Theirs => MergeSide::Ours,
+    }
+}
+
+fn side_name(side: MergeSide) -> &'static str {
+    match side {
+        MergeSide::Ours => "ours",
+        MergeSide::Theirs => "theirs",
+    }
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    uuid::Uuid::from_bytes(*uuid).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legion_prefab::test_util::by_uuid;
+    use serde::{Deserialize, Serialize};
+    use serde_diff::SerdeDiff;
+    use type_uuid::TypeUuid;
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "0e8f6a2c-54b1-4f7d-a39e-c1d27b6f8e04"]
+    struct Transform {
+        x: f32,
+        y: f32,
+    }
+
+    const PREFAB_REF: PrefabUuid = [1; 16];
+    const ENTITY: EntityUuid = [2; 16];
+
+    // Creates the override data setting a default transform to the given one
+    fn transform_override(
+        registration: &ComponentRegistration,
+        transform: Transform,
+    ) -> String {
+        let mut world = World::default();
+        let default_entity = world.push((Transform::default(),));
+        let entity = world.push((transform,));
+        override_diff(registration, &world, default_entity, entity).unwrap()
+    }
+
+    fn prefab_refs(data: Option<String>) -> HashMap<PrefabUuid, PrefabRef> {
+        let mut overrides = BTreeMap::new();
+        if let Some(data) = data {
+            overrides.insert((ENTITY, Transform::UUID), data);
+        }
+
+        let mut prefab_refs = HashMap::new();
+        prefab_refs.insert(PREFAB_REF, build_prefab_ref(overrides));
+        prefab_refs
+    }
+
+    fn merged_override(prefab_refs: &HashMap<PrefabUuid, PrefabRef>) -> Option<&str> {
+        override_data(&prefab_refs[&PREFAB_REF])
+            .get(&(ENTITY, Transform::UUID))
+            .cloned()
+    }
+
+    #[test]
+    fn merges_override_fields_changed_on_both_sides() {
+        let registration = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&registration]);
+
+        let base = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 1.0, y: 1.0 },
+        )));
+        let ours = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 2.0, y: 1.0 },
+        )));
+        let theirs = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 1.0, y: 3.0 },
+        )));
+
+        let mut conflicts = vec![];
+        let merged = merge_prefab_refs(
+            &base,
+            &ours,
+            &theirs,
+            &registered_components,
+            &mut conflicts,
+        );
+
+        assert!(conflicts.is_empty());
+        let expected = transform_override(&registration, Transform { x: 2.0, y: 3.0 });
+        assert_eq!(merged_override(&merged), Some(expected.as_str()));
+    }
+
+    #[test]
+    fn reports_override_field_conflicts_and_keeps_ours() {
+        let registration = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&registration]);
+
+        let base = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 1.0, y: 1.0 },
+        )));
+        let ours_data = transform_override(&registration, Transform { x: 2.0, y: 1.0 });
+        let ours = prefab_refs(Some(ours_data.clone()));
+        let theirs = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 3.0, y: 1.0 },
+        )));
+
+        let mut conflicts = vec![];
+        let merged = merge_prefab_refs(
+            &base,
+            &ours,
+            &theirs,
+            &registered_components,
+            &mut conflicts,
+        );
+
+        assert_eq!(conflicts.len(), 1);
+        match &conflicts[0] {
+            MergeConflict::OverrideField {
+                prefab_ref,
+                entity,
+                path,
+                ..
+            } => {
+                assert_eq!(*prefab_ref, PREFAB_REF);
+                assert_eq!(*entity, ENTITY);
+                assert_eq!(path.to_string(), "x");
+            }
+            conflict => panic!("unexpected conflict {:?}", conflict),
+        }
+        assert_eq!(merged_override(&merged), Some(ours_data.as_str()));
+    }
+
+    #[test]
+    fn reports_override_removed_on_one_side_and_changed_on_the_other() {
+        let registration = ComponentRegistration::of::<Transform>();
+        let registered_components = by_uuid(&[&registration]);
+
+        let base = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 1.0, y: 1.0 },
+        )));
+        let ours = prefab_refs(None);
+        let theirs = prefab_refs(Some(transform_override(
+            &registration,
+            Transform { x: 3.0, y: 1.0 },
+        )));
+
+        let mut conflicts = vec![];
+        let merged = merge_prefab_refs(
+            &base,
+            &ours,
+            &theirs,
+            &registered_components,
+            &mut conflicts,
+        );
+
+        assert_eq!(
+            conflicts,
+            vec![MergeConflict::Override {
+                prefab_ref: PREFAB_REF,
+                entity: ENTITY,
+                component_type: Transform::UUID,
+            }]
+        );
+        assert_eq!(merged_override(&merged), None);
+    }
+}
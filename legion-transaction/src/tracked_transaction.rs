@@ -0,0 +1,529 @@
+use crate::component_diffs::{ComponentDiff, EntityDiff, EntityDiffOp, WorldDiff};
+use crate::{ApplyDiffError, TransactionDiffs};
+use crate::{BincodeCodec, DiffPayloadCodec};
+use legion::storage::{Component, ComponentTypeId, IntoComponentSource};
+use legion::*;
+use legion_prefab::{ComponentRegistration, DiffSingleResult};
+use prefab_format::{ComponentTypeUuid, EntityUuid};
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+
+// Entities in the snapshot world need at least one component to be created
+struct SnapshotMarker;
+
+struct TrackedEntityInfo {
+    // The entity in the world being modified. None once it has been deleted
+    entity: Option<Entity>,
+
+    // False for entities spawned during the transaction. Their components are not snapshotted,
+    // everything on them is new
+    existed_before: bool,
+
+    // Holds the original value of every snapshotted component the entity had. Created the first
+    // time a component is snapshotted
+    snapshot_entity: Option<Entity>,
+
+    // Component types whose original state has been recorded, whether or not the entity had them
+    snapshotted: HashSet<ComponentTypeId>,
+}
+
+/// A transaction that modifies a world in place and only copies a component the first time it is
+/// changed.
+///
+/// `Transaction` clones every selected entity into a before and an after world when it begins.
+/// TrackedTransaction instead records the original value of a component when it is first accessed
+/// mutably, added or removed, which makes starting a transaction on a large selection cheap. All
+/// changes must go through the transaction so that they are tracked. `create_transaction_diffs`
+/// produces the same diffs `Transaction` would.
+///
+/// Since the world is changed directly, dropping the transaction keeps the changes. Use `cancel` to
+/// undo them.
+pub struct TrackedTransaction<'a> {
+    world: &'a mut World,
+
+    // Original components of modified entities
+    snapshot_world: World,
+
+    entities: HashMap<EntityUuid, TrackedEntityInfo>,
+
+    registrations: HashMap<ComponentTypeId, (ComponentTypeUuid, &'a ComponentRegistration)>,
+
+    // Encodes the component payloads of the diffs
+    diff_codec: Box<dyn DiffPayloadCodec>,
+}
+
+impl<'a> TrackedTransaction<'a> {
+    /// Starts tracking changes to the given entities, which are identified by UUID in the diffs.
+    /// Only registered component types are tracked
+    pub fn new<S: BuildHasher>(
+        world: &'a mut World,
+        entities: impl IntoIterator<Item = (EntityUuid, Entity)>,
+        registered_components: &'a HashMap<ComponentTypeUuid, ComponentRegistration, S>,
+    ) -> Self {
+        let entities = entities
+            .into_iter()
+            .map(|(entity_uuid, entity)| {
+                (
+                    entity_uuid,
+                    TrackedEntityInfo {
+                        entity: Some(entity),
+                        existed_before: true,
+                        snapshot_entity: None,
+                        snapshotted: HashSet::new(),
+                    },
+                )
+            })
+            .collect();
+
+        let registrations = registered_components
+            .iter()
+            .map(|(component_type, registration)| {
+                (
+                    registration.component_type_id(),
+                    (*component_type, registration),
+                )
+            })
+            .collect();
+
+        TrackedTransaction {
+            world,
+            snapshot_world: World::default(),
+            entities,
+            registrations,
+            diff_codec: Box::new(BincodeCodec),
+        }
+    }
+
+    /// Sets the codec used to encode the component payloads of the diffs. Defaults to bincode
+    pub fn with_diff_codec(
+        mut self,
+        diff_codec: Box<dyn DiffPayloadCodec>,
+    ) -> Self {
+        self.diff_codec = diff_codec;
+        self
+    }
+
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    pub fn uuid_to_entity(
+        &self,
+        uuid: EntityUuid,
+    ) -> Option<Entity> {
+        self.entities.get(&uuid).and_then(|info| info.entity)
+    }
+
+    /// Returns the component for modification, recording its original value first if this is the
+    /// first time it is changed
+    pub fn component_mut<T: Component>(
+        &mut self,
+        entity_uuid: EntityUuid,
+    ) -> Option<&mut T> {
+        let entity = self.snapshot_component(entity_uuid, ComponentTypeId::of::<T>())?;
+        self.world.entry(entity)?.into_component_mut::<T>().ok()
+    }
+
+    /// Adds the component to the entity, replacing it if the entity already has one. Returns false
+    /// if the entity is not part of the transaction or has been deleted
+    pub fn add_component<T: Component>(
+        &mut self,
+        entity_uuid: EntityUuid,
+        component: T,
+    ) -> bool {
+        let entity = match self.snapshot_component(entity_uuid, ComponentTypeId::of::<T>()) {
+            Some(entity) => entity,
+            None => return false,
+        };
+
+        self.world.entry(entity).unwrap().add_component(component);
+        true
+    }
+
+    /// Removes the component from the entity. Returns false if the entity is not part of the
+    /// transaction or has been deleted
+    pub fn remove_component<T: Component>(
+        &mut self,
+        entity_uuid: EntityUuid,
+    ) -> bool {
+        let entity = match self.snapshot_component(entity_uuid, ComponentTypeId::of::<T>()) {
+            Some(entity) => entity,
+            None => return false,
+        };
+
+        self.world.entry(entity).unwrap().remove_component::<T>();
+        true
+    }
+
+    /// Creates a new entity with the given UUID. Returns None if the UUID is already in use
+    pub fn spawn_entity<T>(
+        &mut self,
+        entity_uuid: EntityUuid,
+        components: T,
+    ) -> Option<Entity>
+    where
+        Option<T>: IntoComponentSource,
+    {
+        if self.entities.contains_key(&entity_uuid) {
+            return None;
+        }
+
+        let entity = self.world.push(components);
+        self.entities.insert(
+            entity_uuid,
+            TrackedEntityInfo {
+                entity: Some(entity),
+                existed_before: false,
+                snapshot_entity: None,
+                snapshotted: HashSet::new(),
+            },
+        );
+        Some(entity)
+    }
+
+    /// Deletes the entity from the world. Returns false if the entity is not part of the
+    /// transaction or has already been deleted
+    pub fn delete_entity(
+        &mut self,
+        entity_uuid: EntityUuid,
+    ) -> bool {
+        let entity = match self.uuid_to_entity(entity_uuid) {
+            Some(entity) => entity,
+            None => return false,
+        };
+
+        // All of the entity's components are needed to restore it when reverting
+        let component_types: Vec<_> = match self.world.entry_ref(entity) {
+            Ok(entry) => entry.archetype().layout().component_types().to_vec(),
+            Err(_) => vec![],
+        };
+        for component_type in component_types {
+            self.snapshot_component(entity_uuid, component_type);
+        }
+
+        self.world.remove(entity);
+
+        let info = self.entities.get_mut(&entity_uuid).unwrap();
+        if info.existed_before {
+            info.entity = None;
+        } else {
+            // An entity that is spawned and deleted in the same transaction doesn't show up in
+            // the diffs at all
+            self.entities.remove(&entity_uuid);
+        }
+
+        true
+    }
+
+    pub fn create_transaction_diffs(&self) -> TransactionDiffs {
+        log::trace!("create diffs for {} entities", self.entities.len());
+
+        let mut apply_entity_diffs = vec![];
+        let mut revert_entity_diffs = vec![];
+        let mut apply_component_diffs = vec![];
+        let mut revert_component_diffs = vec![];
+
+        for (entity_uuid, info) in &self.entities {
+            let component_types = match (info.existed_before, info.entity) {
+                (true, None) => {
+                    apply_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Remove));
+                    revert_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
+                    info.snapshotted.iter().cloned().collect()
+                }
+                (false, Some(entity)) => {
+                    apply_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
+                    revert_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Remove));
+                    legion_prefab::diff_component_types(self.world, None, self.world, Some(entity))
+                }
+                (true, Some(_)) => info.snapshotted.iter().cloned().collect(),
+                (false, None) => continue,
+            };
+
+            // Components that were never snapshotted are unchanged, so the snapshot only needs to
+            // stand in for the before state of the snapshotted types
+            let before_entity = if info.existed_before {
+                info.snapshot_entity
+            } else {
+                None
+            };
+
+            for component_type_id in component_types {
+                let (component_type, registration) =
+                    match self.registrations.get(&component_type_id) {
+                        Some(registration) => *registration,
+                        None => continue,
+                    };
+
+                let (apply_result, apply_data) = crate::component_diffs::diff_component(
+                    registration,
+                    &self.snapshot_world,
+                    before_entity,
+                    self.world,
+                    info.entity,
+                    &*self.diff_codec,
+                );
+
+                if apply_result == DiffSingleResult::NoChange {
+                    continue;
+                }
+
+                let (revert_result, revert_data) = crate::component_diffs::diff_component(
+                    registration,
+                    self.world,
+                    info.entity,
+                    &self.snapshot_world,
+                    before_entity,
+                    &*self.diff_codec,
+                );
+
+                apply_component_diffs.push(
+                    ComponentDiff::new_from_diff_single_result(
+                        *entity_uuid,
+                        component_type,
+                        apply_result,
+                        apply_data,
+                    )
+                    .unwrap(),
+                );
+
+                revert_component_diffs.push(
+                    ComponentDiff::new_from_diff_single_result(
+                        *entity_uuid,
+                        component_type,
+                        revert_result,
+                        revert_data,
+                    )
+                    .unwrap(),
+                );
+            }
+        }
+
+        let encoding = self.diff_codec.encoding();
+        let apply_diff = WorldDiff::new_with_encoding(
+            apply_entity_diffs,
+            apply_component_diffs,
+            encoding.clone(),
+        );
+        let revert_diff =
+            WorldDiff::new_with_encoding(revert_entity_diffs, revert_component_diffs, encoding);
+
+        TransactionDiffs::new(apply_diff, revert_diff)
+    }
+
+    /// Undoes the changes made through the transaction by applying its revert diff to the world.
+    /// Entities deleted during the transaction are recreated with new Entity handles, and only
+    /// their registered components are restored
+    pub fn cancel(self) -> Result<(), ApplyDiffError> {
+        let diffs = self.create_transaction_diffs();
+
+        let mut uuid_to_entity: HashMap<EntityUuid, Entity> = self
+            .entities
+            .iter()
+            .filter_map(|(entity_uuid, info)| info.entity.map(|entity| (*entity_uuid, entity)))
+            .collect();
+        let registered_components: HashMap<ComponentTypeUuid, ComponentRegistration> = self
+            .registrations
+            .values()
+            .map(|(component_type, registration)| (*component_type, (*registration).clone()))
+            .collect();
+
+        crate::apply_diff_in_place_with_codec(
+            self.world,
+            &mut uuid_to_entity,
+            diffs.revert_diff(),
+            &registered_components,
+            &*self.diff_codec,
+        )?;
+        Ok(())
+    }
+
+    // Records the original value of a component of a preexisting entity, unless that was already
+    // done. Returns the entity if it is part of the transaction and hasn't been deleted
+    fn snapshot_component(
+        &mut self,
+        entity_uuid: EntityUuid,
+        component_type: ComponentTypeId,
+    ) -> Option<Entity> {
+        let info = self.entities.get_mut(&entity_uuid)?;
+        let entity = info.entity?;
+
+        if info.existed_before && info.snapshotted.insert(component_type) {
+            if let Some((_, registration)) = self.registrations.get(&component_type) {
+                let snapshot_world = &mut self.snapshot_world;
+                let snapshot_entity = *info
+                    .snapshot_entity
+                    .get_or_insert_with(|| snapshot_world.push((SnapshotMarker,)));
+
+                registration.copy_to_entity(
+                    self.world,
+                    entity,
+                    &mut self.snapshot_world,
+                    snapshot_entity,
+                );
+            }
+        }
+
+        Some(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ComponentDiffOp, TransactionBuilder};
+    use legion_prefab::test_util::{by_type_id, by_uuid};
+    use legion_prefab::CopyCloneImpl;
+    use serde::{Deserialize, Serialize};
+    use serde_diff::SerdeDiff;
+    use type_uuid::TypeUuid;
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "9a4e2f71-0c3b-4d86-b5e9-17f2a8c6d034"]
+    struct Position {
+        x: f32,
+    }
+
+    #[derive(TypeUuid, Serialize, Deserialize, SerdeDiff, Clone, Default, PartialEq, Debug)]
+    #[uuid = "e2b85c19-6f4a-4a07-8d3e-5c91b0f7a642"]
+    struct Velocity {
+        x: f32,
+    }
+
+    const CHANGED: EntityUuid = [1; 16];
+    const STRIPPED: EntityUuid = [2; 16];
+    const DELETED: EntityUuid = [3; 16];
+    const SPAWNED: EntityUuid = [4; 16];
+
+    type SortedDiff = (
+        Vec<(EntityUuid, EntityDiffOp)>,
+        Vec<(EntityUuid, ComponentTypeUuid, ComponentDiffOp)>,
+    );
+
+    // Diffs list entities in hash map order
+    fn sorted(diff: &WorldDiff) -> SortedDiff {
+        let mut entity_diffs: Vec<_> = diff
+            .entity_diffs()
+            .iter()
+            .map(|entity_diff| (*entity_diff.entity_uuid(), entity_diff.op().clone()))
+            .collect();
+        entity_diffs.sort_by_key(|(entity_uuid, _)| *entity_uuid);
+
+        let mut component_diffs: Vec<_> = diff
+            .component_diffs()
+            .iter()
+            .map(|component_diff| {
+                (
+                    *component_diff.entity_uuid(),
+                    *component_diff.component_type(),
+                    component_diff.op().clone(),
+                )
+            })
+            .collect();
+        component_diffs
+            .sort_by_key(|(entity_uuid, component_type, _)| (*entity_uuid, *component_type));
+
+        (entity_diffs, component_diffs)
+    }
+
+    fn create_world() -> (World, Vec<(EntityUuid, Entity)>) {
+        let mut world = World::default();
+        let changed = world.push((Position { x: 1.0 },));
+        let stripped = world.push((Position { x: 2.0 }, Velocity { x: 20.0 }));
+        let deleted = world.push((Position { x: 3.0 }, Velocity { x: 30.0 }));
+        (
+            world,
+            vec![(CHANGED, changed), (STRIPPED, stripped), (DELETED, deleted)],
+        )
+    }
+
+    // Changes a component, adds a component, removes a component, deletes an entity and spawns one
+    fn change_tracked(transaction: &mut TrackedTransaction) {
+        transaction.component_mut::<Position>(CHANGED).unwrap().x = 5.0;
+        assert!(transaction.add_component(CHANGED, Velocity { x: 10.0 }));
+        assert!(transaction.remove_component::<Velocity>(STRIPPED));
+        assert!(transaction.delete_entity(DELETED));
+        assert!(transaction
+            .spawn_entity(SPAWNED, (Position { x: 4.0 },))
+            .is_some());
+    }
+
+    fn component_values<T: Component + Clone>(world: &World) -> Vec<T> {
+        let mut query = Read::<T>::query();
+        query.iter(world).cloned().collect()
+    }
+
+    #[test]
+    fn creates_the_same_diffs_as_transaction() {
+        let registered_components = by_uuid(&[
+            &ComponentRegistration::of::<Position>(),
+            &ComponentRegistration::of::<Velocity>(),
+        ]);
+        let components = by_type_id(&[
+            &ComponentRegistration::of::<Position>(),
+            &ComponentRegistration::of::<Velocity>(),
+        ]);
+
+        let (world, entities) = create_world();
+        let mut builder = TransactionBuilder::new();
+        for (entity_uuid, entity) in &entities {
+            builder = builder.add_entity(*entity, *entity_uuid);
+        }
+        let mut transaction = builder.begin(&world, CopyCloneImpl::new(&components));
+
+        let changed = transaction.uuid_to_entity(CHANGED).unwrap();
+        let stripped = transaction.uuid_to_entity(STRIPPED).unwrap();
+        let deleted = transaction.uuid_to_entity(DELETED).unwrap();
+        let transaction_world = transaction.world_mut();
+        let mut entry = transaction_world.entry(changed).unwrap();
+        entry.get_component_mut::<Position>().unwrap().x = 5.0;
+        entry.add_component(Velocity { x: 10.0 });
+        transaction_world
+            .entry(stripped)
+            .unwrap()
+            .remove_component::<Velocity>();
+        transaction_world.remove(deleted);
+        let spawned = transaction_world.push((Position { x: 4.0 },));
+        transaction.register_new_entity(spawned, SPAWNED).unwrap();
+        let expected = transaction.create_transaction_diffs(&registered_components);
+
+        let (mut world, entities) = create_world();
+        let mut tracked = TrackedTransaction::new(&mut world, entities, &registered_components);
+        change_tracked(&mut tracked);
+        let diffs = tracked.create_transaction_diffs();
+
+        assert_eq!(sorted(diffs.apply_diff()), sorted(expected.apply_diff()));
+        assert_eq!(sorted(diffs.revert_diff()), sorted(expected.revert_diff()));
+    }
+
+    #[test]
+    fn cancel_restores_the_world() {
+        let registered_components = by_uuid(&[
+            &ComponentRegistration::of::<Position>(),
+            &ComponentRegistration::of::<Velocity>(),
+        ]);
+
+        let (mut world, entities) = create_world();
+        let changed = entities[0].1;
+        let mut tracked = TrackedTransaction::new(&mut world, entities, &registered_components);
+        change_tracked(&mut tracked);
+        tracked.cancel().unwrap();
+
+        let mut positions = component_values::<Position>(&world);
+        positions.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        assert_eq!(
+            positions,
+            vec![
+                Position { x: 1.0 },
+                Position { x: 2.0 },
+                Position { x: 3.0 }
+            ]
+        );
+
+        let mut velocities = component_values::<Velocity>(&world);
+        velocities.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        assert_eq!(velocities, vec![Velocity { x: 20.0 }, Velocity { x: 30.0 }]);
+
+        let entry = world.entry_ref(changed).unwrap();
+        assert!(entry.get_component::<Velocity>().is_err());
+    }
+}
@@ -4,9 +4,12 @@ use prefab_format::{EntityUuid, ComponentTypeUuid};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use legion_prefab::{ComponentRegistration, DiffSingleResult};
-use crate::component_diffs::{ComponentDiff, EntityDiff, EntityDiffOp, WorldDiff};
-use legion_prefab::CopyCloneImpl;
+use crate::component_diffs::{CoalesceError, ComponentDiff, EntityDiff, EntityDiffOp, WorldDiff};
+use legion_prefab::{CloneMergeImpl, CopyCloneImpl};
+use crate::TrackedTransaction;
+use crate::{BincodeCodec, DiffPayloadCodec};
 use std::hash::BuildHasher;
+use serde::{Deserialize, Serialize};
 
 struct TransactionBuilderEntityInfo {
     entity_uuid: EntityUuid,
@@ -16,6 +19,7 @@ struct TransactionBuilderEntityInfo {
 #[derive(Default)]
 pub struct TransactionBuilder {
     entities: Vec<TransactionBuilderEntityInfo>,
+    diff_codec: Option<Box<dyn DiffPayloadCodec>>,
 }
 
 impl TransactionBuilder {
@@ -35,6 +39,15 @@ impl TransactionBuilder {
         self
     }
 
+    /// Sets the codec used to encode the component payloads of the diffs. Defaults to bincode
+    pub fn diff_codec(
+        mut self,
+        diff_codec: Box<dyn DiffPayloadCodec>,
+    ) -> Self {
+        self.diff_codec = Some(diff_codec);
+        self
+    }
+
     pub fn begin<S: BuildHasher>(
         self,
         src_world: &World,
@@ -45,11 +58,17 @@ impl TransactionBuilder {
 
         let mut uuid_to_entities = HashMap::new();
 
+        let selection = self.entities.iter().map(|entity_info| entity_info.entity);
+        // References between the selected entities are pointed at the entities in the
+        // transaction's worlds
+        let before_result_mappings =
+            clone_impl.clone_entities(&mut before_world, src_world, selection.clone());
+        let after_result_mappings =
+            clone_impl.clone_entities(&mut after_world, src_world, selection);
+
         for entity_info in self.entities {
-            let before_entity =
-                before_world.clone_from_single(&src_world, entity_info.entity, &mut clone_impl);
-            let after_entity =
-                after_world.clone_from_single(&src_world, entity_info.entity, &mut clone_impl);
+            let before_entity = before_result_mappings[&entity_info.entity];
+            let after_entity = after_result_mappings[&entity_info.entity];
             uuid_to_entities.insert(
                 entity_info.entity_uuid,
                 TransactionEntityInfo {
@@ -63,6 +82,25 @@ impl TransactionBuilder {
             before_world,
             after_world,
             uuid_to_entities,
+            diff_codec: self.diff_codec.unwrap_or_else(|| Box::new(BincodeCodec)),
+        }
+    }
+
+    /// Starts a transaction that modifies the world in place and only snapshots components as they
+    /// are changed. See `TrackedTransaction`
+    pub fn begin_tracked<'a, S: BuildHasher>(
+        self,
+        world: &'a mut World,
+        registered_components: &'a HashMap<ComponentTypeUuid, ComponentRegistration, S>,
+    ) -> TrackedTransaction<'a> {
+        let entities = self
+            .entities
+            .into_iter()
+            .map(|entity_info| (entity_info.entity_uuid, entity_info.entity));
+        let transaction = TrackedTransaction::new(world, entities, registered_components);
+        match self.diff_codec {
+            Some(diff_codec) => transaction.with_diff_codec(diff_codec),
+            None => transaction,
         }
     }
 }
@@ -93,6 +131,16 @@ impl TransactionEntityInfo {
     }
 }
 
+#[derive(Debug)]
+pub enum RegisterNewEntityError {
+    /// The UUID already belongs to an entity in the transaction
+    UuidAlreadyRegistered(EntityUuid),
+    /// The entity was already registered with a different UUID
+    EntityAlreadyRegistered(Entity),
+    /// The entity does not exist in the transaction's world
+    EntityNotFound(Entity),
+}
+
 pub struct Transaction {
     // This is the snapshot of the world when the transaction starts
     before_world: legion::world::World,
@@ -103,9 +151,12 @@ pub struct Transaction {
 
     // All known entities throughout the transaction
     uuid_to_entities: HashMap<EntityUuid, TransactionEntityInfo>,
+
+    // Encodes the component payloads of the diffs
+    diff_codec: Box<dyn DiffPayloadCodec>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TransactionDiffs {
     apply_diff: WorldDiff,
     revert_diff: WorldDiff,
@@ -133,6 +184,29 @@ impl TransactionDiffs {
     pub fn reverse(&mut self) {
         std::mem::swap(&mut self.apply_diff, &mut self.revert_diff);
     }
+
+    /// Combines this transaction with one that was applied after it. Applying the result has the
+    /// same effect as applying both, and reverting it restores the state from before this one. See
+    /// `WorldDiff::coalesce`. All four diffs must use the same encoding
+    pub fn coalesce(
+        self,
+        next: TransactionDiffs,
+    ) -> Result<TransactionDiffs, CoalesceError> {
+        // Checked up front so that neither diff is coalesced if the other can't be
+        for diff in &[&self.revert_diff, &next.apply_diff, &next.revert_diff] {
+            if diff.encoding() != self.apply_diff.encoding() {
+                return Err(CoalesceError::EncodingMismatch {
+                    first: self.apply_diff.encoding().clone(),
+                    next: diff.encoding().clone(),
+                });
+            }
+        }
+
+        Ok(TransactionDiffs {
+            apply_diff: self.apply_diff.coalesce(next.apply_diff)?,
+            revert_diff: next.revert_diff.coalesce(self.revert_diff)?,
+        })
+    }
 }
 
 impl Transaction {
@@ -151,10 +225,49 @@ impl Transaction {
         self.uuid_to_entities[&uuid].after_entity()
     }
 
+    /// Assigns a UUID to an entity that was created in the transaction's world. Entities that are
+    /// not registered are given a random UUID when the diffs are created
+    pub fn register_new_entity(
+        &mut self,
+        entity: Entity,
+        entity_uuid: EntityUuid,
+    ) -> Result<(), RegisterNewEntityError> {
+        if self.uuid_to_entities.contains_key(&entity_uuid) {
+            return Err(RegisterNewEntityError::UuidAlreadyRegistered(entity_uuid));
+        }
+
+        if self
+            .uuid_to_entities
+            .values()
+            .any(|entity_info| entity_info.after_entity == Some(entity))
+        {
+            return Err(RegisterNewEntityError::EntityAlreadyRegistered(entity));
+        }
+
+        if !self.after_world.contains(entity) {
+            return Err(RegisterNewEntityError::EntityNotFound(entity));
+        }
+
+        self.uuid_to_entities
+            .insert(entity_uuid, TransactionEntityInfo::new(None, Some(entity)));
+        Ok(())
+    }
+
     pub fn create_transaction_diffs<S: BuildHasher>(
         &mut self,
         registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, S>,
     ) -> TransactionDiffs {
+        self.create_transaction_diffs_with_assigned_uuids(registered_components)
+            .0
+    }
+
+    /// Same as `create_transaction_diffs`, but also returns the UUIDs that were assigned to new
+    /// entities that had not been registered with `register_new_entity`, keyed by their entity in
+    /// the transaction's world
+    pub fn create_transaction_diffs_with_assigned_uuids<S: BuildHasher>(
+        &mut self,
+        registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, S>,
+    ) -> (TransactionDiffs, HashMap<Entity, EntityUuid>) {
         log::trace!("create diffs for {} entities", self.uuid_to_entities.len());
 
         // These will contain the instructions to add/remove entities
@@ -168,14 +281,24 @@ impl Transaction {
             if let Some(after_entity) = entity_info.after_entity {
                 if !self.after_world.contains(after_entity) {
                     removed_entity_uuids.insert(*entity_uuid);
-                    revert_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
-                    apply_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Remove));
+
+                    // A registered new entity that was deleted again never existed as far as the
+                    // diffs are concerned
+                    if entity_info.before_entity.is_some() {
+                        revert_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
+                        apply_entity_diffs
+                            .push(EntityDiff::new(*entity_uuid, EntityDiffOp::Remove));
+                    }
+                } else if entity_info.before_entity.is_none() {
+                    apply_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Add));
+                    revert_entity_diffs.push(EntityDiff::new(*entity_uuid, EntityDiffOp::Remove));
                 }
 
                 preexisting_after_entities.insert(after_entity);
             }
         }
 
+        let mut assigned_uuids = HashMap::new();
         let mut all = Entity::query();
         for after_entity in all.iter(&self.after_world) {
             if !preexisting_after_entities.contains(&after_entity) {
@@ -197,6 +320,7 @@ impl Transaction {
                     *new_entity_uuid.as_bytes(),
                     TransactionEntityInfo::new(None, Some(*after_entity)),
                 );
+                assigned_uuids.insert(*after_entity, *new_entity_uuid.as_bytes());
             }
         }
 
@@ -213,48 +337,70 @@ impl Transaction {
         let mut apply_component_diffs = vec![];
         let mut revert_component_diffs = vec![];
 
+        // Only the component types on an entity are diffed, so look registrations up by type id
+        let registrations_by_type_id: HashMap<_, _> = registered_components
+            .iter()
+            .map(|(component_type, registration)| {
+                (
+                    registration.component_type_id(),
+                    (*component_type, registration),
+                )
+            })
+            .collect();
+
+        // Reused for every component so that unchanged components don't allocate
+        let mut apply_data = vec![];
+        let mut revert_data = vec![];
+
         // Iterate the entities in the selection world and prefab world and genereate diffs for
-        // each component type.
+        // each component type on them
         for (entity_uuid, entity_info) in &self.uuid_to_entities {
-            // Do diffs for each component type
-            for (component_type, registration) in registered_components {
-                let mut apply_data = vec![];
-                let mut apply_ser = bincode::Serializer::new(
-                    &mut apply_data,
-                    bincode::config::DefaultOptions::new(),
-                );
-                let mut apply_ser_erased = erased_serde::Serializer::erase(&mut apply_ser);
-
-                let apply_result = registration.diff_single(
-                    &mut apply_ser_erased,
-                    &self.before_world,
-                    entity_info.before_entity,
-                    &self.after_world,
-                    entity_info.after_entity,
-                );
-
-                if apply_result != DiffSingleResult::NoChange {
-                    let mut revert_data = vec![];
-                    let mut revert_ser = bincode::Serializer::new(
-                        &mut revert_data,
-                        bincode::config::DefaultOptions::new(),
-                    );
-                    let mut revert_ser_erased = erased_serde::Serializer::erase(&mut revert_ser);
+            let component_type_ids = legion_prefab::diff_component_types(
+                &self.before_world,
+                entity_info.before_entity,
+                &self.after_world,
+                entity_info.after_entity,
+            );
 
-                    let revert_result = registration.diff_single(
-                        &mut revert_ser_erased,
-                        &self.after_world,
-                        entity_info.after_entity,
+            // Do diffs for each component type
+            for component_type_id in component_type_ids {
+                let (component_type, registration) =
+                    match registrations_by_type_id.get(&component_type_id) {
+                        Some(registration) => *registration,
+                        None => continue,
+                    };
+
+                apply_data.clear();
+                let mut apply_result = DiffSingleResult::NoChange;
+                self.diff_codec.serialize(&mut apply_data, &mut |ser| {
+                    apply_result = registration.diff_single(
+                        ser,
                         &self.before_world,
                         entity_info.before_entity,
+                        &self.after_world,
+                        entity_info.after_entity,
                     );
+                });
+
+                if apply_result != DiffSingleResult::NoChange {
+                    revert_data.clear();
+                    let mut revert_result = DiffSingleResult::NoChange;
+                    self.diff_codec.serialize(&mut revert_data, &mut |ser| {
+                        revert_result = registration.diff_single(
+                            ser,
+                            &self.after_world,
+                            entity_info.after_entity,
+                            &self.before_world,
+                            entity_info.before_entity,
+                        );
+                    });
 
                     apply_component_diffs.push(
                         ComponentDiff::new_from_diff_single_result(
                             *entity_uuid,
-                            *component_type,
+                            component_type,
                             apply_result,
-                            apply_data,
+                            apply_data.clone(),
                         )
                         .unwrap(),
                     );
@@ -262,9 +408,9 @@ impl Transaction {
                     revert_component_diffs.push(
                         ComponentDiff::new_from_diff_single_result(
                             *entity_uuid,
-                            *component_type,
+                            component_type,
                             revert_result,
-                            revert_data,
+                            revert_data.clone(),
                         )
                         .unwrap(),
                     );
@@ -278,9 +424,18 @@ impl Transaction {
             self.uuid_to_entities.remove(removed_entity_uuid);
         }
 
-        let apply_diff = WorldDiff::new(apply_entity_diffs, apply_component_diffs);
-        let revert_diff = WorldDiff::new(revert_entity_diffs, revert_component_diffs);
-
-        TransactionDiffs::new(apply_diff, revert_diff)
+        let encoding = self.diff_codec.encoding();
+        let apply_diff = WorldDiff::new_with_encoding(
+            apply_entity_diffs,
+            apply_component_diffs,
+            encoding.clone(),
+        );
+        let revert_diff =
+            WorldDiff::new_with_encoding(revert_entity_diffs, revert_component_diffs, encoding);
+
+        (
+            TransactionDiffs::new(apply_diff, revert_diff),
+            assigned_uuids,
+        )
     }
 }
@@ -0,0 +1,618 @@
+use crate::{
+    ApplyDiffError, ComponentDiffOp, DiffEncoding, DiffPayloadCodec, TransactionDiffs, WorldDiff,
+};
+use legion::*;
+use legion_prefab::ComponentRegistration;
+use prefab_format::{ComponentTypeUuid, EntityUuid};
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+
+// One undo step. Usually a single transaction, but grouped transactions are undone and redone
+// together
+struct UndoStep {
+    diffs: Vec<TransactionDiffs>,
+    memory: usize,
+}
+
+/// A linear undo/redo stack of committed transactions.
+///
+/// Transactions are pushed after they have been applied. Undo applies their revert diffs and redo
+/// applies them again, in place, with `apply_diff_in_place_with_codec`. Diffs with a built-in
+/// encoding are decoded with its codec; transactions built with a custom codec need that codec to be
+/// added with `add_diff_codec`. Pushing a transaction after undoing discards the steps that could
+/// have been redone.
+pub struct UndoHistory {
+    // Steps before `position` can be undone, steps from `position` on can be redone
+    steps: VecDeque<UndoStep>,
+    position: usize,
+
+    max_steps: Option<usize>,
+    max_memory: Option<usize>,
+
+    // The position at which the world matched what was last saved. None if that state can no longer
+    // be reached through undo/redo
+    saved_position: Option<usize>,
+
+    // Transactions pushed while a group is open. They become a single step when the outermost group
+    // ends
+    group: Vec<TransactionDiffs>,
+    group_depth: usize,
+
+    // Codecs for diffs with a custom encoding
+    diff_codecs: Vec<Box<dyn DiffPayloadCodec>>,
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UndoHistory {
+    pub fn new() -> Self {
+        UndoHistory {
+            steps: VecDeque::new(),
+            position: 0,
+            max_steps: None,
+            max_memory: None,
+            saved_position: Some(0),
+            group: vec![],
+            group_depth: 0,
+            diff_codecs: vec![],
+        }
+    }
+
+    /// Adds a codec used to undo and redo transactions whose diffs were encoded with it. Replaces a
+    /// codec added before for the same encoding
+    pub fn add_diff_codec(
+        &mut self,
+        diff_codec: Box<dyn DiffPayloadCodec>,
+    ) {
+        let encoding = diff_codec.encoding();
+        self.diff_codecs
+            .retain(|existing| existing.encoding() != encoding);
+        self.diff_codecs.push(diff_codec);
+    }
+
+    /// Limits the number of steps that can be undone. The oldest steps are discarded first
+    pub fn set_max_steps(
+        &mut self,
+        max_steps: Option<usize>,
+    ) {
+        self.max_steps = max_steps;
+        self.enforce_limits();
+    }
+
+    /// Limits the approximate memory used by the stored diffs, in bytes. The oldest steps are
+    /// discarded first, but the most recent step is always kept
+    pub fn set_max_memory(
+        &mut self,
+        max_memory: Option<usize>,
+    ) {
+        self.max_memory = max_memory;
+        self.enforce_limits();
+    }
+
+    /// Records a transaction that has already been applied to the world
+    pub fn push(
+        &mut self,
+        diffs: TransactionDiffs,
+    ) {
+        if self.group_depth > 0 {
+            self.group.push(diffs);
+        } else {
+            self.push_step(vec![diffs]);
+        }
+    }
+
+    /// Starts collecting pushed transactions into a single undo step. Groups may be nested; the step
+    /// is created when the outermost group ends
+    pub fn begin_group(&mut self) {
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            return;
+        }
+
+        self.group_depth -= 1;
+        if self.group_depth == 0 && !self.group.is_empty() {
+            let diffs = std::mem::replace(&mut self.group, vec![]);
+            self.push_step(diffs);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0 || !self.group.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.steps.len()
+    }
+
+    pub fn undo_count(&self) -> usize {
+        self.position
+    }
+
+    pub fn redo_count(&self) -> usize {
+        self.steps.len() - self.position
+    }
+
+    /// Approximate memory used by the stored diffs, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.steps.iter().map(|step| step.memory).sum()
+    }
+
+    /// Reverts the most recent step. Any open group is ended first. Returns false if there was
+    /// nothing to undo. If the step can't be reverted, the world and the history are left as they
+    /// were and the error is returned
+    pub fn undo<S: BuildHasher, T: BuildHasher>(
+        &mut self,
+        world: &mut World,
+        uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+        registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    ) -> Result<bool, ApplyDiffError> {
+        self.close_groups();
+        if self.position == 0 {
+            return Ok(false);
+        }
+
+        apply_all(
+            world,
+            uuid_to_entity,
+            &self.diff_codecs,
+            self.steps[self.position - 1]
+                .diffs
+                .iter()
+                .rev()
+                .map(TransactionDiffs::revert_diff),
+            registered_components,
+        )?;
+        self.position -= 1;
+
+        Ok(true)
+    }
+
+    /// Applies the most recently undone step again. Returns false if there was nothing to redo.
+    /// If the step can't be applied, the world and the history are left as they were and the error
+    /// is returned
+    pub fn redo<S: BuildHasher, T: BuildHasher>(
+        &mut self,
+        world: &mut World,
+        uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+        registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+    ) -> Result<bool, ApplyDiffError> {
+        self.close_groups();
+        if self.position == self.steps.len() {
+            return Ok(false);
+        }
+
+        apply_all(
+            world,
+            uuid_to_entity,
+            &self.diff_codecs,
+            self.steps[self.position]
+                .diffs
+                .iter()
+                .map(TransactionDiffs::apply_diff),
+            registered_components,
+        )?;
+        self.position += 1;
+
+        Ok(true)
+    }
+
+    /// Records that the world in its current state has been saved
+    pub fn mark_saved(&mut self) {
+        self.close_groups();
+        self.saved_position = Some(self.position);
+    }
+
+    /// True if the world has changed since `mark_saved` was last called (or since the history was
+    /// created)
+    pub fn is_dirty(&self) -> bool {
+        !self.group.is_empty() || self.saved_position != Some(self.position)
+    }
+
+    /// Discards all steps. The current state of the world is considered saved if it was before
+    pub fn clear(&mut self) {
+        let dirty = self.is_dirty();
+        self.steps.clear();
+        self.position = 0;
+        self.group.clear();
+        self.group_depth = 0;
+        self.saved_position = if dirty { None } else { Some(0) };
+    }
+
+    fn close_groups(&mut self) {
+        if self.group_depth > 0 {
+            self.group_depth = 1;
+            self.end_group();
+        }
+    }
+
+    fn push_step(
+        &mut self,
+        diffs: Vec<TransactionDiffs>,
+    ) {
+        // Anything that could have been redone is no longer reachable
+        self.steps.truncate(self.position);
+        if let Some(saved_position) = self.saved_position {
+            if saved_position > self.position {
+                self.saved_position = None;
+            }
+        }
+
+        let memory = std::mem::size_of::<UndoStep>()
+            + diffs.capacity() * std::mem::size_of::<TransactionDiffs>()
+            + diffs
+                .iter()
+                .map(|diffs| {
+                    world_diff_memory(diffs.apply_diff()) + world_diff_memory(diffs.revert_diff())
+                })
+                .sum::<usize>();
+        self.steps.push_back(UndoStep { diffs, memory });
+        self.position += 1;
+
+        self.enforce_limits();
+    }
+
+    fn enforce_limits(&mut self) {
+        loop {
+            let over_steps = self
+                .max_steps
+                .map(|max_steps| self.steps.len() > max_steps)
+                .unwrap_or(false);
+            let over_memory = self
+                .max_memory
+                .map(|max_memory| self.steps.len() > 1 && self.memory_usage() > max_memory)
+                .unwrap_or(false);
+
+            if !over_steps && !over_memory {
+                break;
+            }
+
+            if self.position > 0 {
+                // Drop the oldest step that can be undone
+                self.steps.pop_front();
+                self.position -= 1;
+                self.saved_position = match self.saved_position {
+                    Some(0) | None => None,
+                    Some(saved_position) => Some(saved_position - 1),
+                };
+            } else {
+                // Everything has been undone, so drop the step furthest away from being redone
+                self.steps.pop_back();
+                if self.saved_position > Some(self.steps.len()) {
+                    self.saved_position = None;
+                }
+            }
+        }
+    }
+}
+
+// Applies the diffs of a step in order. If one fails, the ones already applied are reverted so that
+// the step is applied either completely or not at all
+fn apply_all<'a, S: BuildHasher, T: BuildHasher>(
+    world: &mut World,
+    uuid_to_entity: &mut HashMap<EntityUuid, Entity, S>,
+    diff_codecs: &[Box<dyn DiffPayloadCodec>],
+    diffs: impl Iterator<Item = &'a WorldDiff>,
+    registered_components: &HashMap<ComponentTypeUuid, ComponentRegistration, T>,
+) -> Result<(), ApplyDiffError> {
+    // Each inverse is encoded with the codec of the diff it was produced from
+    let mut inverses = vec![];
+    for diff in diffs {
+        let result = codec_for_encoding(diff_codecs, diff.encoding()).and_then(|codec| {
+            crate::apply_diff_in_place_with_codec(
+                world,
+                uuid_to_entity,
+                diff,
+                registered_components,
+                codec,
+            )
+            .map(|inverse| (inverse, codec))
+        });
+
+        match result {
+            Ok(inverse) => inverses.push(inverse),
+            Err(error) => {
+                for (inverse, codec) in inverses.iter().rev() {
+                    let _ = crate::apply_diff_in_place_with_codec(
+                        world,
+                        uuid_to_entity,
+                        inverse,
+                        registered_components,
+                        *codec,
+                    );
+                }
+                return Err(error);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn codec_for_encoding<'a>(
+    diff_codecs: &'a [Box<dyn DiffPayloadCodec>],
+    encoding: &DiffEncoding,
+) -> Result<&'a dyn DiffPayloadCodec, ApplyDiffError> {
+    if let Some(codec) = crate::builtin_diff_codec(encoding) {
+        return Ok(codec);
+    }
+
+    diff_codecs
+        .iter()
+        .find(|codec| codec.encoding() == *encoding)
+        .map(|codec| &**codec)
+        .ok_or_else(|| ApplyDiffError::UnknownEncoding(encoding.clone()))
+}
+
+// The heap memory held by a diff, not including the WorldDiff itself, which is stored inline in
+// TransactionDiffs. Capacities are counted rather than lengths since that is what is allocated
+fn world_diff_memory(diff: &WorldDiff) -> usize {
+    let payload_memory: usize = diff
+        .component_diffs()
+        .iter()
+        .map(|component_diff| match component_diff.op() {
+            ComponentDiffOp::Change(data) | ComponentDiffOp::Add(data) => data.capacity(),
+            ComponentDiffOp::Remove => 0,
+        })
+        .sum();
+
+    let encoding_memory = match diff.encoding() {
+        DiffEncoding::Custom(name) => name.capacity(),
+        _ => 0,
+    };
+
+    diff.entity_diffs().capacity() * std::mem::size_of::<crate::EntityDiff>()
+        + diff.component_diffs().capacity() * std::mem::size_of::<crate::ComponentDiff>()
+        + payload_memory
+        + encoding_memory
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BincodeCodec, EntityDiff, EntityDiffOp};
+
+    // Bincode under another name, standing in for a codec provided by the application
+    struct RenamedCodec;
+
+    impl DiffPayloadCodec for RenamedCodec {
+        fn encoding(&self) -> DiffEncoding {
+            DiffEncoding::Custom("renamed".to_string())
+        }
+
+        fn serialize(
+            &self,
+            data: &mut Vec<u8>,
+            serialize_fn: &mut dyn FnMut(&mut dyn erased_serde::Serializer),
+        ) {
+            BincodeCodec.serialize(data, serialize_fn)
+        }
+
+        fn deserialize(
+            &self,
+            data: &[u8],
+            deserialize_fn: &mut dyn FnMut(
+                &mut dyn erased_serde::Deserializer,
+            ) -> Result<(), erased_serde::Error>,
+        ) -> Result<(), erased_serde::Error> {
+            BincodeCodec.deserialize(data, deserialize_fn)
+        }
+    }
+
+    // Transactions without any changes, which are enough to exercise the bookkeeping of the stack
+    fn empty_diffs() -> TransactionDiffs {
+        TransactionDiffs::new(
+            WorldDiff::new(vec![], vec![]),
+            WorldDiff::new(vec![], vec![]),
+        )
+    }
+
+    // Adds entities without any components, so that only the entity diffs take up memory
+    fn entity_diffs(count: u8) -> TransactionDiffs {
+        let apply = (0..count)
+            .map(|i| EntityDiff::new([i; 16], EntityDiffOp::Add))
+            .collect();
+        let revert = (0..count)
+            .map(|i| EntityDiff::new([i; 16], EntityDiffOp::Remove))
+            .collect();
+        TransactionDiffs::new(
+            WorldDiff::new(apply, vec![]),
+            WorldDiff::new(revert, vec![]),
+        )
+    }
+
+    struct Editor {
+        history: UndoHistory,
+        world: World,
+        uuid_to_entity: HashMap<EntityUuid, Entity>,
+        registered_components: HashMap<ComponentTypeUuid, ComponentRegistration>,
+    }
+
+    impl Editor {
+        fn new() -> Self {
+            Editor {
+                history: UndoHistory::new(),
+                world: World::default(),
+                uuid_to_entity: HashMap::new(),
+                registered_components: HashMap::new(),
+            }
+        }
+
+        fn undo(&mut self) -> bool {
+            self.history
+                .undo(
+                    &mut self.world,
+                    &mut self.uuid_to_entity,
+                    &self.registered_components,
+                )
+                .unwrap()
+        }
+
+        fn redo(&mut self) -> bool {
+            self.history
+                .redo(
+                    &mut self.world,
+                    &mut self.uuid_to_entity,
+                    &self.registered_components,
+                )
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn undo_redo_returns_to_saved_point() {
+        let mut editor = Editor::new();
+        assert!(!editor.history.is_dirty());
+
+        editor.history.push(empty_diffs());
+        editor.history.push(empty_diffs());
+        editor.history.mark_saved();
+        assert!(!editor.history.is_dirty());
+
+        assert!(editor.undo());
+        assert!(editor.history.is_dirty());
+        assert!(editor.undo());
+        assert!(!editor.undo());
+        assert_eq!(editor.history.redo_count(), 2);
+
+        assert!(editor.redo());
+        assert!(editor.history.is_dirty());
+        assert!(editor.redo());
+        assert!(!editor.history.is_dirty());
+        assert!(!editor.redo());
+    }
+
+    #[test]
+    fn push_after_undo_discards_saved_point_ahead() {
+        let mut editor = Editor::new();
+        editor.history.push(empty_diffs());
+        editor.history.push(empty_diffs());
+        editor.history.mark_saved();
+
+        assert!(editor.undo());
+        editor.history.push(empty_diffs());
+        assert_eq!(editor.history.redo_count(), 0);
+        assert!(editor.history.is_dirty());
+
+        // The saved state was on the discarded branch, so no position matches it anymore
+        assert!(editor.undo());
+        assert!(editor.history.is_dirty());
+        assert!(editor.undo());
+        assert!(editor.history.is_dirty());
+    }
+
+    #[test]
+    fn dropping_oldest_step_keeps_saved_point() {
+        let mut editor = Editor::new();
+        editor.history.set_max_steps(Some(2));
+        editor.history.push(empty_diffs());
+        editor.history.mark_saved();
+        editor.history.push(empty_diffs());
+        editor.history.push(empty_diffs());
+        assert_eq!(editor.history.undo_count(), 2);
+
+        // Undoing both remaining steps returns to the state after the first one, which was saved
+        assert!(editor.undo());
+        assert!(editor.history.is_dirty());
+        assert!(editor.undo());
+        assert!(!editor.history.is_dirty());
+        assert!(!editor.undo());
+    }
+
+    #[test]
+    fn dropping_saved_step_from_front_loses_saved_point() {
+        let mut editor = Editor::new();
+        editor.history.set_max_steps(Some(1));
+        editor.history.push(empty_diffs());
+        editor.history.push(empty_diffs());
+
+        // The initial state was saved and can no longer be reached
+        assert!(editor.undo());
+        assert!(editor.history.is_dirty());
+    }
+
+    #[test]
+    fn dropping_redo_steps_keeps_reachable_saved_point() {
+        let mut editor = Editor::new();
+        editor.history.push(empty_diffs());
+        editor.history.mark_saved();
+        editor.history.push(empty_diffs());
+        assert!(editor.undo());
+        assert!(editor.undo());
+
+        // Everything is undone, so the step furthest from being redone is dropped
+        editor.history.set_max_steps(Some(1));
+        assert_eq!(editor.history.redo_count(), 1);
+        assert!(editor.history.is_dirty());
+        assert!(editor.redo());
+        assert!(!editor.history.is_dirty());
+    }
+
+    #[test]
+    fn dropping_redo_steps_loses_unreachable_saved_point() {
+        let mut editor = Editor::new();
+        editor.history.push(empty_diffs());
+        editor.history.push(empty_diffs());
+        editor.history.mark_saved();
+        assert!(editor.undo());
+        assert!(editor.undo());
+
+        editor.history.set_max_steps(Some(1));
+        assert!(editor.redo());
+        assert!(editor.history.is_dirty());
+    }
+
+    #[test]
+    fn memory_includes_entity_diffs() {
+        let mut history = UndoHistory::new();
+        history.push(entity_diffs(100));
+
+        let entity_diff_memory = 200 * std::mem::size_of::<EntityDiff>();
+        assert!(history.memory_usage() >= entity_diff_memory);
+
+        // Only the most recent step fits, so older ones are dropped
+        history.set_max_memory(Some(history.memory_usage()));
+        history.push(entity_diffs(100));
+        assert_eq!(history.undo_count(), 1);
+    }
+
+    #[test]
+    fn custom_encoding_uses_added_codec() {
+        let encoding = RenamedCodec.encoding();
+        let diffs = TransactionDiffs::new(
+            WorldDiff::new_with_encoding(
+                vec![EntityDiff::new([1; 16], EntityDiffOp::Add)],
+                vec![],
+                encoding.clone(),
+            ),
+            WorldDiff::new_with_encoding(
+                vec![EntityDiff::new([1; 16], EntityDiffOp::Remove)],
+                vec![],
+                encoding.clone(),
+            ),
+        );
+
+        let mut editor = Editor::new();
+        editor.history.push(diffs);
+        match editor.history.undo(
+            &mut editor.world,
+            &mut editor.uuid_to_entity,
+            &editor.registered_components,
+        ) {
+            Err(ApplyDiffError::UnknownEncoding(unknown)) => assert_eq!(unknown, encoding),
+            _ => panic!("a step with a custom encoding was undone without its codec"),
+        }
+        assert_eq!(editor.history.undo_count(), 1);
+
+        editor.history.add_diff_codec(Box::new(RenamedCodec));
+        assert!(!editor.redo());
+        assert!(editor.undo());
+        assert!(editor.redo());
+        assert!(editor.uuid_to_entity.contains_key(&[1; 16]));
+    }
+}
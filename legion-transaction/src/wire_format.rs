@@ -0,0 +1,132 @@
+use crate::{TransactionDiffs, WorldDiff};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Identifies data written by `world_diff_to_bytes` and `transaction_diffs_to_bytes`
+pub const DIFF_FORMAT_MAGIC: [u8; 4] = *b"LTDF";
+
+/// Incremented whenever the layout of the serialized diff types changes. Data written with a
+/// different version is rejected rather than misinterpreted.
+pub const DIFF_FORMAT_VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 8;
+
+#[derive(Debug)]
+pub enum DiffFormatError {
+    /// The data does not start with DIFF_FORMAT_MAGIC
+    InvalidHeader,
+    /// The data was written with a different DIFF_FORMAT_VERSION
+    UnsupportedVersion(u32),
+    Bincode(bincode::Error),
+}
+
+impl From<bincode::Error> for DiffFormatError {
+    fn from(error: bincode::Error) -> Self {
+        DiffFormatError::Bincode(error)
+    }
+}
+
+/// Writes a WorldDiff in the versioned wire format: DIFF_FORMAT_MAGIC, DIFF_FORMAT_VERSION as a
+/// little-endian u32, then the diff encoded with bincode's DefaultOptions. Component payloads are
+/// stored as they were produced (serde_diff data in the diff's `DiffEncoding`), so they can only be
+/// decoded by a reader that has the same component types registered.
+pub fn world_diff_to_bytes(diff: &WorldDiff) -> Result<Vec<u8>, DiffFormatError> {
+    to_bytes(diff)
+}
+
+/// Reads a WorldDiff written by `world_diff_to_bytes`
+pub fn world_diff_from_bytes(data: &[u8]) -> Result<WorldDiff, DiffFormatError> {
+    from_bytes(data)
+}
+
+/// Writes TransactionDiffs in the same format as `world_diff_to_bytes`
+pub fn transaction_diffs_to_bytes(diffs: &TransactionDiffs) -> Result<Vec<u8>, DiffFormatError> {
+    to_bytes(diffs)
+}
+
+/// Reads TransactionDiffs written by `transaction_diffs_to_bytes`
+pub fn transaction_diffs_from_bytes(data: &[u8]) -> Result<TransactionDiffs, DiffFormatError> {
+    from_bytes(data)
+}
+
+fn to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>, DiffFormatError> {
+    let mut data = Vec::with_capacity(HEADER_LEN);
+    data.extend_from_slice(&DIFF_FORMAT_MAGIC);
+    data.extend_from_slice(&DIFF_FORMAT_VERSION.to_le_bytes());
+    bincode::DefaultOptions::new().serialize_into(&mut data, value)?;
+    Ok(data)
+}
+
+fn from_bytes<T: DeserializeOwned>(data: &[u8]) -> Result<T, DiffFormatError> {
+    if data.len() < HEADER_LEN || data[0..4] != DIFF_FORMAT_MAGIC {
+        return Err(DiffFormatError::InvalidHeader);
+    }
+
+    let mut version = [0; 4];
+    version.copy_from_slice(&data[4..HEADER_LEN]);
+    let version = u32::from_le_bytes(version);
+    if version != DIFF_FORMAT_VERSION {
+        return Err(DiffFormatError::UnsupportedVersion(version));
+    }
+
+    Ok(bincode::DefaultOptions::new().deserialize(&data[HEADER_LEN..])?)
+}
+
+// Serializes component payloads as byte strings rather than sequences of integers. This is compact
+// in binary formats and keeps text formats readable (i.e. RON writes them as base64)
+pub(crate) mod payload {
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(
+        data: &[u8],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(data)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(PayloadVisitor)
+    }
+
+    struct PayloadVisitor;
+
+    impl<'de> Visitor<'de> for PayloadVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(
+            &self,
+            formatter: &mut fmt::Formatter,
+        ) -> fmt::Result {
+            formatter.write_str("component diff payload bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(
+            self,
+            v: &[u8],
+        ) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(
+            self,
+            v: Vec<u8>,
+        ) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        // Formats without a bytes type (i.e. JSON) write bytes as a sequence of integers
+        fn visit_seq<A: SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+}
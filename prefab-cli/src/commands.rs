@@ -0,0 +1,386 @@
+use crate::files::{self, CliResult, Encoding};
+use crate::outline::PrefabOutline;
+use crate::registry::ComponentRegistry;
+use legion_prefab::{Prefab, PrefabCookOrderError, PrefabDiagnostic};
+use prefab_format::PrefabUuid;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Checks the first file, using the remaining files to resolve prefab references. Returns false if
+/// any problems were found. Data of component types that aren't registered can't be checked, which
+/// is reported as a warning rather than a problem.
+pub fn validate(
+    files: &[PathBuf],
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let mut prefabs = Vec::with_capacity(files.len());
+    for path in files {
+        let (prefab, opaque) = files::read_prefab_with_opaque(path, from, registry)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        for component_type in opaque.component_types() {
+            eprintln!(
+                "warning: {}: component type {} is not registered, so its data is not checked",
+                path.display(),
+                uuid_string(&component_type)
+            );
+        }
+        prefabs.push(prefab);
+    }
+
+    let prefab_lookup = prefab_lookup(&prefabs);
+    let mut diagnostics = vec![];
+    for diagnostic in legion_prefab::validate_prefab(&prefabs[0], &prefab_lookup, &registry.by_uuid)
+    {
+        match diagnostic {
+            PrefabDiagnostic::UnregisteredOverrideComponent { .. } => {
+                eprintln!("warning: {}, so it is not checked", diagnostic)
+            }
+            _ => diagnostics.push(diagnostic),
+        }
+    }
+
+    for diagnostic in &diagnostics {
+        eprintln!("error: {}", diagnostic);
+    }
+
+    if diagnostics.is_empty() {
+        println!("{}: ok", files[0].display());
+    }
+    Ok(diagnostics.is_empty())
+}
+
+/// Cooks the first file and every prefab it references (found among the remaining files) into a
+/// single CookedPrefab
+pub fn cook(
+    files: &[PathBuf],
+    output: &Path,
+    from: Option<Encoding>,
+    encoding: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    if !check_registered(files, from, registry)? {
+        return Ok(false);
+    }
+
+    let prefabs = read_prefabs(files, from, registry)?;
+    let prefab_lookup = prefab_lookup(&prefabs);
+
+    // cook_prefab assumes its input is well-formed, so refuse to cook anything with errors
+    let diagnostics =
+        legion_prefab::validate_prefab(&prefabs[0], &prefab_lookup, &registry.by_uuid);
+    if !diagnostics.is_empty() {
+        for diagnostic in &diagnostics {
+            eprintln!("error: {}", diagnostic);
+        }
+        return Ok(false);
+    }
+
+    let cook_order = legion_prefab::prefab_cook_order(prefabs[0].prefab_id(), &prefab_lookup)
+        .map_err(|e| match e {
+            PrefabCookOrderError::MissingPrefab(prefab) => {
+                format!("prefab {} was not provided", uuid_string(&prefab))
+            }
+            PrefabCookOrderError::ReferenceCycle(cycle) => format!(
+                "prefab references form a cycle: {}",
+                cycle
+                    .iter()
+                    .map(uuid_string)
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+        })?;
+
+    // Only the prefabs that are actually referenced take part in cooking
+    let cook_lookup: HashMap<_, _> = cook_order
+        .iter()
+        .map(|prefab_id| (*prefab_id, prefab_lookup[prefab_id]))
+        .collect();
+
+    let cooked_prefab = legion_prefab::cook_prefab(
+        &registry.by_type_id,
+        &registry.by_uuid,
+        &cook_order,
+        &cook_lookup,
+    );
+
+    let encoding = encoding.unwrap_or_else(|| Encoding::from_path(output));
+    let data = files::cooked_prefab_to_bytes(&cooked_prefab, encoding)?;
+    std::fs::write(output, data)?;
+    Ok(true)
+}
+
+/// Rewrites a prefab in the given encoding. The encoding defaults to the one implied by the output
+/// file's extension. The data of component types that aren't registered is copied as it is, which
+/// only works between text prefabs.
+pub fn convert(
+    input: &Path,
+    output: &Path,
+    from: Option<Encoding>,
+    encoding: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let (prefab, opaque) = files::read_prefab_with_opaque(input, from, registry)?;
+    let encoding = encoding.unwrap_or_else(|| Encoding::from_path(output));
+    let data = files::prefab_with_opaque_to_bytes(&prefab, &opaque, registry, encoding)?;
+    std::fs::write(output, data)?;
+    Ok(true)
+}
+
+/// Rewrites text prefabs in canonical form. With `check` set, files are left untouched and false is
+/// returned if any of them would change. The data of component types that aren't registered is
+/// kept as it is written.
+pub fn format(
+    files: &[PathBuf],
+    from: Option<Encoding>,
+    check: bool,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let mut all_formatted = true;
+    for path in files {
+        let (original, encoding) = files::read_encoded(path, from)?;
+        if encoding != Encoding::Text {
+            return Err(format!("{}: only text prefabs can be formatted", path.display()).into());
+        }
+
+        let (prefab, opaque) = files::read_prefab_with_opaque(path, from, registry)?;
+        let formatted =
+            files::prefab_with_opaque_to_bytes(&prefab, &opaque, registry, Encoding::Text)?;
+        if formatted == original {
+            continue;
+        }
+
+        if check {
+            println!("{}: not formatted", path.display());
+            all_formatted = false;
+        } else {
+            std::fs::write(path, formatted)?;
+            println!("{}: formatted", path.display());
+        }
+    }
+
+    Ok(all_formatted)
+}
+
+/// Prints the differences between two prefabs by entity and component type rather than by line.
+/// Returns false if the prefabs differ, like diff(1).
+pub fn diff(
+    before: &Path,
+    after: &Path,
+    from: Option<Encoding>,
+    cooked: bool,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let prefab_diff = if cooked {
+        let before = files::read_cooked_prefab(before, from)?;
+        let after = files::read_cooked_prefab(after, from)?;
+        legion_transaction::diff_cooked_prefabs(&before, &after, &registry.by_uuid)
+    } else {
+        let paths = [before.to_path_buf(), after.to_path_buf()];
+        if !check_registered(&paths, from, registry)? {
+            return Err("cannot compare prefabs with unregistered component types".into());
+        }
+
+        let before = files::read_prefab(before, from, registry)?;
+        let after = files::read_prefab(after, from, registry)?;
+        legion_transaction::diff_prefabs(&before, &after, &registry.by_uuid)
+    };
+
+    print!("{}", prefab_diff);
+    Ok(!prefab_diff.has_changes())
+}
+
+/// Three-way merges two prefabs with a common base and writes the result to `output`, or over
+/// `ours` if no output is given. This matches the arguments git passes to a merge driver. Returns
+/// false if there were conflicts, in which case the data from `ours` is kept for them.
+pub fn merge(
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    output: Option<&Path>,
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let paths = [base.to_path_buf(), ours.to_path_buf(), theirs.to_path_buf()];
+    if !check_registered(&paths, from, registry)? {
+        return Err("cannot merge prefabs with unregistered component types".into());
+    }
+
+    let (_, encoding) = files::read_encoded(ours, from)?;
+    let base = files::read_prefab(base, from, registry)?;
+    let ours_prefab = files::read_prefab(ours, from, registry)?;
+    let theirs = files::read_prefab(theirs, from, registry)?;
+
+    let clone_impl = legion_prefab::CopyCloneImpl::new(&registry.by_type_id);
+    let result = legion_transaction::merge_prefabs(
+        &base,
+        &ours_prefab,
+        &theirs,
+        &registry.by_uuid,
+        clone_impl,
+    );
+
+    for conflict in &result.conflicts {
+        eprintln!("conflict: {}", conflict);
+    }
+
+    let data = files::prefab_to_bytes(&result.prefab, registry, encoding)?;
+    std::fs::write(output.unwrap_or(ours), data)?;
+    Ok(result.conflicts.is_empty())
+}
+
+/// Prints the prefab reference graph of the given files, either as an indented tree per file or in
+/// graphviz dot format
+pub fn graph(
+    files: &[PathBuf],
+    from: Option<Encoding>,
+    dot: bool,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let mut outlines = Vec::with_capacity(files.len());
+    for path in files {
+        outlines.push((path.as_path(), files::read_outline(path, from, registry)?));
+    }
+
+    let outline_lookup: HashMap<PrefabUuid, (&Path, &PrefabOutline)> = outlines
+        .iter()
+        .map(|(path, outline)| (outline.id, (*path, outline)))
+        .collect();
+
+    if dot {
+        println!("digraph prefabs {{");
+        for (path, outline) in &outlines {
+            println!(
+                "    \"{}\" [label=\"{}\"];",
+                uuid_string(&outline.id),
+                path.display()
+            );
+        }
+        for (_, outline) in &outlines {
+            for (prefab_ref, _) in &outline.prefab_refs {
+                println!(
+                    "    \"{}\" -> \"{}\";",
+                    uuid_string(&outline.id),
+                    uuid_string(prefab_ref)
+                );
+            }
+        }
+        println!("}}");
+    } else {
+        for (_, outline) in &outlines {
+            let mut stack = vec![];
+            print_graph_node(outline.id, &outline_lookup, &mut stack);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Writes a JSON Schema describing prefab files and the data of every registered component type to
+/// `output`, or to stdout if no output is given. Editors can use it to validate and autocomplete
+/// prefabs.
+pub fn schema(
+    output: Option<&Path>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let schema = legion_prefab::prefab_json_schema(&registry.by_uuid);
+    let text = serde_json::to_string_pretty(&schema)?;
+    match output {
+        Some(output) => std::fs::write(output, text)?,
+        None => println!("{}", text),
+    }
+    Ok(true)
+}
+
+fn print_graph_node(
+    prefab: PrefabUuid,
+    outline_lookup: &HashMap<PrefabUuid, (&Path, &PrefabOutline)>,
+    stack: &mut Vec<PrefabUuid>,
+) {
+    let indent = "    ".repeat(stack.len());
+    let outline = match outline_lookup.get(&prefab) {
+        Some((path, outline)) => {
+            println!("{}{} ({})", indent, uuid_string(&prefab), path.display());
+            outline
+        }
+        None => {
+            println!("{}{} (missing)", indent, uuid_string(&prefab));
+            return;
+        }
+    };
+
+    if stack.contains(&prefab) {
+        println!("{}    (cycle)", indent);
+        return;
+    }
+
+    stack.push(prefab);
+    for (prefab_ref, _) in &outline.prefab_refs {
+        print_graph_node(*prefab_ref, outline_lookup, stack);
+    }
+    stack.pop();
+}
+
+// Reports component types used in the files that this binary doesn't know about. Returns false if
+// there were any. Outlines are read first so that this can be reported clearly rather than as a
+// deserialization error
+fn check_registered(
+    files: &[PathBuf],
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<bool> {
+    let mut all_registered = true;
+    for path in files {
+        // Binary prefabs can only be read with all their component types registered, so there is
+        // nothing to check ahead of time
+        if files::read_encoded(path, from)?.1 == Encoding::Binary {
+            continue;
+        }
+
+        let outline = files::read_outline(path, from, registry)?;
+        for component_type in outline.component_types() {
+            if !registry.is_registered(&component_type) {
+                eprintln!(
+                    "error: {}: component type {} is not registered",
+                    path.display(),
+                    uuid_string(&component_type)
+                );
+                all_registered = false;
+            }
+        }
+    }
+
+    if !all_registered {
+        eprintln!(
+            "note: component types are registered with register_component_type!. Build a binary \
+             that links the crates defining them and calls prefab_cli::run"
+        );
+    }
+
+    Ok(all_registered)
+}
+
+fn read_prefabs(
+    files: &[PathBuf],
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<Vec<Prefab>> {
+    let mut prefabs = Vec::with_capacity(files.len());
+    for path in files {
+        let prefab = files::read_prefab(path, from, registry)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        prefabs.push(prefab);
+    }
+    Ok(prefabs)
+}
+
+fn prefab_lookup(prefabs: &[Prefab]) -> HashMap<PrefabUuid, &Prefab> {
+    prefabs
+        .iter()
+        .map(|prefab| (prefab.prefab_id(), prefab))
+        .collect()
+}
+
+fn uuid_string(uuid: &[u8; 16]) -> String {
+    uuid::Uuid::from_bytes(*uuid).to_string()
+}
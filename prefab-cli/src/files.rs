@@ -0,0 +1,315 @@
+use crate::opaque::{OpaqueComponents, OpaqueSerializer, SkipUnregisteredDeserializer};
+use crate::outline::{PrefabOutline, PrefabOutlineDeserializer};
+use crate::registry::ComponentRegistry;
+use bincode::Options;
+use legion::*;
+use legion_prefab::{
+    CookedPrefab, Prefab, PrefabFormatDeserializer, PrefabFormatSerializer, PrefabSerdeContext,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+/// The two ways a prefab can be stored on disk
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    /// The human-readable prefab format (RON)
+    Text,
+    /// bincode-encoded legion world, as produced by serializing a Prefab or CookedPrefab
+    Binary,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" | "ron" => Some(Encoding::Text),
+            "binary" | "bin" | "bincode" => Some(Encoding::Binary),
+            _ => None,
+        }
+    }
+
+    /// Recognizes text prefabs by their RON syntax: after whitespace and comments they start with
+    /// a `(`, a `#![enable(...)]` attribute or a struct name followed by `(`. Everything else that
+    /// isn't valid UTF-8 is binary. Returns None for UTF-8 data that doesn't look like RON, since
+    /// small bincode files can be valid UTF-8 as well
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if looks_like_ron(text) => Some(Encoding::Text),
+            Ok(_) => None,
+            Err(_) => Some(Encoding::Binary),
+        }
+    }
+
+    /// Files ending in .bin are written as binary, anything else as text
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bin") => Encoding::Binary,
+            _ => Encoding::Text,
+        }
+    }
+}
+
+/// Reads a file along with its encoding. The encoding is detected unless `from` is given
+pub fn read_encoded(
+    path: &Path,
+    from: Option<Encoding>,
+) -> CliResult<(Vec<u8>, Encoding)> {
+    let bytes = std::fs::read(path)?;
+    let encoding = from.or_else(|| Encoding::detect(&bytes)).ok_or_else(|| {
+        format!(
+            "{}: can't tell whether this is a text or binary prefab, pass --from text or --from \
+             binary",
+            path.display()
+        )
+    })?;
+    Ok((bytes, encoding))
+}
+
+/// Same as read_prefab, but the data of unregistered component types in text prefabs is skipped
+/// and returned separately instead of failing. Binary prefabs still need every type registered
+pub fn read_prefab_with_opaque(
+    path: &Path,
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<(Prefab, OpaqueComponents)> {
+    let (bytes, encoding) = read_encoded(path, from)?;
+    match encoding {
+        Encoding::Text => {
+            let text = std::str::from_utf8(&bytes)?;
+            let opaque = OpaqueComponents::read(text, registry)?;
+            let prefab_serde_context = PrefabSerdeContext {
+                registered_components: &registry.by_uuid,
+            };
+            let prefab_deser = SkipUnregisteredDeserializer {
+                storage: PrefabFormatDeserializer::new(prefab_serde_context),
+                registry,
+            };
+            let mut de = ron::de::Deserializer::from_str(text)?;
+            prefab_format::deserialize(&mut de, &prefab_deser)?;
+            Ok((prefab_deser.storage.prefab(), opaque))
+        }
+        Encoding::Binary => Ok((
+            read_prefab(path, Some(Encoding::Binary), registry)?,
+            OpaqueComponents::default(),
+        )),
+    }
+}
+
+pub fn read_prefab(
+    path: &Path,
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<Prefab> {
+    let (bytes, encoding) = read_encoded(path, from)?;
+    match encoding {
+        Encoding::Text => {
+            let text = std::str::from_utf8(&bytes)?;
+            let prefab_serde_context = PrefabSerdeContext {
+                registered_components: &registry.by_uuid,
+            };
+            let prefab_deser = PrefabFormatDeserializer::new(prefab_serde_context);
+            let mut de = ron::de::Deserializer::from_str(text)?;
+            prefab_format::deserialize(&mut de, &prefab_deser)?;
+            Ok(prefab_deser.prefab())
+        }
+        Encoding::Binary => Ok(bincode::DefaultOptions::new().deserialize::<Prefab>(&bytes)?),
+    }
+}
+
+/// Reads the structure of a prefab. Text prefabs can be outlined without any registered component
+/// types. Binary prefabs store a legion world, so they must be fully loaded first.
+pub fn read_outline(
+    path: &Path,
+    from: Option<Encoding>,
+    registry: &ComponentRegistry,
+) -> CliResult<PrefabOutline> {
+    let (bytes, encoding) = read_encoded(path, from)?;
+    match encoding {
+        Encoding::Text => {
+            let text = std::str::from_utf8(&bytes)?;
+            let outline_deser = PrefabOutlineDeserializer::new();
+            let mut de = ron::de::Deserializer::from_str(text)?;
+            prefab_format::deserialize(&mut de, &outline_deser)?;
+            Ok(outline_deser.outline())
+        }
+        Encoding::Binary => {
+            let prefab = read_prefab(path, Some(Encoding::Binary), registry)?;
+            Ok(outline_from_prefab(&prefab, registry))
+        }
+    }
+}
+
+// Skips whitespace and comments, then checks for the start of a RON value that a prefab or cooked
+// prefab can begin with
+fn looks_like_ron(text: &str) -> bool {
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            match rest.find("*/") {
+                Some(end) => rest = &rest[end + 2..],
+                None => return false,
+            }
+        } else {
+            break;
+        }
+    }
+
+    if rest.starts_with('(') || rest.starts_with("#![") {
+        return true;
+    }
+
+    let name_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or_else(|| rest.len());
+    name_len > 0
+        && !rest.as_bytes()[0].is_ascii_digit()
+        && rest[name_len..].trim_start().starts_with('(')
+}
+
+fn outline_from_prefab(
+    prefab: &Prefab,
+    registry: &ComponentRegistry,
+) -> PrefabOutline {
+    let uuid_by_type_id: HashMap<_, _> = registry
+        .by_uuid
+        .iter()
+        .map(|(uuid, reg)| (reg.component_type_id(), *uuid))
+        .collect();
+
+    let mut entities: Vec<_> = prefab
+        .prefab_meta
+        .entities
+        .iter()
+        .map(|(entity_uuid, entity)| {
+            let mut component_types: Vec<_> = prefab
+                .world
+                .entry_ref(*entity)
+                .map(|entry| {
+                    entry
+                        .archetype()
+                        .layout()
+                        .component_types()
+                        .iter()
+                        .filter_map(|type_id| uuid_by_type_id.get(type_id).cloned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            component_types.sort();
+            (*entity_uuid, component_types)
+        })
+        .collect();
+    entities.sort_by_key(|(entity_uuid, _)| *entity_uuid);
+
+    let mut prefab_refs: Vec<_> = prefab
+        .prefab_meta
+        .prefab_refs
+        .iter()
+        .map(|(prefab_ref_id, prefab_ref)| {
+            let mut overrides: Vec<_> = prefab_ref
+                .overrides
+                .iter()
+                .flat_map(|(entity_uuid, component_overrides)| {
+                    component_overrides
+                        .iter()
+                        .map(move |o| (*entity_uuid, o.component_type))
+                })
+                .collect();
+            overrides.sort();
+            (*prefab_ref_id, overrides)
+        })
+        .collect();
+    prefab_refs.sort_by_key(|(prefab_ref_id, _)| *prefab_ref_id);
+
+    PrefabOutline {
+        id: prefab.prefab_id(),
+        entities,
+        prefab_refs,
+    }
+}
+
+pub fn read_cooked_prefab(
+    path: &Path,
+    from: Option<Encoding>,
+) -> CliResult<CookedPrefab> {
+    let (bytes, encoding) = read_encoded(path, from)?;
+    match encoding {
+        Encoding::Text => Ok(ron::de::from_bytes::<CookedPrefab>(&bytes)?),
+        Encoding::Binary => Ok(bincode::DefaultOptions::new().deserialize::<CookedPrefab>(&bytes)?),
+    }
+}
+
+pub fn prefab_to_bytes(
+    prefab: &Prefab,
+    registry: &ComponentRegistry,
+    encoding: Encoding,
+) -> CliResult<Vec<u8>> {
+    prefab_with_opaque_to_bytes(prefab, &OpaqueComponents::default(), registry, encoding)
+}
+
+/// Same as prefab_to_bytes, but also writes the data read by read_prefab_with_opaque. Only text
+/// prefabs can hold opaque data
+pub fn prefab_with_opaque_to_bytes(
+    prefab: &Prefab,
+    opaque: &OpaqueComponents,
+    registry: &ComponentRegistry,
+    encoding: Encoding,
+) -> CliResult<Vec<u8>> {
+    match encoding {
+        Encoding::Text => {
+            let prefab_serde_context = PrefabSerdeContext {
+                registered_components: &registry.by_uuid,
+            };
+            let prefab_ser = OpaqueSerializer {
+                storage: PrefabFormatSerializer::new(prefab_serde_context, prefab),
+                opaque,
+            };
+
+            let mut data = Vec::new();
+            let mut ron_ser = ron::ser::Serializer::new(
+                &mut data,
+                Some(ron::ser::PrettyConfig::default()),
+                true,
+            )?;
+            prefab_format::serialize(&mut ron_ser, &prefab_ser, prefab.prefab_id())?;
+            data.push(b'\n');
+
+            if opaque.is_empty() {
+                Ok(data)
+            } else {
+                Ok(opaque
+                    .fill_placeholders(std::str::from_utf8(&data)?)
+                    .into_bytes())
+            }
+        }
+        Encoding::Binary if !opaque.is_empty() => Err(format!(
+            "component types {} are not registered, so the prefab can only be written as text",
+            opaque
+                .component_types()
+                .iter()
+                .map(|component_type| uuid::Uuid::from_bytes(*component_type).to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+        .into()),
+        Encoding::Binary => Ok(bincode::DefaultOptions::new().serialize(prefab)?),
+    }
+}
+
+pub fn cooked_prefab_to_bytes(
+    cooked_prefab: &CookedPrefab,
+    encoding: Encoding,
+) -> CliResult<Vec<u8>> {
+    match encoding {
+        Encoding::Text => {
+            let mut text =
+                ron::ser::to_string_pretty(cooked_prefab, ron::ser::PrettyConfig::default())?;
+            text.push('\n');
+            Ok(text.into_bytes())
+        }
+        Encoding::Binary => Ok(bincode::DefaultOptions::new().serialize(cooked_prefab)?),
+    }
+}
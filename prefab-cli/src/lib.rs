@@ -0,0 +1,328 @@
+//! Command-line tooling for working with prefab files outside of a running game.
+//!
+//! The `prefab` binary built from this crate only knows about component types registered by the
+//! crates it links. To work with your own components, create a binary that depends on this crate
+//! and on the crates that call `register_component_type!`, and forward to `prefab_cli::run`:
+//!
+//! ```ignore
+//! fn main() {
+//!     std::process::exit(prefab_cli::run(std::env::args().skip(1)));
+//! }
+//! ```
+//!
+//! The `graph` subcommand only looks at the structure of text prefabs, so it works without any
+//! registered component types. `validate`, `convert` and `format` copy the data of unregistered
+//! component types in text prefabs as it is written, so they work on text prefabs too, but can't
+//! check that data or write it as binary. `cook`, `diff` and `merge` need every type registered.
+
+mod commands;
+mod files;
+mod opaque;
+mod outline;
+mod registry;
+
+pub use files::Encoding;
+pub use outline::{PrefabOutline, PrefabOutlineDeserializer};
+pub use registry::ComponentRegistry;
+
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+usage: prefab <command> [options]
+
+commands:
+    validate <prefab> [referenced prefabs...]
+        Check a prefab for problems without cooking it
+    cook <prefab> [referenced prefabs...] -o <output> [--to text|binary]
+        Cook a prefab and the prefabs it references into a cooked prefab
+    convert <input> <output> [--to text|binary]
+        Convert a prefab between the text and binary encodings
+    format [--check] <prefabs...>
+        Rewrite text prefabs in canonical form
+    diff [--cooked] <before> <after>
+        Show added and removed entities and components and changed fields
+    merge <base> <ours> <theirs> [-o <output>]
+        Three-way merge prefabs, writing over <ours> unless an output is given
+    graph [--dot] <prefabs...>
+        Print the prefab reference graph
+    schema [-o <output>]
+        Write a JSON Schema of the prefab format and the registered component types
+
+options:
+    --from text|binary
+        The encoding of the input files, for files that aren't recognized as either
+    --to text|binary
+        The encoding of the output file. Defaults to binary for files ending in .bin and to text
+        otherwise";
+
+/// Exit code for a command that ran but found problems
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid command-line arguments
+pub const EXIT_USAGE: i32 = 2;
+
+/// Runs the tool with the given arguments (not including the program name) and returns the
+/// process exit code
+pub fn run<I: IntoIterator<Item = String>>(args: I) -> i32 {
+    let registry = ComponentRegistry::from_inventory();
+    run_with_registry(args, &registry)
+}
+
+/// Same as `run`, but with an explicitly provided set of component types
+pub fn run_with_registry<I: IntoIterator<Item = String>>(
+    args: I,
+    registry: &ComponentRegistry,
+) -> i32 {
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let result = match args.command.as_str() {
+        "validate" => commands::validate(&args.files, args.from, registry),
+        "cook" => match &args.output {
+            Some(output) => commands::cook(&args.files, output, args.from, args.encoding, registry),
+            None => {
+                eprintln!("error: cook requires an output file (-o)\n\n{}", USAGE);
+                return EXIT_USAGE;
+            }
+        },
+        "convert" if args.files.len() == 2 => commands::convert(
+            &args.files[0],
+            &args.files[1],
+            args.from,
+            args.encoding,
+            registry,
+        ),
+        "convert" => {
+            eprintln!(
+                "error: convert requires an input and an output file\n\n{}",
+                USAGE
+            );
+            return EXIT_USAGE;
+        }
+        "diff" if args.files.len() == 2 => commands::diff(
+            &args.files[0],
+            &args.files[1],
+            args.from,
+            args.cooked,
+            registry,
+        ),
+        "diff" => {
+            eprintln!("error: diff requires two files\n\n{}", USAGE);
+            return EXIT_USAGE;
+        }
+        "merge" if args.files.len() == 3 => commands::merge(
+            &args.files[0],
+            &args.files[1],
+            &args.files[2],
+            args.output.as_deref(),
+            args.from,
+            registry,
+        ),
+        "merge" => {
+            eprintln!(
+                "error: merge requires a base, ours and theirs file\n\n{}",
+                USAGE
+            );
+            return EXIT_USAGE;
+        }
+        "format" => commands::format(&args.files, args.from, args.check, registry),
+        "graph" => commands::graph(&args.files, args.from, args.dot, registry),
+        "schema" => commands::schema(args.output.as_deref(), registry),
+        _ => {
+            eprintln!("error: unknown command {}\n\n{}", args.command, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => EXIT_FAILURE,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+struct Args {
+    command: String,
+    files: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    from: Option<Encoding>,
+    encoding: Option<Encoding>,
+    check: bool,
+    cooked: bool,
+    dot: bool,
+}
+
+impl Args {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let command = args.next().ok_or_else(|| "no command given".to_string())?;
+
+        let mut parsed = Args {
+            command,
+            files: vec![],
+            output: None,
+            from: None,
+            encoding: None,
+            check: false,
+            cooked: false,
+            dot: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => {
+                    let output = args
+                        .next()
+                        .ok_or_else(|| format!("{} requires a file", arg))?;
+                    parsed.output = Some(PathBuf::from(output));
+                }
+                "--from" | "--to" => {
+                    let name = args
+                        .next()
+                        .ok_or_else(|| format!("{} requires an encoding", arg))?;
+                    let encoding = Encoding::parse(&name)
+                        .ok_or_else(|| format!("unknown encoding {}", name))?;
+                    if arg == "--from" {
+                        parsed.from = Some(encoding);
+                    } else {
+                        parsed.encoding = Some(encoding);
+                    }
+                }
+                "--check" => parsed.check = true,
+                "--cooked" => parsed.cooked = true,
+                "--dot" => parsed.dot = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ => parsed.files.push(PathBuf::from(arg)),
+            }
+        }
+
+        // schema describes the registered component types rather than any files
+        if parsed.files.is_empty() && parsed.command != "schema" {
+            return Err(format!("{} requires at least one file", parsed.command));
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // Uses a component type that isn't registered, so its data is passed through as it is
+    const PREFAB: &str = r#"Prefab(
+    id: "14dec17f-ae14-40a3-8e44-e487fc423287",
+    objects: [
+        Entity((
+            id: "62b3dbd1-56a8-469e-a262-41a66321da8b",
+            components: [
+                (
+                    type: "f5780013-bae4-49f0-ac0e-a108ff52fec0",
+                    data: (position: [100.0, 100.0], shape: Circle(radius: 2.0)),
+                ),
+            ],
+        )),
+    ],
+)"#;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn path_arg(path: &Path) -> String {
+        path.to_str().unwrap().to_string()
+    }
+
+    fn empty_registry() -> ComponentRegistry {
+        ComponentRegistry {
+            by_type_id: HashMap::new(),
+            by_uuid: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn parses_files_and_options() {
+        let parsed = Args::parse(args(&[
+            "convert",
+            "in.prefab",
+            "--from",
+            "text",
+            "out.bin",
+            "--to",
+            "binary",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.command, "convert");
+        assert_eq!(
+            parsed.files,
+            vec![PathBuf::from("in.prefab"), PathBuf::from("out.bin")]
+        );
+        assert_eq!(parsed.from, Some(Encoding::Text));
+        assert_eq!(parsed.encoding, Some(Encoding::Binary));
+
+        let parsed = Args::parse(args(&["format", "--check", "a.prefab"])).unwrap();
+        assert!(parsed.check);
+        assert!(!parsed.cooked && !parsed.dot);
+
+        let parsed = Args::parse(args(&["schema", "-o", "schema.json"])).unwrap();
+        assert!(parsed.files.is_empty());
+        assert_eq!(parsed.output, Some(PathBuf::from("schema.json")));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(Args::parse(args(&[])).is_err());
+        assert!(Args::parse(args(&["validate"])).is_err());
+        assert!(Args::parse(args(&["cook", "a.prefab", "-o"])).is_err());
+        assert!(Args::parse(args(&["convert", "a.prefab", "--to", "yaml"])).is_err());
+        assert!(Args::parse(args(&["format", "--bogus", "a.prefab"])).is_err());
+    }
+
+    #[test]
+    fn convert_and_format_pass_unregistered_data_through() {
+        let dir = std::env::temp_dir().join(format!("prefab-cli-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.prefab");
+        let output = dir.join("output.prefab");
+        std::fs::write(&input, PREFAB).unwrap();
+        let registry = empty_registry();
+
+        let exit_code = run_with_registry(
+            args(&["convert", &path_arg(&input), &path_arg(&output)]),
+            &registry,
+        );
+        assert_eq!(exit_code, 0);
+        let converted = std::fs::read_to_string(&output).unwrap();
+        assert!(converted.contains("(position: [100.0, 100.0], shape: Circle(radius: 2.0))"));
+
+        // Converted prefabs are already in canonical form, and formatting the input matches them
+        let exit_code =
+            run_with_registry(args(&["format", "--check", &path_arg(&output)]), &registry);
+        assert_eq!(exit_code, 0);
+        let exit_code = run_with_registry(args(&["format", &path_arg(&input)]), &registry);
+        assert_eq!(exit_code, 0);
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), converted);
+
+        let exit_code = run_with_registry(args(&["validate", &path_arg(&input)]), &registry);
+        assert_eq!(exit_code, 0);
+
+        // The data can't be written as a legion world without the type
+        let binary = dir.join("output.bin");
+        let exit_code = run_with_registry(
+            args(&["convert", &path_arg(&input), &path_arg(&binary)]),
+            &registry,
+        );
+        assert_eq!(exit_code, EXIT_FAILURE);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
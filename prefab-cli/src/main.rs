@@ -0,0 +1,3 @@
+fn main() {
+    std::process::exit(prefab_cli::run(std::env::args().skip(1)));
+}
@@ -0,0 +1,516 @@
+use crate::files::CliResult;
+use crate::registry::ComponentRegistry;
+use prefab_format::{ComponentTypeUuid, EntityUuid, PrefabUuid, StorageDeserializer, StorageSerializer};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer, Serializer};
+use std::collections::HashMap;
+
+/// Component data of types this binary doesn't know about, kept as the RON text it was read from so
+/// that convert and format can write it back unchanged instead of refusing the prefab
+#[derive(Default)]
+pub struct OpaqueComponents {
+    components: HashMap<EntityUuid, Vec<(ComponentTypeUuid, String)>>,
+}
+
+impl OpaqueComponents {
+    /// Collects the data of the components in a text prefab whose types aren't registered
+    pub fn read(
+        text: &str,
+        registry: &ComponentRegistry,
+    ) -> CliResult<Self> {
+        let mut opaque = OpaqueComponents::default();
+        for (entity, component_type, data) in scan_component_data(text)? {
+            if !registry.is_registered(&component_type) {
+                opaque
+                    .components
+                    .entry(entity)
+                    .or_insert_with(Vec::new)
+                    .push((component_type, data.to_string()));
+            }
+        }
+        Ok(opaque)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// The unregistered component types, sorted
+    pub fn component_types(&self) -> Vec<ComponentTypeUuid> {
+        let mut component_types: Vec<_> = self
+            .components
+            .values()
+            .flat_map(|components| components.iter().map(|(component_type, _)| *component_type))
+            .collect();
+        component_types.sort();
+        component_types.dedup();
+        component_types
+    }
+
+    fn entity_components(
+        &self,
+        entity: &EntityUuid,
+    ) -> &[(ComponentTypeUuid, String)] {
+        self.components
+            .get(entity)
+            .map(|components| components.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn contains(
+        &self,
+        entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+    ) -> bool {
+        self.entity_components(entity)
+            .iter()
+            .any(|(opaque_type, _)| opaque_type == component_type)
+    }
+
+    // Opaque data can't be passed through a serde Serializer, so a placeholder string is written in
+    // its place and replaced with the data once the prefab has been serialized
+    fn placeholder(
+        entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+    ) -> String {
+        format!(
+            "prefab-cli-opaque-component:{}:{}",
+            uuid::Uuid::from_bytes(*entity),
+            uuid::Uuid::from_bytes(*component_type)
+        )
+    }
+
+    /// Replaces the placeholders written by OpaqueSerializer with the data they stand for
+    pub fn fill_placeholders(
+        &self,
+        text: &str,
+    ) -> String {
+        let mut text = text.to_string();
+        for (entity, components) in &self.components {
+            for (component_type, data) in components {
+                let placeholder = format!("\"{}\"", Self::placeholder(entity, component_type));
+                text = text.replace(&placeholder, data);
+            }
+        }
+        text
+    }
+}
+
+/// Deserializes a text prefab like the wrapped storage, but skips the data of unregistered
+/// component types. Their data is collected separately with OpaqueComponents::read
+pub struct SkipUnregisteredDeserializer<'a, S: StorageDeserializer> {
+    pub storage: S,
+    pub registry: &'a ComponentRegistry,
+}
+
+impl<'a, S: StorageDeserializer> StorageDeserializer for SkipUnregisteredDeserializer<'a, S> {
+    fn begin_prefab(
+        &self,
+        prefab: &PrefabUuid,
+    ) {
+        self.storage.begin_prefab(prefab);
+    }
+
+    fn begin_entity_object(
+        &self,
+        prefab: &PrefabUuid,
+        entity: &EntityUuid,
+    ) {
+        self.storage.begin_entity_object(prefab, entity);
+    }
+
+    fn end_entity_object(
+        &self,
+        prefab: &PrefabUuid,
+        entity: &EntityUuid,
+    ) {
+        self.storage.end_entity_object(prefab, entity);
+    }
+
+    fn deserialize_component<'de, D: Deserializer<'de>>(
+        &self,
+        prefab: &PrefabUuid,
+        entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        if self.registry.is_registered(component_type) {
+            self.storage
+                .deserialize_component(prefab, entity, component_type, deserializer)
+        } else {
+            IgnoredAny::deserialize(deserializer)?;
+            Ok(())
+        }
+    }
+
+    fn begin_prefab_ref(
+        &self,
+        prefab: &PrefabUuid,
+        target_prefab: &PrefabUuid,
+    ) {
+        self.storage.begin_prefab_ref(prefab, target_prefab);
+    }
+
+    fn end_prefab_ref(
+        &self,
+        prefab: &PrefabUuid,
+        target_prefab: &PrefabUuid,
+    ) {
+        self.storage.end_prefab_ref(prefab, target_prefab);
+    }
+
+    // Overrides are stored as strings, so they never need their component type to be read
+    fn apply_component_diff<'de, D: Deserializer<'de>>(
+        &self,
+        parent_prefab: &PrefabUuid,
+        prefab_ref: &PrefabUuid,
+        entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        self.storage.apply_component_diff(
+            parent_prefab,
+            prefab_ref,
+            entity,
+            component_type,
+            deserializer,
+        )
+    }
+}
+
+/// Serializes a prefab like the wrapped storage, adding the opaque components. Their data is
+/// written as placeholders that OpaqueComponents::fill_placeholders replaces
+pub struct OpaqueSerializer<'a, S: StorageSerializer> {
+    pub storage: S,
+    pub opaque: &'a OpaqueComponents,
+}
+
+impl<'a, S: StorageSerializer> StorageSerializer for OpaqueSerializer<'a, S> {
+    fn entities(&self) -> Vec<EntityUuid> {
+        self.storage.entities()
+    }
+
+    fn component_types(
+        &self,
+        entity: &EntityUuid,
+    ) -> Vec<ComponentTypeUuid> {
+        let mut component_types = self.storage.component_types(entity);
+        component_types.extend(
+            self.opaque
+                .entity_components(entity)
+                .iter()
+                .map(|(component_type, _)| *component_type),
+        );
+        component_types.sort();
+        component_types
+    }
+
+    fn serialize_entity_component<SE: Serializer>(
+        &self,
+        serializer: SE,
+        entity: &EntityUuid,
+        component: &ComponentTypeUuid,
+    ) -> Result<SE::Ok, SE::Error> {
+        if self.opaque.contains(entity, component) {
+            serializer.serialize_str(&OpaqueComponents::placeholder(entity, component))
+        } else {
+            self.storage
+                .serialize_entity_component(serializer, entity, component)
+        }
+    }
+
+    fn prefab_refs(&self) -> Vec<PrefabUuid> {
+        self.storage.prefab_refs()
+    }
+
+    fn prefab_ref_overrides(
+        &self,
+        uuid: &PrefabUuid,
+    ) -> Vec<(EntityUuid, Vec<ComponentTypeUuid>)> {
+        self.storage.prefab_ref_overrides(uuid)
+    }
+
+    fn serialize_component_override_diff<SE: Serializer>(
+        &self,
+        serializer: SE,
+        prefab_ref: &PrefabUuid,
+        entity: &EntityUuid,
+        component: &ComponentTypeUuid,
+    ) -> Result<SE::Ok, SE::Error> {
+        self.storage
+            .serialize_component_override_diff(serializer, prefab_ref, entity, component)
+    }
+}
+
+// Finds the data of every entity component in a text prefab, along with the entity and component
+// type. Only the structure around the data is read here. Values, including the data, are skipped
+// with ron so that the returned text is exactly what was in the file
+fn scan_component_data(text: &str) -> CliResult<Vec<(EntityUuid, ComponentTypeUuid, &str)>> {
+    let mut component_data = vec![];
+    let mut scanner = Scanner { text, pos: 0 };
+    scanner.fields(|scanner, field| {
+        if field != "objects" {
+            return scanner.skip_value();
+        }
+
+        scanner.items(|scanner| {
+            if !scanner.eat_ident("Entity") {
+                return scanner.skip_value();
+            }
+
+            scanner.expect('(')?;
+            let mut entity = None;
+            let mut components = vec![];
+            scanner.fields(|scanner, field| match field {
+                "id" => {
+                    entity = Some(scanner.uuid()?);
+                    Ok(())
+                }
+                "components" => scanner.items(|scanner| {
+                    let mut component_type = None;
+                    let mut data = None;
+                    scanner.fields(|scanner, field| match field {
+                        "type" => {
+                            component_type = Some(scanner.uuid()?);
+                            Ok(())
+                        }
+                        "data" => {
+                            data = Some(scanner.value()?);
+                            Ok(())
+                        }
+                        _ => scanner.skip_value(),
+                    })?;
+                    match (component_type, data) {
+                        (Some(component_type), Some(data)) => {
+                            components.push((component_type, data));
+                            Ok(())
+                        }
+                        _ => Err("entity component without a type or data".into()),
+                    }
+                }),
+                _ => scanner.skip_value(),
+            })?;
+            scanner.expect(')')?;
+
+            let entity = entity.ok_or("entity without an id")?;
+            component_data.extend(
+                components
+                    .into_iter()
+                    .map(|(component_type, data)| (entity, component_type, data)),
+            );
+            Ok(())
+        })
+    })?;
+
+    Ok(component_data)
+}
+
+// Reads the structs and sequences around component data in RON text
+struct Scanner<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    // Skips whitespace and comments
+    fn skip_whitespace(&mut self) -> CliResult<()> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or_else(|| trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                let end = trimmed.find("*/").ok_or("unterminated comment")?;
+                self.pos += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn eat(
+        &mut self,
+        c: char,
+    ) -> CliResult<bool> {
+        self.skip_whitespace()?;
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect(
+        &mut self,
+        c: char,
+    ) -> CliResult<()> {
+        if self.eat(c)? {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn ident(&mut self) -> CliResult<Option<&'a str>> {
+        self.skip_whitespace()?;
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or_else(|| rest.len());
+        if len == 0 || rest.as_bytes()[0].is_ascii_digit() {
+            return Ok(None);
+        }
+
+        self.pos += len;
+        Ok(Some(&rest[..len]))
+    }
+
+    // Consumes the identifier if it's next
+    fn eat_ident(
+        &mut self,
+        expected: &str,
+    ) -> bool {
+        let pos = self.pos;
+        match self.ident() {
+            Ok(Some(ident)) if ident == expected => true,
+            _ => {
+                self.pos = pos;
+                false
+            }
+        }
+    }
+
+    // Reads a struct, with or without its name, and passes each field name to on_field, which
+    // must read the field's value
+    fn fields(
+        &mut self,
+        mut on_field: impl FnMut(&mut Self, &'a str) -> CliResult<()>,
+    ) -> CliResult<()> {
+        self.ident()?;
+        self.expect('(')?;
+        loop {
+            if self.eat(')')? {
+                return Ok(());
+            }
+
+            let field = self
+                .ident()?
+                .ok_or_else(|| self.error("expected a field name"))?;
+            self.expect(':')?;
+            on_field(self, field)?;
+
+            if !self.eat(',')? {
+                return self.expect(')');
+            }
+        }
+    }
+
+    // Reads a sequence and calls on_item for each item, which must read the item
+    fn items(
+        &mut self,
+        mut on_item: impl FnMut(&mut Self) -> CliResult<()>,
+    ) -> CliResult<()> {
+        self.expect('[')?;
+        loop {
+            if self.eat(']')? {
+                return Ok(());
+            }
+
+            on_item(self)?;
+
+            if !self.eat(',')? {
+                return self.expect(']');
+            }
+        }
+    }
+
+    // Reads any value and returns its text
+    fn value(&mut self) -> CliResult<&'a str> {
+        self.skip_whitespace()?;
+        let rest = self.rest();
+        let mut de = ron::de::Deserializer::from_str(rest)?;
+        IgnoredAny::deserialize(&mut de).map_err(|e| self.error(&e.to_string()))?;
+        let len = rest.len() - de.remainder().len();
+        self.pos += len;
+        Ok(rest[..len].trim_end())
+    }
+
+    fn skip_value(&mut self) -> CliResult<()> {
+        self.value().map(|_| ())
+    }
+
+    fn uuid(&mut self) -> CliResult<[u8; 16]> {
+        let value = self.value()?;
+        let uuid = ron::de::from_str::<String>(value)?;
+        Ok(*uuid::Uuid::parse_str(&uuid)?.as_bytes())
+    }
+
+    fn error(
+        &self,
+        message: &str,
+    ) -> Box<dyn std::error::Error> {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("line {}: {}", line, message).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: &str = "62b3dbd1-56a8-469e-a262-41a66321da8b";
+    const COMPONENT_TYPE: &str = "f5780013-bae4-49f0-ac0e-a108ff52fec0";
+
+    fn uuid(text: &str) -> [u8; 16] {
+        *uuid::Uuid::parse_str(text).unwrap().as_bytes()
+    }
+
+    #[test]
+    fn scans_component_data_as_written() {
+        let text = format!(
+            r#"Prefab(
+    // Prefab AssetUuid
+    id: "14dec17f-ae14-40a3-8e44-e487fc423287",
+    objects: [
+        PrefabRef((
+            prefab_id: "5fd8256d-db36-4fe2-8211-c7b3446e1927",
+            entity_overrides: [],
+        )),
+        Entity((
+            components: [
+                (
+                    data: Shape(kind: Circle(radius: 1.5), /* ) */ name: "data: ]"),
+                    type: "{}",
+                ),
+            ],
+            id: "{}",
+        )),
+    ],
+)"#,
+            COMPONENT_TYPE, ENTITY
+        );
+
+        let component_data = scan_component_data(&text).unwrap();
+        assert_eq!(
+            component_data,
+            vec![(
+                uuid(ENTITY),
+                uuid(COMPONENT_TYPE),
+                r#"Shape(kind: Circle(radius: 1.5), /* ) */ name: "data: ]")"#
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_malformed_structure() {
+        let error = scan_component_data("Prefab(\n    objects: [\n        Entity(]").unwrap_err();
+        assert!(error.to_string().starts_with("line 3:"));
+    }
+}
@@ -0,0 +1,128 @@
+use prefab_format::{ComponentTypeUuid, EntityUuid, PrefabUuid, StorageDeserializer};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer};
+use std::cell::RefCell;
+
+/// The structure of a prefab file without any component data. Component data is skipped while
+/// reading, so an outline can be read even when none of the component types are registered.
+#[derive(Default)]
+pub struct PrefabOutline {
+    pub id: PrefabUuid,
+
+    /// Every entity in the prefab and the types of the components it has
+    pub entities: Vec<(EntityUuid, Vec<ComponentTypeUuid>)>,
+
+    /// Every referenced prefab and the (entity, component type) pairs overridden in it
+    pub prefab_refs: Vec<(PrefabUuid, Vec<(EntityUuid, ComponentTypeUuid)>)>,
+}
+
+impl PrefabOutline {
+    /// All component types used by the prefab, either as component data or as override targets
+    pub fn component_types(&self) -> Vec<ComponentTypeUuid> {
+        let mut component_types: Vec<_> = self
+            .entities
+            .iter()
+            .flat_map(|(_, component_types)| component_types.iter().cloned())
+            .chain(self.prefab_refs.iter().flat_map(|(_, overrides)| {
+                overrides.iter().map(|(_, component_type)| *component_type)
+            }))
+            .collect();
+        component_types.sort();
+        component_types.dedup();
+        component_types
+    }
+}
+
+/// Reads the prefab format into a PrefabOutline
+#[derive(Default)]
+pub struct PrefabOutlineDeserializer {
+    outline: RefCell<PrefabOutline>,
+}
+
+impl PrefabOutlineDeserializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn outline(self) -> PrefabOutline {
+        self.outline.into_inner()
+    }
+}
+
+impl StorageDeserializer for PrefabOutlineDeserializer {
+    fn begin_prefab(
+        &self,
+        prefab: &PrefabUuid,
+    ) {
+        self.outline.borrow_mut().id = *prefab;
+    }
+
+    fn begin_entity_object(
+        &self,
+        _prefab: &PrefabUuid,
+        entity: &EntityUuid,
+    ) {
+        self.outline.borrow_mut().entities.push((*entity, vec![]));
+    }
+
+    fn end_entity_object(
+        &self,
+        _prefab: &PrefabUuid,
+        _entity: &EntityUuid,
+    ) {
+    }
+
+    fn deserialize_component<'de, D: Deserializer<'de>>(
+        &self,
+        _prefab: &PrefabUuid,
+        _entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        IgnoredAny::deserialize(deserializer)?;
+        let mut outline = self.outline.borrow_mut();
+        let (_, component_types) = outline
+            .entities
+            .last_mut()
+            // deserializer implementation error, begin_entity_object shall always be called before deserialize_component
+            .expect("component data outside of an entity");
+        component_types.push(*component_type);
+        Ok(())
+    }
+
+    fn begin_prefab_ref(
+        &self,
+        _prefab: &PrefabUuid,
+        target_prefab: &PrefabUuid,
+    ) {
+        self.outline
+            .borrow_mut()
+            .prefab_refs
+            .push((*target_prefab, vec![]));
+    }
+
+    fn end_prefab_ref(
+        &self,
+        _prefab: &PrefabUuid,
+        _target_prefab: &PrefabUuid,
+    ) {
+    }
+
+    fn apply_component_diff<'de, D: Deserializer<'de>>(
+        &self,
+        _parent_prefab: &PrefabUuid,
+        _prefab_ref: &PrefabUuid,
+        entity: &EntityUuid,
+        component_type: &ComponentTypeUuid,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        IgnoredAny::deserialize(deserializer)?;
+        let mut outline = self.outline.borrow_mut();
+        let (_, overrides) = outline
+            .prefab_refs
+            .last_mut()
+            .expect("apply_component_diff called without begin_prefab_ref");
+        overrides.push((*entity, *component_type));
+        Ok(())
+    }
+}
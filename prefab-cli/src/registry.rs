@@ -0,0 +1,36 @@
+use legion::storage::ComponentTypeId;
+use legion_prefab::ComponentRegistration;
+use prefab_format::ComponentTypeUuid;
+use std::collections::HashMap;
+
+/// The component types known to this binary. These are collected from every
+/// `register_component_type!` linked into the executable, so a game that wants the tool to
+/// understand its components builds its own binary that links its component crates and calls
+/// `prefab_cli::run`
+pub struct ComponentRegistry {
+    pub by_type_id: HashMap<ComponentTypeId, ComponentRegistration>,
+    pub by_uuid: HashMap<ComponentTypeUuid, ComponentRegistration>,
+}
+
+impl ComponentRegistry {
+    pub fn from_inventory() -> Self {
+        let by_type_id = legion_prefab::iter_component_registrations()
+            .map(|reg| (reg.component_type_id(), reg.clone()))
+            .collect();
+        let by_uuid = legion_prefab::iter_component_registrations()
+            .map(|reg| (*reg.uuid(), reg.clone()))
+            .collect();
+
+        ComponentRegistry {
+            by_type_id,
+            by_uuid,
+        }
+    }
+
+    pub fn is_registered(
+        &self,
+        component_type: &ComponentTypeUuid,
+    ) -> bool {
+        self.by_uuid.contains_key(component_type)
+    }
+}